@@ -8,7 +8,15 @@ fn main() {
     let mut args: Vec<_> = env::args().collect();
     args.remove(0);
 
-    let verbose = if args.get(0).map(|x| x.as_str()) == Some("-v") {
+    let verbose = if args.first().map(|x| x.as_str()) == Some("-v") {
+        args.remove(0);
+        true
+    } else {
+        false
+    };
+
+    // Like c++filt: demangle the names found anywhere in each line.
+    let filter = if args.first().map(|x| x.as_str()) == Some("--filter") {
         args.remove(0);
         true
     } else {
@@ -16,14 +24,15 @@ fn main() {
     };
 
     let print_demangled = |sym: &str| {
-        let parsed = match msvc_demangler::parse(&sym) {
-            Ok(parsed) => parsed,
-            Err(err) => {
-                eprintln!("error: {}", err);
+        let (parsed, err) = msvc_demangler::parse_partial(sym);
+        if let Some(err) = err {
+            eprintln!("error: {}", err);
+            // Only print what we recovered if we got at least the name.
+            if parsed.symbol.name == msvc_demangler::Name::Unknown {
                 println!("{}", sym);
                 return;
             }
-        };
+        }
         if verbose {
             eprintln!("{:#?}", &parsed);
         }
@@ -38,7 +47,18 @@ fn main() {
         }
     };
 
-    if args.is_empty() {
+    if filter {
+        let flags = msvc_demangler::DemangleFlags::llvm();
+        let stdin = io::stdin();
+        let handle = stdin.lock();
+
+        for line in handle.lines() {
+            match line {
+                Ok(line) => println!("{}", msvc_demangler::demangle_in_text(&line, flags)),
+                _ => continue,
+            }
+        }
+    } else if args.is_empty() {
         let stdin = io::stdin();
         let handle = stdin.lock();
 
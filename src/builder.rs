@@ -0,0 +1,393 @@
+//! Construction of symbols without spelling out the AST.
+//!
+//! `SymbolBuilder` builds a `ParseResult` for a function, method or
+//! variable, and the constructors on `Type` build the types that go into
+//! it.  The result can be serialized or mangled like a parsed name.
+//!
+//! ```
+//! use msvc_demangler::{mangle, SymbolBuilder, Type};
+//!
+//! let symbol = SymbolBuilder::method("bar", &["Foo"])
+//!     .public()
+//!     .virtual_()
+//!     .returns(Type::int())
+//!     .param(Type::ptr(Type::char().const_()))
+//!     .build();
+//! assert_eq!(
+//!     symbol.to_string(),
+//!     "public: virtual int __thiscall Foo::bar(char const *)"
+//! );
+//! assert_eq!(mangle(&symbol).unwrap(), "?bar@Foo@@UAEHPBD@Z");
+//! ```
+//!
+//! Symbols are built for x86 by default.  `SymbolBuilder::ptr64` builds
+//! them for x64, where `this` and all pointers are `__ptr64`:
+//!
+//! ```
+//! use msvc_demangler::{mangle, SymbolBuilder, Type};
+//!
+//! let symbol = SymbolBuilder::method("bar", &["Foo"])
+//!     .ptr64()
+//!     .returns(Type::int())
+//!     .param(Type::ptr(Type::char().const_()))
+//!     .build();
+//! assert_eq!(mangle(&symbol).unwrap(), "?bar@Foo@@QEAAHPEBD@Z");
+//! ```
+
+use std::borrow::Cow;
+
+use super::visit::walk_type_mut;
+use super::{
+    CallingConv, FuncClass, FunctionSignature, Name, NameSequence, Params, ParseResult,
+    StorageClass, Symbol, Type, VarStorageKind, VisitMut,
+};
+
+#[derive(Clone, Debug)]
+enum Entity {
+    Function,
+    Method,
+    Variable(Type<'static>),
+}
+
+/// Builds the `ParseResult` of a function, method or variable.
+///
+/// Scopes are given outermost first, the way they are written in C++, so
+/// `&["ns", "Foo"]` is `ns::Foo`.  Methods default to public, non-virtual
+/// `__thiscall` and functions to `__cdecl`.  Without a return type or
+/// parameters a function returns `void` and takes `(void)`.
+#[derive(Clone, Debug)]
+pub struct SymbolBuilder {
+    entity: Entity,
+    symbol: Symbol<'static>,
+    func_class: FuncClass,
+    calling_conv: CallingConv,
+    this_quals: StorageClass,
+    return_type: Type<'static>,
+    params: Vec<Type<'static>>,
+    noexcept: bool,
+    ptr64: bool,
+}
+
+impl SymbolBuilder {
+    fn new(entity: Entity, name: &str, scope: &[&str], calling_conv: CallingConv) -> Self {
+        SymbolBuilder {
+            entity,
+            symbol: Symbol {
+                name: identifier(name),
+                scope: NameSequence {
+                    names: scope.iter().rev().map(|s| identifier(s)).collect(),
+                },
+            },
+            func_class: FuncClass::empty(),
+            calling_conv,
+            this_quals: StorageClass::empty(),
+            return_type: Type::void(),
+            params: Vec::new(),
+            noexcept: false,
+            ptr64: false,
+        }
+    }
+
+    /// A free function or a function in a namespace.
+    pub fn function(name: &str, scope: &[&str]) -> Self {
+        SymbolBuilder::new(Entity::Function, name, scope, CallingConv::Cdecl)
+    }
+
+    /// A member function of the class that ends `scope`.
+    pub fn method(name: &str, scope: &[&str]) -> Self {
+        let mut builder = SymbolBuilder::new(Entity::Method, name, scope, CallingConv::Thiscall);
+        builder.func_class = FuncClass::PUBLIC;
+        builder
+    }
+
+    /// A global variable, or a static data member when given an access
+    /// specifier.
+    pub fn variable(name: &str, scope: &[&str], t: Type<'static>) -> Self {
+        SymbolBuilder::new(Entity::Variable(t), name, scope, CallingConv::Cdecl)
+    }
+
+    fn access(mut self, access: FuncClass) -> Self {
+        self.func_class -= FuncClass::PUBLIC | FuncClass::PROTECTED | FuncClass::PRIVATE;
+        self.func_class |= access;
+        self
+    }
+
+    pub fn public(self) -> Self {
+        self.access(FuncClass::PUBLIC)
+    }
+
+    pub fn protected(self) -> Self {
+        self.access(FuncClass::PROTECTED)
+    }
+
+    pub fn private(self) -> Self {
+        self.access(FuncClass::PRIVATE)
+    }
+
+    /// Makes a method virtual.
+    pub fn virtual_(mut self) -> Self {
+        self.func_class -= FuncClass::STATIC;
+        self.func_class |= FuncClass::VIRTUAL;
+        self
+    }
+
+    /// Makes a method static.
+    pub fn static_(mut self) -> Self {
+        self.func_class -= FuncClass::VIRTUAL;
+        self.func_class |= FuncClass::STATIC;
+        self
+    }
+
+    /// Makes a method `const`.
+    pub fn const_(mut self) -> Self {
+        self.this_quals |= StorageClass::CONST;
+        self
+    }
+
+    pub fn calling_conv(mut self, calling_conv: CallingConv) -> Self {
+        self.calling_conv = calling_conv;
+        self
+    }
+
+    pub fn cdecl(self) -> Self {
+        self.calling_conv(CallingConv::Cdecl)
+    }
+
+    pub fn stdcall(self) -> Self {
+        self.calling_conv(CallingConv::Stdcall)
+    }
+
+    pub fn fastcall(self) -> Self {
+        self.calling_conv(CallingConv::Fastcall)
+    }
+
+    pub fn thiscall(self) -> Self {
+        self.calling_conv(CallingConv::Thiscall)
+    }
+
+    pub fn noexcept(mut self) -> Self {
+        self.noexcept = true;
+        self
+    }
+
+    pub fn returns(mut self, t: Type<'static>) -> Self {
+        self.return_type = t;
+        self
+    }
+
+    /// Appends a parameter.
+    pub fn param(mut self, t: Type<'static>) -> Self {
+        self.params.push(t);
+        self
+    }
+
+    /// Builds the symbol for x64: `this` and every pointer and reference in
+    /// the symbol's types become `__ptr64`, and methods use `__cdecl`
+    /// instead of `__thiscall`, which x64 does not have.
+    pub fn ptr64(mut self) -> Self {
+        self.ptr64 = true;
+        self
+    }
+
+    /// Builds the symbol.
+    pub fn build(mut self) -> ParseResult<'static> {
+        if self.ptr64 {
+            if let Entity::Method = self.entity {
+                self.this_quals |= StorageClass::PTR64;
+            }
+            if self.calling_conv == CallingConv::Thiscall {
+                self.calling_conv = CallingConv::Cdecl;
+            }
+        }
+        let access =
+            self.func_class & (FuncClass::PUBLIC | FuncClass::PROTECTED | FuncClass::PRIVATE);
+        let symbol_type = match self.entity {
+            Entity::Variable(t) => {
+                let kind = if access.contains(FuncClass::PUBLIC) {
+                    VarStorageKind::PublicStatic
+                } else if access.contains(FuncClass::PROTECTED) {
+                    VarStorageKind::ProtectedStatic
+                } else if access.contains(FuncClass::PRIVATE) {
+                    VarStorageKind::PrivateStatic
+                } else {
+                    VarStorageKind::Global
+                };
+                // x64 pointer and reference variables are `__ptr64`
+                // themselves.
+                let storage_class = match t {
+                    Type::Ptr(..)
+                    | Type::MemberDataPointer(..)
+                    | Type::Ref(..)
+                    | Type::RValueRef(..)
+                        if self.ptr64 =>
+                    {
+                        StorageClass::PTR64
+                    }
+                    _ => StorageClass::empty(),
+                };
+                Type::Var(Box::new(t), kind, storage_class)
+            }
+            entity => {
+                let params = if self.params.is_empty() {
+                    vec![Type::void()]
+                } else {
+                    self.params
+                };
+                let static_ = self.func_class.contains(FuncClass::STATIC);
+                let sig = FunctionSignature {
+                    calling_conv: self.calling_conv,
+                    params: Params { types: params },
+                    this_quals: if static_ {
+                        StorageClass::empty()
+                    } else {
+                        self.this_quals
+                    },
+                    return_type: Box::new(self.return_type),
+                    noexcept: self.noexcept,
+                    this_adjustment: 0,
+                };
+                match entity {
+                    Entity::Method => Type::MemberFunction(self.func_class, sig),
+                    _ => Type::NonMemberFunction(sig),
+                }
+            }
+        };
+        let mut rv = ParseResult {
+            symbol: self.symbol,
+            symbol_type,
+        };
+        if self.ptr64 {
+            Ptr64.visit_parse_result(&mut rv);
+        }
+        rv
+    }
+}
+
+// Makes every pointer and reference `__ptr64`.  Like the parser, this
+// stores the qualifier on the pointee.
+struct Ptr64;
+
+impl<'a> VisitMut<'a> for Ptr64 {
+    fn visit_type(&mut self, t: &mut Type<'a>) {
+        match *t {
+            Type::Ptr(ref mut inner, _)
+            | Type::MemberDataPointer(_, ref mut inner, _)
+            | Type::Ref(ref mut inner, _)
+            | Type::RValueRef(ref mut inner, _) => {
+                if let Some(sc) = inner.storage_class_mut() {
+                    *sc |= StorageClass::PTR64;
+                }
+            }
+            _ => {}
+        }
+        walk_type_mut(self, t);
+    }
+}
+
+fn identifier(s: &str) -> Name<'static> {
+    Name::NonTemplate(Cow::Owned(s.as_bytes().to_vec()))
+}
+
+// Parses `a::b::C` into a symbol named `C` in the scope `a::b`.
+fn qualified(name: &str) -> Symbol<'static> {
+    let mut names: Vec<_> = name.rsplit("::").map(identifier).collect();
+    let name = names.remove(0);
+    Symbol {
+        name,
+        scope: NameSequence { names },
+    }
+}
+
+impl<'a> Type<'a> {
+    pub fn void() -> Type<'a> {
+        Type::Void(StorageClass::empty())
+    }
+
+    pub fn bool() -> Type<'a> {
+        Type::Bool(StorageClass::empty())
+    }
+
+    pub fn char() -> Type<'a> {
+        Type::Char(StorageClass::empty())
+    }
+
+    pub fn wchar() -> Type<'a> {
+        Type::Wchar(StorageClass::empty())
+    }
+
+    pub fn short() -> Type<'a> {
+        Type::Short(StorageClass::empty())
+    }
+
+    pub fn int() -> Type<'a> {
+        Type::Int(StorageClass::empty())
+    }
+
+    pub fn uint() -> Type<'a> {
+        Type::Uint(StorageClass::empty())
+    }
+
+    pub fn long() -> Type<'a> {
+        Type::Long(StorageClass::empty())
+    }
+
+    pub fn int64() -> Type<'a> {
+        Type::Int64(StorageClass::empty())
+    }
+
+    pub fn uint64() -> Type<'a> {
+        Type::Uint64(StorageClass::empty())
+    }
+
+    pub fn float() -> Type<'a> {
+        Type::Float(StorageClass::empty())
+    }
+
+    pub fn double() -> Type<'a> {
+        Type::Double(StorageClass::empty())
+    }
+
+    /// A pointer to `t`.
+    pub fn ptr(t: Type<'a>) -> Type<'a> {
+        Type::Ptr(Box::new(t), StorageClass::empty())
+    }
+
+    /// A `__ptr64` pointer to `t`, as on x64.  Symbols built with
+    /// `SymbolBuilder::ptr64` get these from `Type::ptr` already.
+    pub fn ptr64(mut t: Type<'a>) -> Type<'a> {
+        if let Some(sc) = t.storage_class_mut() {
+            *sc |= StorageClass::PTR64;
+        }
+        Type::Ptr(Box::new(t), StorageClass::empty())
+    }
+
+    /// An lvalue reference to `t`.
+    pub fn reference(t: Type<'a>) -> Type<'a> {
+        Type::Ref(Box::new(t), StorageClass::empty())
+    }
+
+    /// The class with the qualified name `name`, such as `ns::Foo`.
+    pub fn class(name: &str) -> Type<'a> {
+        Type::Class(qualified(name), StorageClass::empty())
+    }
+
+    /// The struct with the qualified name `name`.
+    pub fn struct_(name: &str) -> Type<'a> {
+        Type::Struct(qualified(name), StorageClass::empty())
+    }
+
+    /// The enum with the qualified name `name`.
+    pub fn enum_(name: &str) -> Type<'a> {
+        Type::Enum(qualified(name), StorageClass::empty())
+    }
+
+    /// Adds `const` to the type.  For pointers and references this is the
+    /// qualifier of the pointer itself; qualify the pointee for
+    /// `char const *`.  Types without qualifiers are returned unchanged.
+    pub fn const_(mut self) -> Type<'a> {
+        if let Some(sc) = self.storage_class_mut() {
+            *sc |= StorageClass::CONST;
+        }
+        self
+    }
+}
@@ -0,0 +1,1099 @@
+//! Parsing of demangled declarations.
+//!
+//! `parse_declaration` reads the text that `serialize` produces with
+//! `DemangleFlags::COMPLETE` or `DemangleFlags::llvm()` and rebuilds the
+//! `ParseResult` it was printed from, so that text can be mangled again:
+//!
+//! ```
+//! use msvc_demangler::{mangle, parse_declaration};
+//!
+//! let parsed =
+//!     parse_declaration("public: virtual int __thiscall Foo::bar(char const *)").unwrap();
+//! assert_eq!(mangle(&parsed).unwrap(), "?bar@Foo@@UAEHPBD@Z");
+//! ```
+//!
+//! Functions, methods, variables and the types that appear in them are
+//! supported, and so are special names such as vftables, RTTI data and
+//! thunks to virtual calls, and names nested in functions.  String
+//! literals are not, since the text leaves out their contents.
+//! Details that the text leaves out, such as `__ptr64` pointers or the
+//! adjustment of a `[thunk]:`, come back with their default values.
+
+use std::borrow::Cow;
+
+use super::{
+    CallingConv, Error, ErrorKind, FuncClass, FunctionSignature, Name, NameSequence, Operator,
+    PackMarker, Params, ParseResult, Result, StorageClass, Symbol, Type, VarStorageKind,
+    DEFAULT_MAX_DEPTH,
+};
+
+/// Parses a demangled declaration back into the `ParseResult` it was
+/// printed from.
+pub fn parse_declaration(input: &str) -> Result<ParseResult<'_>> {
+    let mut parser = DeclarationParser {
+        input: input.as_bytes(),
+        offset: 0,
+        calling_conv: None,
+        conversion_type: None,
+        depth: 0,
+    };
+    let result = parser.parse()?;
+    parser.skip_whitespace();
+    if parser.offset < parser.input.len() {
+        return Err(parser.fail(ErrorKind::TrailingData));
+    }
+    Ok(result)
+}
+
+// A piece of a declarator, applied to the type on its left.
+enum Declarator<'a> {
+    Ptr(StorageClass),
+    Ref(StorageClass),
+    RValueRef(StorageClass),
+    MemberPtr(Symbol<'a>, StorageClass),
+    Function(FunctionSignature<'a>),
+    Array(i32),
+}
+
+// The operators that are spelled with punctuation, longest first so that a
+// prefix never shadows a longer operator.
+const OPERATORS: &[(&str, Operator<'static>)] = &[
+    ("new[]", Operator::ArrayNew),
+    ("delete[]", Operator::ArrayDelete),
+    ("new", Operator::New),
+    ("delete", Operator::Delete),
+    ("co_await", Operator::CoroutineAwait),
+    (">>=", Operator::GreaterGreaterEqual),
+    ("<<=", Operator::LessLessEqual),
+    ("->*", Operator::ArrowStar),
+    ("()", Operator::Call),
+    ("[]", Operator::Subscript),
+    ("->", Operator::Arrow),
+    ("++", Operator::PlusPlus),
+    ("--", Operator::MinusMinus),
+    (">>", Operator::RShift),
+    ("<<", Operator::LShift),
+    ("==", Operator::EqualEqual),
+    ("!=", Operator::BangEqual),
+    ("<=", Operator::LessEqual),
+    (">=", Operator::GreaterEqual),
+    ("&&", Operator::AmpAmp),
+    ("||", Operator::PipePipe),
+    ("*=", Operator::StarEqual),
+    ("+=", Operator::PlusEqual),
+    ("-=", Operator::MinusEqual),
+    ("/=", Operator::SlashEqual),
+    ("%=", Operator::PercentEqual),
+    ("&=", Operator::AmpEqual),
+    ("|=", Operator::PipeEqual),
+    ("^=", Operator::CaretEqual),
+    ("=", Operator::Equal),
+    ("!", Operator::Bang),
+    ("*", Operator::Star),
+    ("-", Operator::Minus),
+    ("+", Operator::Plus),
+    ("&", Operator::Amp),
+    ("/", Operator::Slash),
+    ("%", Operator::Percent),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+    (",", Operator::Comma),
+    ("~", Operator::Tilde),
+    ("^", Operator::Caret),
+    ("|", Operator::Pipe),
+];
+
+// The special names that print as a quoted string.
+const SPECIAL_NAMES: &[(&str, Operator<'static>)] = &[
+    ("`vftable'", Operator::VFTable),
+    ("`vbtable'", Operator::VBTable),
+    ("`vcall'", Operator::VCall),
+    ("`typeof'", Operator::Typeof),
+    ("`string'", Operator::String),
+    ("`vbase destructor'", Operator::VBaseDtor),
+    ("`vector deleting destructor'", Operator::VectorDeletingDtor),
+    (
+        "`default constructor closure'",
+        Operator::DefaultCtorClosure,
+    ),
+    ("`scalar deleting destructor'", Operator::ScalarDeletingDtor),
+    (
+        "`vector constructor iterator'",
+        Operator::VectorCtorIterator,
+    ),
+    ("`vector destructor iterator'", Operator::VectorDtorIterator),
+    (
+        "`vector vbase constructor iterator'",
+        Operator::VectorVBaseCtorIterator,
+    ),
+    (
+        "`virtual displacement map'",
+        Operator::VirtualDisplacementMap,
+    ),
+    (
+        "`eh vector constructor iterator'",
+        Operator::EHVectorCtorIterator,
+    ),
+    (
+        "`eh vector destructor iterator'",
+        Operator::EHVectorDtorIterator,
+    ),
+    (
+        "`eh vector vbase constructor iterator'",
+        Operator::EHVectorVBaseCtorIterator,
+    ),
+    ("`copy constructor closure'", Operator::CopyCtorClosure),
+    ("`local vftable'", Operator::LocalVFTable),
+    (
+        "`local vftable constructor closure'",
+        Operator::LocalVFTableCtorClosure,
+    ),
+    (
+        "`placement delete closure'",
+        Operator::PlacementDeleteClosure,
+    ),
+    (
+        "`placement delete[] closure'",
+        Operator::PlacementArrayDeleteClosure,
+    ),
+    ("`RTTI Base Class Array'", Operator::RTTIBaseClassArray),
+    (
+        "`RTTI Class Hierarchy Descriptor'",
+        Operator::RTTIClassHierarchyDescriptor,
+    ),
+    (
+        "`RTTI Complete Object Locator'",
+        Operator::RTTIClassCompleteObjectLocator,
+    ),
+    ("`dynamic initializer'", Operator::DynamicInitializer),
+    ("`dynamic atexit destructor'", Operator::DynamicAtexitDtor),
+];
+
+// The name of an RTTI type descriptor, which follows the type it describes
+// like a member.
+const TYPE_DESCRIPTOR: &str = "`RTTI Type Descriptor'";
+
+const CALLING_CONVS: &[(&str, CallingConv)] = &[
+    ("__cdecl", CallingConv::Cdecl),
+    ("__pascal", CallingConv::Pascal),
+    ("__thiscall", CallingConv::Thiscall),
+    ("__stdcall", CallingConv::Stdcall),
+    ("__fastcall", CallingConv::Fastcall),
+    ("__vectorcall", CallingConv::Vectorcall),
+    ("__regcall", CallingConv::_Regcall),
+];
+
+struct DeclarationParser<'a> {
+    input: &'a [u8],
+    offset: usize,
+    // The calling convention written before a declarator name, waiting for
+    // the parameter list that it belongs to.
+    calling_conv: Option<CallingConv>,
+    // The target type of `operator <type>` when that is the name that the
+    // last declarator declared, which the AST keeps as the return type.
+    conversion_type: Option<Type<'a>>,
+    // How deeply the type read so far nests, checked against
+    // `DEFAULT_MAX_DEPTH` so that the parser and the AST it builds cannot
+    // overflow the stack.
+    depth: usize,
+}
+
+fn is_parsed_name(name: &Name) -> bool {
+    matches!(*name, Name::ParsedName(_))
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$'
+}
+
+// Depth errors are never retried as something else, so that they are not
+// hidden by the error of the alternative.
+fn is_depth_error(err: &Error) -> bool {
+    matches!(err.kind(), Some(ErrorKind::DepthLimitExceeded(_)))
+}
+
+impl<'a> DeclarationParser<'a> {
+    fn fail(&self, kind: ErrorKind) -> Error {
+        Error::new_parse_error(kind, self.input, self.offset)
+    }
+
+    // Runs `f` one level deeper.  Levels that `f` adds with `deepen` are
+    // given back when it returns.
+    fn descend<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let depth = self.depth;
+        self.deepen()?;
+        let rv = f(self);
+        self.depth = depth;
+        rv
+    }
+
+    // Accounts for one more level of nesting, such as a pointer around the
+    // type read so far.
+    fn deepen(&mut self) -> Result<()> {
+        if self.depth >= DEFAULT_MAX_DEPTH {
+            return Err(self.fail(ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH)));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.input.get(self.offset) == Some(&b' ') {
+            self.offset += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.offset).cloned()
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.input[self.offset..]
+    }
+
+    // Consumes `token` if it comes next.  Tokens that end in an identifier
+    // character only match whole words.
+    fn consume(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let token = token.as_bytes();
+        if !self.remaining().starts_with(token) {
+            return false;
+        }
+        if is_identifier_byte(token[token.len() - 1])
+            && self
+                .input
+                .get(self.offset + token.len())
+                .map_or(false, |&c| is_identifier_byte(c))
+        {
+            return false;
+        }
+        self.offset += token.len();
+        true
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<()> {
+        if self.consume(token) {
+            Ok(())
+        } else if self.peek().is_none() {
+            Err(self.fail(ErrorKind::UnexpectedEnd))
+        } else {
+            Err(self.fail(ErrorKind::Expected(token)))
+        }
+    }
+
+    fn identifier(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespace();
+        let rest = self.remaining();
+        if !rest
+            .first()
+            .map_or(false, |&c| is_identifier_byte(c) && !c.is_ascii_digit())
+        {
+            return None;
+        }
+        let len = rest.iter().take_while(|&&c| is_identifier_byte(c)).count();
+        self.offset += len;
+        Some(&rest[..len])
+    }
+
+    fn number(&mut self) -> Option<i32> {
+        self.skip_whitespace();
+        let start = self.offset;
+        let negative = self.input.get(self.offset) == Some(&b'-');
+        let digits = self.input[start + negative as usize..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        let end = start + negative as usize + digits;
+        let n = std::str::from_utf8(&self.input[start..end])
+            .ok()
+            .and_then(|s| s.parse().ok())?;
+        self.offset = end;
+        Some(n)
+    }
+
+    fn calling_conv(&mut self) -> Option<CallingConv> {
+        for &(keyword, calling_conv) in CALLING_CONVS {
+            if self.consume(keyword) {
+                return Some(calling_conv);
+            }
+        }
+        None
+    }
+
+    fn starts_calling_conv(&mut self) -> bool {
+        let offset = self.offset;
+        let found = self.calling_conv().is_some();
+        self.offset = offset;
+        found
+    }
+
+    fn parse(&mut self) -> Result<ParseResult<'a>> {
+        let mut func_class = FuncClass::empty();
+        if self.consume("[thunk]:") {
+            func_class |= FuncClass::THUNK;
+        }
+        if self.consume("public:") {
+            func_class |= FuncClass::PUBLIC;
+        } else if self.consume("protected:") {
+            func_class |= FuncClass::PROTECTED;
+        } else if self.consume("private:") {
+            func_class |= FuncClass::PRIVATE;
+        }
+        if self.consume("static") {
+            func_class |= FuncClass::STATIC;
+        } else if self.consume("virtual") {
+            func_class |= FuncClass::VIRTUAL;
+        }
+
+        if func_class.is_empty() {
+            if let Some(result) = self.untyped_name()? {
+                return Ok(result);
+            }
+        }
+
+        // Constructors and destructors have no return type.
+        let base = if self.starts_calling_conv() {
+            Type::None
+        } else {
+            self.type_specifier()?
+        };
+        let offset = self.offset;
+        let (declarators, symbol) = self.declarator(true)?;
+        let symbol = match symbol {
+            Some(symbol) => symbol,
+            None => {
+                self.offset = offset;
+                return Err(self.fail(ErrorKind::Expected("name")));
+            }
+        };
+        if symbol.name == Name::Operator(Operator::VCall) {
+            if func_class != FuncClass::THUNK || base != Type::None || !declarators.is_empty() {
+                return Err(self.fail(ErrorKind::Malformed("vcall thunk with a type")));
+            }
+            return self.vcall_thunk(symbol);
+        }
+        let mut t = apply_declarators(base, declarators, self.input, offset)?;
+        let conversion_type = self.conversion_type.take();
+        if let (Name::Operator(Operator::Conversion), Type::NonMemberFunction(sig)) =
+            (&symbol.name, &mut t)
+        {
+            match conversion_type {
+                Some(conversion_type) if *sig.return_type == Type::None => {
+                    *sig.return_type = conversion_type;
+                }
+                Some(ref conversion_type) if *sig.return_type == *conversion_type => {}
+                _ => {
+                    return Err(self.fail(ErrorKind::Malformed(
+                        "conversion operator with a different return type",
+                    )))
+                }
+            }
+        }
+
+        let symbol_type = match t {
+            Type::NonMemberFunction(mut sig) if !func_class.is_empty() => {
+                if func_class.contains(FuncClass::STATIC) && !sig.this_quals.is_empty() {
+                    return Err(self.fail(ErrorKind::Malformed(
+                        "static member function with qualifiers",
+                    )));
+                }
+                if func_class.contains(FuncClass::STATIC) {
+                    sig.this_quals = StorageClass::empty();
+                }
+                Type::MemberFunction(func_class, sig)
+            }
+            Type::NonMemberFunction(sig) => Type::NonMemberFunction(sig),
+            Type::None => return Err(self.fail(ErrorKind::Expected("type"))),
+            mut t => {
+                let kind = if func_class == FuncClass::PUBLIC | FuncClass::STATIC {
+                    VarStorageKind::PublicStatic
+                } else if func_class == FuncClass::PROTECTED | FuncClass::STATIC {
+                    VarStorageKind::ProtectedStatic
+                } else if func_class == FuncClass::PRIVATE | FuncClass::STATIC {
+                    VarStorageKind::PrivateStatic
+                } else if func_class.is_empty() && symbol.scope.names.iter().any(is_parsed_name) {
+                    VarStorageKind::FunctionLocalStatic
+                } else if func_class.is_empty() {
+                    VarStorageKind::Global
+                } else {
+                    return Err(self.fail(ErrorKind::Malformed("member variable is not static")));
+                };
+                // Pointers keep their own qualifiers; for everything else
+                // the variable carries them.
+                let sc = match t {
+                    Type::Ptr(..)
+                    | Type::MemberDataPointer(..)
+                    | Type::Ref(..)
+                    | Type::RValueRef(..)
+                    | Type::Array(..) => StorageClass::empty(),
+                    _ => match t.storage_class_mut() {
+                        Some(sc) => {
+                            let cv = *sc & (StorageClass::CONST | StorageClass::VOLATILE);
+                            *sc -= cv;
+                            cv
+                        }
+                        None => StorageClass::empty(),
+                    },
+                };
+                Type::Var(Box::new(t), kind, sc)
+            }
+        };
+        Ok(ParseResult {
+            symbol,
+            symbol_type,
+        })
+    }
+
+    // Reads the declarations that have no type in front of their name:
+    // `extern "C"` names, virtual tables, RTTI data and static guards.
+    fn untyped_name(&mut self) -> Result<Option<ParseResult<'a>>> {
+        let offset = self.offset;
+        if let Some(n) = self.tss_guard() {
+            let symbol = self.qualified_name()?;
+            return Ok(Some(ParseResult {
+                symbol,
+                symbol_type: Type::ThreadSafeStaticGuard(n),
+            }));
+        }
+
+        // RTTI type descriptors are named after the type they describe.
+        match self.type_id() {
+            Ok(mut t) if self.consume("::") && self.consume(TYPE_DESCRIPTOR) => {
+                let sc = t.storage_class_mut().map_or(StorageClass::empty(), |sc| {
+                    *sc & (StorageClass::CONST | StorageClass::VOLATILE)
+                });
+                return Ok(Some(ParseResult {
+                    symbol: Symbol {
+                        name: Name::Operator(Operator::RTTITypeDescriptor(sc, Box::new(t))),
+                        scope: NameSequence { names: Vec::new() },
+                    },
+                    symbol_type: Type::RTTIType,
+                }));
+            }
+            Err(err) if is_depth_error(&err) => return Err(err),
+            _ => self.offset = offset,
+        }
+
+        let sc = self.cv_qualifiers();
+        let symbol = match self.qualified_name() {
+            Ok(symbol) => symbol,
+            Err(err) if is_depth_error(&err) => return Err(err),
+            Err(_) => {
+                self.offset = offset;
+                return Ok(None);
+            }
+        };
+        let symbol_type = match symbol.name {
+            Name::Operator(Operator::VFTable)
+            | Name::Operator(Operator::LocalVFTable)
+            | Name::Operator(Operator::RTTIClassCompleteObjectLocator) => {
+                let target = if self.peek() == Some(b'{') {
+                    self.table_target()?
+                } else {
+                    NameSequence { names: Vec::new() }
+                };
+                Type::CXXVFTable(target, sc)
+            }
+            Name::Operator(Operator::VBTable) => Type::CXXVBTable(self.table_target()?, sc),
+            ref name if sc.is_empty() && super::is_rtti_name(name) => Type::RTTIType,
+            _ if sc.is_empty() && self.at_end() => Type::None,
+            _ => {
+                self.offset = offset;
+                return Ok(None);
+            }
+        };
+        Ok(Some(ParseResult {
+            symbol,
+            symbol_type,
+        }))
+    }
+
+    // Reads the `TSS0` that the guard of a thread-safe static prints in
+    // front of its scope.
+    fn tss_guard(&mut self) -> Option<i32> {
+        let offset = self.offset;
+        if let Some(identifier) = self.identifier() {
+            if self.remaining().starts_with(b"`") {
+                let n = identifier
+                    .strip_prefix(b"TSS")
+                    .and_then(|digits| std::str::from_utf8(digits).ok())
+                    .and_then(|digits| digits.parse().ok());
+                if n.is_some() {
+                    return n;
+                }
+            }
+        }
+        self.offset = offset;
+        None
+    }
+
+    // Reads the `{for `A::B'}` of a virtual table, the class whose part of
+    // the object the table is for.
+    fn table_target(&mut self) -> Result<NameSequence<'a>> {
+        self.expect("{for")?;
+        self.expect("`")?;
+        let mut names = Vec::new();
+        if !self.consume("'") {
+            let target = self.qualified_name()?;
+            names.push(target.name);
+            names.extend(target.scope.names);
+            self.expect("'")?;
+        }
+        self.expect("}")?;
+        Ok(NameSequence { names })
+    }
+
+    // Reads the `{968,{flat}}` that follows the name of a vcall thunk.
+    fn vcall_thunk(&mut self, symbol: Symbol<'a>) -> Result<ParseResult<'a>> {
+        let calling_conv = match self.calling_conv.take() {
+            Some(calling_conv) => calling_conv,
+            None => return Err(self.fail(ErrorKind::Expected("calling convention"))),
+        };
+        self.expect("{")?;
+        let offset = match self.number() {
+            Some(offset) => offset,
+            None => return Err(self.fail(ErrorKind::Expected("vftable offset"))),
+        };
+        self.expect(",")?;
+        self.expect("{flat}")?;
+        self.expect("}")?;
+        Ok(ParseResult {
+            symbol,
+            symbol_type: Type::VCallThunk(offset, calling_conv),
+        })
+    }
+
+    // Whether the declaration ends here, either at the end of the input or
+    // at the quote that closes a declaration nested in a name.
+    fn at_end(&mut self) -> bool {
+        matches!(self.peek(), None | Some(b'\''))
+    }
+
+    fn cv_qualifiers(&mut self) -> StorageClass {
+        let mut sc = StorageClass::empty();
+        loop {
+            if self.consume("const") {
+                sc |= StorageClass::CONST;
+            } else if self.consume("volatile") {
+                sc |= StorageClass::VOLATILE;
+            } else {
+                return sc;
+            }
+        }
+    }
+
+    // Reads a type without declarators, such as `unsigned int const` or
+    // `class ns::Foo`.
+    fn type_specifier(&mut self) -> Result<Type<'a>> {
+        let leading = self.cv_qualifiers();
+        let mut t = if self.consume("class") {
+            Type::Class(self.qualified_name()?, StorageClass::empty())
+        } else if self.consume("struct") {
+            Type::Struct(self.qualified_name()?, StorageClass::empty())
+        } else if self.consume("union") {
+            Type::Union(self.qualified_name()?, StorageClass::empty())
+        } else if self.consume("enum") {
+            Type::Enum(self.qualified_name()?, StorageClass::empty())
+        } else if self.consume("std::nullptr_t") {
+            Type::Nullptr
+        } else {
+            self.builtin_type()?
+        };
+        let sc = leading | self.cv_qualifiers();
+        if !sc.is_empty() {
+            match t.storage_class_mut() {
+                Some(storage_class) => *storage_class |= sc,
+                None => return Err(self.fail(ErrorKind::Malformed("qualified std::nullptr_t"))),
+            }
+        }
+        Ok(t)
+    }
+
+    fn builtin_type(&mut self) -> Result<Type<'a>> {
+        let sc = StorageClass::empty();
+        let t = if self.consume("unsigned") {
+            if self.consume("char") {
+                Type::Uchar(sc)
+            } else if self.consume("short") {
+                Type::Ushort(sc)
+            } else if self.consume("int") {
+                Type::Uint(sc)
+            } else if self.consume("long") {
+                Type::Ulong(sc)
+            } else if self.consume("__int64") {
+                Type::Uint64(sc)
+            } else if self.consume("__int128") {
+                Type::Uint128(sc)
+            } else {
+                return Err(self.fail(ErrorKind::UnknownPrimitiveType));
+            }
+        } else if self.consume("signed") {
+            self.expect("char")?;
+            Type::Schar(sc)
+        } else if self.consume("long") {
+            if self.consume("double") {
+                Type::Ldouble(sc)
+            } else {
+                Type::Long(sc)
+            }
+        } else if self.consume("void") {
+            Type::Void(sc)
+        } else if self.consume("bool") {
+            Type::Bool(sc)
+        } else if self.consume("char") {
+            Type::Char(sc)
+        } else if self.consume("short") {
+            Type::Short(sc)
+        } else if self.consume("int") {
+            Type::Int(sc)
+        } else if self.consume("__int64") || self.consume("int64_t") {
+            Type::Int64(sc)
+        } else if self.consume("uint64_t") {
+            Type::Uint64(sc)
+        } else if self.consume("__int128") || self.consume("int128_t") {
+            Type::Int128(sc)
+        } else if self.consume("uint128_t") {
+            Type::Uint128(sc)
+        } else if self.consume("wchar_t") {
+            Type::Wchar(sc)
+        } else if self.consume("char8_t") {
+            Type::Char8(sc)
+        } else if self.consume("char16_t") {
+            Type::Char16(sc)
+        } else if self.consume("char32_t") {
+            Type::Char32(sc)
+        } else if self.consume("float") {
+            Type::Float(sc)
+        } else if self.consume("double") {
+            Type::Double(sc)
+        } else if self.peek().is_none() {
+            return Err(self.fail(ErrorKind::UnexpectedEnd));
+        } else {
+            return Err(self.fail(ErrorKind::UnknownPrimitiveType));
+        };
+        Ok(t)
+    }
+
+    // Reads a type with an abstract declarator, as found in parameter and
+    // template argument lists.
+    fn type_id(&mut self) -> Result<Type<'a>> {
+        self.descend(|this| {
+            // The calling convention of the enclosing declarator waits
+            // until this type is done.
+            let calling_conv = this.calling_conv.take();
+            let base = this.type_specifier()?;
+            let offset = this.offset;
+            let (declarators, _) = this.declarator(false)?;
+            this.calling_conv = calling_conv;
+            apply_declarators(base, declarators, this.input, offset)
+        })
+    }
+
+    // Reads `*`, `&`, `&&` and `Foo::*` with their qualifiers, and the
+    // calling conventions between them.
+    fn pointer_operators(&mut self, declarators: &mut Vec<Declarator<'a>>) -> Result<()> {
+        loop {
+            if let Some(calling_conv) = self.calling_conv() {
+                self.calling_conv = Some(calling_conv);
+            } else if self.consume("*") {
+                self.deepen()?;
+                let sc = self.cv_qualifiers();
+                declarators.push(Declarator::Ptr(sc));
+            } else if self.consume("&&") {
+                self.deepen()?;
+                let sc = self.cv_qualifiers();
+                declarators.push(Declarator::RValueRef(sc));
+            } else if self.consume("&") {
+                self.deepen()?;
+                let sc = self.cv_qualifiers();
+                declarators.push(Declarator::Ref(sc));
+            } else if let Some(symbol) = self.member_pointer_class()? {
+                self.deepen()?;
+                let sc = self.cv_qualifiers();
+                declarators.push(Declarator::MemberPtr(symbol, sc));
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    // Reads the `Foo::` of `Foo::*`, or nothing if no member pointer
+    // follows.
+    fn member_pointer_class(&mut self) -> Result<Option<Symbol<'a>>> {
+        match self.peek() {
+            Some(c) if is_identifier_byte(c) && !c.is_ascii_digit() => {}
+            _ => return Ok(None),
+        }
+        let offset = self.offset;
+        if let Ok(symbol) = self.qualified_name() {
+            if self.consume("::") && self.consume("*") {
+                return Ok(Some(symbol));
+            }
+        }
+        self.offset = offset;
+        Ok(None)
+    }
+
+    // Reads a declarator and returns the pieces to apply to the base type,
+    // in order, along with the declared name.
+    fn declarator(&mut self, named: bool) -> Result<(Vec<Declarator<'a>>, Option<Symbol<'a>>)> {
+        let mut declarators = Vec::new();
+        self.pointer_operators(&mut declarators)?;
+
+        let mut inner = Vec::new();
+        let mut symbol = None;
+        let mut conversion_type = None;
+        if self.peek() == Some(b'(') && self.starts_nested_declarator() {
+            self.expect("(")?;
+            self.deepen()?;
+            let (nested, nested_symbol) = self.declarator(named)?;
+            self.expect(")")?;
+            inner = nested;
+            symbol = nested_symbol;
+            conversion_type = self.conversion_type.take();
+        } else if named {
+            match self.peek() {
+                Some(b'(') | Some(b'[') | Some(b')') | Some(b',') | Some(b'>') | None => {}
+                Some(_) => {
+                    self.conversion_type = None;
+                    symbol = Some(self.qualified_name()?);
+                    conversion_type = self.conversion_type.take();
+                }
+            }
+        }
+
+        let mut suffixes = Vec::new();
+        loop {
+            if self.peek() == Some(b'(') {
+                self.deepen()?;
+                suffixes.push(Declarator::Function(self.function_suffix()?));
+            } else if self.consume("[") {
+                self.deepen()?;
+                let len = match self.number() {
+                    Some(len) if len > 0 => len,
+                    _ => return Err(self.fail(ErrorKind::Expected("array dimension"))),
+                };
+                self.expect("]")?;
+                suffixes.push(Declarator::Array(len));
+            } else {
+                break;
+            }
+        }
+
+        declarators.extend(suffixes.into_iter().rev());
+        declarators.extend(inner);
+        self.conversion_type = conversion_type;
+        Ok((declarators, symbol))
+    }
+
+    // Tells a parenthesized declarator like `(__cdecl *)` apart from a
+    // parameter list.
+    fn starts_nested_declarator(&mut self) -> bool {
+        let offset = self.offset;
+        self.offset += 1;
+        let nested = self.starts_calling_conv()
+            || matches!(self.peek(), Some(b'*') | Some(b'&'))
+            || self.member_pointer_class().ok().flatten().is_some();
+        self.offset = offset;
+        nested
+    }
+
+    fn function_suffix(&mut self) -> Result<FunctionSignature<'a>> {
+        let calling_conv = self.calling_conv.take().unwrap_or(CallingConv::Cdecl);
+        let params = self.params()?;
+        let mut this_quals = StorageClass::empty();
+        loop {
+            if self.consume("const") {
+                this_quals |= StorageClass::CONST;
+            } else if self.consume("volatile") {
+                this_quals |= StorageClass::VOLATILE;
+            } else if self.consume("__ptr64") {
+                this_quals |= StorageClass::PTR64;
+            } else if self.consume("__restrict") {
+                this_quals |= StorageClass::RESTRICT;
+            } else if self.consume("&&") {
+                this_quals |= StorageClass::RVALUE_QUAL;
+            } else if self.consume("&") {
+                this_quals |= StorageClass::LVALUE_QUAL;
+            } else {
+                break;
+            }
+        }
+        let noexcept = self.consume("noexcept");
+        Ok(FunctionSignature {
+            calling_conv,
+            params: Params { types: params },
+            this_quals,
+            return_type: Box::new(Type::None),
+            noexcept,
+            this_adjustment: 0,
+        })
+    }
+
+    fn params(&mut self) -> Result<Vec<Type<'a>>> {
+        self.expect("(")?;
+        let mut params = Vec::new();
+        if self.consume(")") {
+            return Ok(params);
+        }
+        loop {
+            if self.consume("...") {
+                params.push(Type::VarArgs);
+            } else {
+                params.push(self.type_id()?);
+            }
+            if !self.consume(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(params)
+    }
+
+    fn template_args(&mut self) -> Result<Params<'a>> {
+        self.descend(|this| this.template_args_here())
+    }
+
+    fn template_args_here(&mut self) -> Result<Params<'a>> {
+        self.expect("<")?;
+        let mut types = Vec::new();
+        if self.consume(">") {
+            // `f<>` instantiates a template with an empty parameter pack.
+            types.push(Type::ParameterPack(PackMarker::EmptyTypePack));
+        } else {
+            loop {
+                if let Some(n) = self.number() {
+                    types.push(Type::Constant(n));
+                } else {
+                    // A bare name is a template template argument.
+                    let offset = self.offset;
+                    match self.type_id() {
+                        Ok(t) => types.push(t),
+                        Err(err) if is_depth_error(&err) => return Err(err),
+                        Err(err) => {
+                            self.offset = offset;
+                            match self.qualified_name() {
+                                Ok(symbol) => types.push(Type::TemplateTemplate(symbol)),
+                                Err(name_err) if is_depth_error(&name_err) => return Err(name_err),
+                                Err(_) => return Err(err),
+                            }
+                        }
+                    }
+                }
+                if !self.consume(",") {
+                    break;
+                }
+            }
+            self.expect(">")?;
+        }
+        Ok(Params { types })
+    }
+
+    // Reads `a::b::C`.  The last component is the name, and a last
+    // component that repeats the class name is a constructor.
+    fn qualified_name(&mut self) -> Result<Symbol<'a>> {
+        let mut names = vec![self.unqualified_name()?];
+        loop {
+            let offset = self.offset;
+            if !self.consume("::") {
+                break;
+            }
+            if self.peek() == Some(b'*') || self.remaining().starts_with(TYPE_DESCRIPTOR.as_bytes())
+            {
+                self.offset = offset;
+                break;
+            }
+            names.push(self.unqualified_name()?);
+        }
+        let mut name = names.pop().unwrap();
+        if let Some(class) = names.last() {
+            if name == *class {
+                name = Name::Operator(Operator::Ctor);
+            }
+        }
+        names.reverse();
+        Ok(Symbol {
+            name,
+            scope: NameSequence { names },
+        })
+    }
+
+    fn unqualified_name(&mut self) -> Result<Name<'a>> {
+        if self.consume("`anonymous namespace'") {
+            return Ok(Name::AnonymousNamespace(None));
+        }
+        if self.peek() == Some(b'`') {
+            return self.special_name();
+        }
+        if self.consume("~") {
+            // The class name is repeated; the scope already records it.
+            self.descend(|this| this.unqualified_name())?;
+            return Ok(Name::Operator(Operator::Dtor));
+        }
+        let offset = self.offset;
+        let name = match self.identifier() {
+            Some(b"operator") => Name::Operator(self.operator_name()?),
+            // Templated conversion operators print their target type as a
+            // template argument.
+            Some(b"operatorcast") => {
+                if self.peek() != Some(b'<') {
+                    return Err(self.fail(ErrorKind::Expected("<")));
+                }
+                Name::Operator(Operator::Conversion)
+            }
+            Some(identifier) => Name::NonTemplate(Cow::Borrowed(identifier)),
+            None if self.peek().is_none() => return Err(self.fail(ErrorKind::UnexpectedEnd)),
+            None => return Err(self.fail(ErrorKind::Expected("name"))),
+        };
+        if self.peek() == Some(b'<') && !self.input[offset..self.offset].ends_with(b"<") {
+            let args = self.template_args()?;
+            return Ok(Name::Template(Box::new(name), args));
+        }
+        Ok(name)
+    }
+
+    // Reads a name in quotes: a special name, a discriminator or the
+    // declaration that a local name is nested in.
+    fn special_name(&mut self) -> Result<Name<'a>> {
+        for &(token, ref op) in SPECIAL_NAMES {
+            if self.consume(token) {
+                return Ok(Name::Operator(op.clone()));
+            }
+        }
+        if self.consume("`local static guard'") {
+            return Ok(Name::Operator(Operator::LocalStaticGuard(
+                self.guard_index()?,
+            )));
+        }
+        if self.consume("`local static thread guard'") {
+            return Ok(Name::Operator(Operator::LocalStaticThreadGuard(
+                self.guard_index()?,
+            )));
+        }
+        if self.consume("`RTTI Base Class Descriptor at (") {
+            let mut offsets = [0; 4];
+            for (i, offset) in offsets.iter_mut().enumerate() {
+                if i > 0 {
+                    self.expect(",")?;
+                }
+                *offset = match self.number() {
+                    Some(n) => n,
+                    None => return Err(self.fail(ErrorKind::Expected("offset"))),
+                };
+            }
+            self.expect(")'")?;
+            let [nv_offset, vbptr_offset, vbtable_offset, flags] = offsets;
+            return Ok(Name::Operator(Operator::RTTIBaseClassDescriptor(
+                nv_offset,
+                vbptr_offset,
+                vbtable_offset,
+                flags,
+            )));
+        }
+        self.expect("`")?;
+        if let Some(n) = self.number() {
+            self.expect("'")?;
+            return Ok(Name::Discriminator(n));
+        }
+        let parsed = self.descend(|this| {
+            // The nested declaration has its own declarators.
+            let calling_conv = this.calling_conv.take();
+            let parsed = this.parse();
+            this.calling_conv = calling_conv;
+            parsed
+        })?;
+        self.expect("'")?;
+        Ok(Name::ParsedName(Box::new(parsed)))
+    }
+
+    // Reads the `{2}` after the name of a static guard, if there is one.
+    fn guard_index(&mut self) -> Result<Option<u32>> {
+        if !self.consume("{") {
+            return Ok(None);
+        }
+        let n = match self.number() {
+            Some(n) if n >= 0 => n as u32,
+            _ => return Err(self.fail(ErrorKind::Expected("guard index"))),
+        };
+        self.expect("}")?;
+        Ok(Some(n))
+    }
+
+    fn operator_name(&mut self) -> Result<Operator<'a>> {
+        self.skip_whitespace();
+        for &(token, ref op) in OPERATORS {
+            if self.remaining().starts_with(token.as_bytes()) {
+                self.offset += token.len();
+                return Ok(op.clone());
+            }
+        }
+        // `operator int`: the target type is also the return type, which
+        // is where the AST keeps it.
+        let offset = self.offset;
+        let t = self.descend(|this| {
+            let base = this.type_specifier()?;
+            let mut declarators = Vec::new();
+            this.pointer_operators(&mut declarators)?;
+            apply_declarators(base, declarators, this.input, offset)
+        })?;
+        self.conversion_type = Some(t);
+        Ok(Operator::Conversion)
+    }
+}
+
+// Applies the declarators to the base type.  `input` and `offset` locate
+// the declarator for errors.
+fn apply_declarators<'a>(
+    mut t: Type<'a>,
+    declarators: Vec<Declarator<'a>>,
+    input: &[u8],
+    offset: usize,
+) -> Result<Type<'a>> {
+    for declarator in declarators {
+        t = match declarator {
+            Declarator::Ptr(sc) => Type::Ptr(Box::new(t), sc),
+            Declarator::Ref(sc) => Type::Ref(Box::new(t), sc),
+            Declarator::RValueRef(sc) => Type::RValueRef(Box::new(t), sc),
+            Declarator::MemberPtr(symbol, sc) => match t {
+                Type::NonMemberFunction(sig) if sc.is_empty() => {
+                    Type::MemberFunctionPointer(symbol, FuncClass::empty(), sig)
+                }
+                Type::NonMemberFunction(_) => {
+                    return Err(Error::new_parse_error(
+                        ErrorKind::Malformed("qualified pointer to member function"),
+                        input,
+                        offset,
+                    ))
+                }
+                t => Type::MemberDataPointer(symbol, Box::new(t), sc),
+            },
+            Declarator::Function(mut sig) => {
+                sig.return_type = Box::new(t);
+                Type::NonMemberFunction(sig)
+            }
+            Declarator::Array(len) => {
+                // Every dimension carries the qualifiers of the element.
+                let sc = match t {
+                    Type::Array(_, _, sc) => sc,
+                    ref mut t => match t.storage_class_mut() {
+                        Some(sc) => {
+                            let cv = *sc & (StorageClass::CONST | StorageClass::VOLATILE);
+                            *sc -= cv;
+                            cv
+                        }
+                        None => StorageClass::empty(),
+                    },
+                };
+                Type::Array(len, Box::new(t), sc)
+            }
+        };
+    }
+    Ok(t)
+}
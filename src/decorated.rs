@@ -0,0 +1,123 @@
+//! C-level decorations around symbol names.
+//!
+//! Before a name reaches the symbol table, 32-bit compilers decorate C
+//! names with their calling convention and import libraries prefix
+//! imported symbols with `__imp_`.  `parse_decorated` takes these apart
+//! and parses the C++ name inside, if there is one:
+//!
+//! ```
+//! use msvc_demangler::{parse_decorated, CallingConv};
+//!
+//! let decorated = parse_decorated("__imp__GetTickCount@0").unwrap();
+//! assert!(decorated.import);
+//! assert_eq!(decorated.calling_conv, Some(CallingConv::Stdcall));
+//! assert_eq!(decorated.arg_bytes, Some(0));
+//! assert_eq!(decorated.name, "GetTickCount");
+//! assert!(decorated.parsed.is_none());
+//! ```
+
+use super::{parse, CallingConv, Error, ErrorKind, ParseResult, Result};
+
+/// A symbol name with its C-level decorations taken apart.  See
+/// `parse_decorated`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecoratedName<'a> {
+    /// Whether the name had the `__imp_` prefix of import thunks.
+    pub import: bool,
+    /// The calling convention the decoration implies: `_foo` is
+    /// `__cdecl`, `_foo@12` `__stdcall`, `@foo@8` `__fastcall` and
+    /// `foo@@16` `__vectorcall`.  `None` for names without a decoration,
+    /// including C++ names.
+    pub calling_conv: Option<CallingConv>,
+    /// The size of the arguments in bytes, from the `@<n>` suffix.
+    pub arg_bytes: Option<u32>,
+    /// The name without the decorations: the C identifier, or the whole
+    /// mangled name for C++ symbols.
+    pub name: &'a str,
+    /// The parsed C++ name, for names that start with `?`.
+    pub parsed: Option<ParseResult<'a>>,
+}
+
+/// Strips the `__imp_` prefix and the calling convention decorations from
+/// a symbol name and parses the C++ name inside.
+///
+/// Names that have none of these decorations, like those of 64-bit C
+/// functions, come back unchanged.  Errors in the C++ name are passed
+/// on, and an `@` suffix whose argument size is missing or does not fit
+/// in a `u32` is an error.
+pub fn parse_decorated(input: &str) -> Result<DecoratedName<'_>> {
+    let (import, name) = match input.strip_prefix("__imp_") {
+        Some(name) => (true, name),
+        None => (false, input),
+    };
+    let offset = input.len() - name.len();
+    let fail_at = |kind, offset| Err(Error::new_parse_error(kind, input.as_bytes(), offset));
+    let fail = |kind| fail_at(kind, offset);
+
+    if name.is_empty() {
+        return fail(ErrorKind::UnexpectedEnd);
+    }
+
+    if name.starts_with('?') {
+        let parsed = parse(name).map_err(|err| match (err.kind(), err.offset()) {
+            (Some(kind), Some(inner)) => {
+                Error::new_parse_error(kind.clone(), input.as_bytes(), offset + inner)
+            }
+            _ => err,
+        })?;
+        return Ok(DecoratedName {
+            import,
+            calling_conv: None,
+            arg_bytes: None,
+            name,
+            parsed: Some(parsed),
+        });
+    }
+
+    let (calling_conv, suffix, name) = if let Some(rest) = name.strip_prefix('@') {
+        match split_arg_bytes(rest, "@") {
+            Some((name, digits)) => (Some(CallingConv::Fastcall), Some(digits), name),
+            None => return fail(ErrorKind::Expected("argument size")),
+        }
+    } else if let Some((name, digits)) = split_arg_bytes(name, "@@") {
+        (Some(CallingConv::Vectorcall), Some(digits), name)
+    } else if let Some(rest) = name.strip_prefix('_') {
+        match split_arg_bytes(rest, "@") {
+            Some((name, digits)) => (Some(CallingConv::Stdcall), Some(digits), name),
+            None => (Some(CallingConv::Cdecl), None, rest),
+        }
+    } else {
+        (None, None, name)
+    };
+    if name.is_empty() {
+        return fail(ErrorKind::Expected("name"));
+    }
+    // A separator without a size, or one too large, is not a `__cdecl`
+    // name with an `@` in it.
+    let arg_bytes = match suffix {
+        Some("") => return fail_at(ErrorKind::Expected("argument size"), input.len()),
+        Some(digits) => match digits.parse() {
+            Ok(arg_bytes) => Some(arg_bytes),
+            Err(_) => return fail_at(ErrorKind::BadNumber, input.len() - digits.len()),
+        },
+        None => None,
+    };
+    Ok(DecoratedName {
+        import,
+        calling_conv,
+        arg_bytes,
+        name,
+        parsed: None,
+    })
+}
+
+// Splits `name<separator><decimal digits>` into the name and the digits,
+// which may be empty.
+fn split_arg_bytes<'a>(s: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let index = s.rfind(separator)?;
+    let digits = &s[index + separator.len()..];
+    if !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((&s[..index], digits))
+}
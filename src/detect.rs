@@ -0,0 +1,164 @@
+//! Telling MSVC names apart from other symbols by their prefix.
+//!
+//! `detect` looks at the first few bytes of a symbol only.  It neither
+//! allocates nor parses, so it suits routing streams that mix MSVC names
+//! with Itanium or Rust ones:
+//!
+//! ```
+//! use msvc_demangler::{detect, MangledKind};
+//!
+//! assert_eq!(detect("?foo@@YAXXZ"), Some(MangledKind::Mangled));
+//! assert_eq!(detect(".?AVFoo@@"), Some(MangledKind::TypeName));
+//! assert_eq!(detect("_foo@12"), Some(MangledKind::Decorated));
+//! assert_eq!(detect("_ZN3foo3barEv"), None);
+//! ```
+
+use std::str;
+
+/// The kind of symbol `detect` finds from the prefix of a name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MangledKind {
+    /// A C++ mangled name of a kind that only parsing can tell.
+    Mangled,
+    /// A mangled name that starts with `??$`, such as a function template
+    /// specialization.
+    Template,
+    /// The `??@<md5>@` name that stands for an over-long mangled name.  See
+    /// `md5_mangled_name`.
+    Md5,
+    /// The guard variable of a function-local static.
+    Guard,
+    /// A string literal.
+    StringLiteral,
+    /// A virtual function table.
+    VTable,
+    /// A virtual base table.
+    VBTable,
+    /// RTTI data such as a type or class hierarchy descriptor.
+    RTTI,
+    /// A type on its own, such as the `.?AVFoo@@` of RTTI.  See
+    /// `parse_type`.
+    TypeName,
+    /// A name with the `__imp_` prefix of import thunks.  See
+    /// `parse_decorated`.
+    Import,
+    /// A C name decorated with its calling convention, such as `_foo@12`.
+    /// See `parse_decorated`.
+    Decorated,
+}
+
+/// Classifies a symbol by its prefix, or returns `None` if it does not
+/// look like an MSVC name.
+///
+/// Symbols whose prefix gives their kind away, like string literals,
+/// vftables, RTTI data and guards, get that kind.  Other C++ names are
+/// `MangledKind::Mangled`.  A match does not mean the name parses.
+pub fn detect(symbol: &str) -> Option<MangledKind> {
+    let s = symbol.as_bytes();
+    if s.starts_with(b"__imp_") {
+        return Some(MangledKind::Import);
+    }
+    if s.starts_with(b"?") {
+        return Some(if s.starts_with(b"??@") {
+            MangledKind::Md5
+        } else if s.starts_with(b"??$") {
+            MangledKind::Template
+        } else if s.starts_with(b"?$TSS") || s.starts_with(b"??_B") {
+            MangledKind::Guard
+        } else if s.starts_with(b"??_C@") {
+            MangledKind::StringLiteral
+        } else if s.starts_with(b"??_7") {
+            MangledKind::VTable
+        } else if s.starts_with(b"??_8") {
+            MangledKind::VBTable
+        } else if s.starts_with(b"??_R") {
+            MangledKind::RTTI
+        } else {
+            MangledKind::Mangled
+        });
+    }
+    if is_type_name(s) {
+        return Some(MangledKind::TypeName);
+    }
+    if is_decorated(s) {
+        return Some(MangledKind::Decorated);
+    }
+    None
+}
+
+/// Returns whether `symbol` is an MSVC mangled name, possibly behind an
+/// `__imp_` prefix.  C names with calling convention decorations are not
+/// mangled.
+pub fn is_msvc_mangled(symbol: &str) -> bool {
+    let symbol = symbol.strip_prefix("__imp_").unwrap_or(symbol);
+    match detect(symbol) {
+        Some(MangledKind::Decorated) | Some(MangledKind::Import) | None => false,
+        Some(_) => true,
+    }
+}
+
+// The raw names of RTTI type descriptors: `.` and a type, such as
+// `.?AVFoo@@`, `.PEAX` or `.H`.  Fundamental types must make up the whole
+// name, so section names like `.CRT$XCU` are left out.
+fn is_type_name(s: &[u8]) -> bool {
+    match s {
+        [b'.', b'?', b'A' | b'B', ..] | [b'.', b'P' | b'Q' | b'R' | b'S', ..] => true,
+        [b'.', b'C'..=b'K' | b'M'..=b'O' | b'X'] => true,
+        [b'.', b'_', b'J'..=b'N' | b'Q' | b'S' | b'U' | b'W'] => true,
+        _ => s == b".$$T",
+    }
+}
+
+// `_foo`, `_foo@12`, `@foo@8` or `foo@@16`, the decorations that
+// `parse_decorated` takes apart.  Itanium (`_Z`, `__Z`) and Rust (`_R`)
+// manglings also start with an underscore and are left out.
+fn is_decorated(s: &[u8]) -> bool {
+    if let Some(rest) = s.strip_prefix(b"@") {
+        return match split_arg_bytes(rest, b"@") {
+            Some(name) => is_identifier(name),
+            None => false,
+        };
+    }
+    if let Some(name) = split_arg_bytes(s, b"@@") {
+        return is_identifier(name);
+    }
+    if let Some(rest) = s.strip_prefix(b"_") {
+        if is_foreign_mangling(rest) {
+            return false;
+        }
+        let name = split_arg_bytes(rest, b"@").unwrap_or(rest);
+        return is_identifier(name);
+    }
+    false
+}
+
+// `Z`, `_Z` or `R` after the leading underscore, followed by the end, a
+// digit or an uppercase letter the way those manglings continue.  C names
+// such as `_Render@4` are still decorated.
+fn is_foreign_mangling(rest: &[u8]) -> bool {
+    let tail = match rest {
+        [b'Z', tail @ ..] | [b'_', b'Z', tail @ ..] | [b'R', tail @ ..] => tail,
+        _ => return false,
+    };
+    tail.first()
+        .map_or(true, |c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+// Returns the name of `name<separator><decimal number>`, where the number
+// fits in a `u32` like `parse_decorated` requires.
+fn split_arg_bytes<'a>(s: &'a [u8], separator: &[u8]) -> Option<&'a [u8]> {
+    let index = s.windows(separator.len()).rposition(|w| w == separator)?;
+    let digits = &s[index + separator.len()..];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    str::from_utf8(digits).ok()?.parse::<u32>().ok()?;
+    Some(&s[..index])
+}
+
+fn is_identifier(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name
+            .iter()
+            .all(|&c| c.is_ascii_alphanumeric() || c == b'_' || c == b'$')
+}
@@ -0,0 +1,124 @@
+//! Demangling of the names embedded in free-form text.
+//!
+//! Linker errors, stack traces, `.map` files and assembly listings mention
+//! mangled names in the middle of other text.  `demangle_in_text` finds
+//! them, like `c++filt` does, and replaces each one that demangles:
+//!
+//! ```
+//! use msvc_demangler::{demangle_in_text, DemangleFlags};
+//!
+//! let line = "app.exe!?foo@Bar@@QAEHH@Z+0x1a";
+//! assert_eq!(
+//!     demangle_in_text(line, DemangleFlags::llvm()),
+//!     "app.exe!public: int __thiscall Bar::foo(int)+0x1a"
+//! );
+//! ```
+
+use std::borrow::Cow;
+
+use super::{demangle_type_with_options, demangle_with_options, DemangleFlags, ParseOptions};
+
+const IMPORT_PREFIX: &str = "__imp_";
+
+// Text can come from anywhere, so names that expand far beyond what real
+// symbols need are left alone.  The largest names in the test corpus have
+// a few hundred nodes and demangle to about a kilobyte.
+const MAX_AST_NODES: usize = 10_000;
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+/// Replaces every MSVC mangled name in `text` with its demangled form.
+///
+/// A name starts with `?`, or with `.?A` for RTTI type names, after any
+/// byte that cannot be part of a name, such as a space, a quote or a
+/// parenthesis.  It extends as far as such bytes do, so an offset like
+/// `+0x1a` or a closing quote is left alone.  Names with a part like
+/// `<lambda_1>` extend over the angle brackets if they only demangle with
+/// them.  Imported names keep their
+/// meaning as `__declspec(dllimport)`.
+///
+/// Names are parsed strictly, and those that do not demangle stay as they
+/// are, as does all the text around them.  So do names that expand far
+/// beyond the size of real symbols.  The text is only copied if
+/// something was replaced.
+pub fn demangle_in_text(text: &str, flags: DemangleFlags) -> Cow<'_, str> {
+    let bytes = text.as_bytes();
+    let mut out = String::new();
+    // How much of `text` has been accounted for in `out`.
+    let mut copied = 0;
+    let mut offset = 0;
+    while let Some(found) = bytes[offset..].iter().position(|&c| c == b'?') {
+        let question_mark = offset + found;
+        let span_end = |is_name_byte: fn(u8) -> bool| {
+            question_mark
+                + bytes[question_mark..]
+                    .iter()
+                    .take_while(|&&c| is_name_byte(c))
+                    .count()
+        };
+        let end = span_end(is_name_byte);
+        offset = end;
+
+        let mut start = question_mark;
+        let mut import = false;
+        let mut type_name = false;
+        if text[..start].ends_with(IMPORT_PREFIX) {
+            start -= IMPORT_PREFIX.len();
+            import = true;
+        } else if text[..start].ends_with('.') && text[start..].starts_with("?A") {
+            start -= 1;
+            type_name = true;
+        }
+        if start > 0 && is_name_byte(bytes[start - 1]) {
+            continue;
+        }
+
+        let options = ParseOptions::default()
+            .with_strict(true)
+            .with_max_ast_nodes(Some(MAX_AST_NODES))
+            .with_max_output_len(Some(MAX_OUTPUT_LEN));
+        let demangle_to = |end: usize| {
+            if type_name {
+                demangle_type_with_options(&text[start..end], flags, options)
+            } else {
+                demangle_with_options(&text[question_mark..end], flags, options)
+            }
+        };
+        // Names like `<lambda_1>` and `<unnamed-tag>` need the longer
+        // span, but brackets and dashes usually belong to the text around
+        // a name, so they are only tried if the shorter span fails.
+        let long_end = span_end(is_long_name_byte);
+        let (end, demangled) = match demangle_to(end) {
+            Ok(demangled) => (end, demangled),
+            Err(_) if long_end > end => match demangle_to(long_end) {
+                Ok(demangled) => (long_end, demangled),
+                Err(_) => continue,
+            },
+            Err(_) => continue,
+        };
+        offset = end;
+
+        out.push_str(&text[copied..start]);
+        if import {
+            out.push_str("__declspec(dllimport) ");
+        }
+        out.push_str(&demangled);
+        copied = end;
+    }
+
+    if copied == 0 {
+        return Cow::Borrowed(text);
+    }
+    out.push_str(&text[copied..]);
+    Cow::Owned(out)
+}
+
+// The bytes that mangled names are made of.
+fn is_name_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'$' | b'?' | b'@')
+}
+
+// Also the bytes of the names the compiler makes up, such as
+// `<lambda_1>` and `<unnamed-tag>`.
+fn is_long_name_byte(c: u8) -> bool {
+    is_name_byte(c) || matches!(c, b'<' | b'>' | b'-')
+}
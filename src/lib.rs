@@ -18,6 +18,11 @@
 //! mangling scheme this is likely not to be entirely accurate.  When unclear
 //! the implementation tries to follow what LLVM does.
 //!
+//! Parsing and demangling never panic, whatever the input.  Malformed input
+//! is reported as an `Error`, and `ParseOptions` bounds the nesting depth so
+//! that hostile input cannot overflow the stack.  Inputs that used to
+//! trigger panics are kept in `tests/regressions/panics.txt`.
+//!
 //! # License
 //!
 //! This msvc-demangler is dual licensed under the MIT and the University of
@@ -36,15 +41,62 @@ use std::fmt;
 use std::io;
 use std::io::Write;
 use std::mem;
+use std::ops::Range;
 use std::result;
 use std::str;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
+mod builder;
+mod declaration;
+mod decorated;
+mod detect;
+mod filter;
+mod mangle;
+mod md5;
+pub mod visit;
+
+pub use builder::SymbolBuilder;
+pub use declaration::parse_declaration;
+pub use decorated::{parse_decorated, DecoratedName};
+pub use detect::{detect, is_msvc_mangled, MangledKind};
+pub use filter::demangle_in_text;
+pub use mangle::{mangle, mangle_bytes};
+pub use md5::{find_md5_source, is_md5_name, md5_mangled_name};
+pub use visit::{Visit, VisitMut};
+
 pub struct Error {
     repr: ErrorRepr,
+    origin: Option<Box<ParseOrigin>>,
+    diagnostic: Option<Box<Diagnostic>>,
+}
+
+// The input of a failed parse, for `Error::render_diagnostic`.  Only
+// allocated for parse errors, so successful parses do not pay for it.
+#[derive(Clone, Debug)]
+struct ParseOrigin {
+    input: Vec<u8>,
+    // How the mangled name parser was run when it failed, so it can parse
+    // the input again the same way to get back to the failure.  `None` for
+    // errors from the declaration and decorated name parsers.
+    options: Option<ParseOptions>,
+    type_only: bool,
+}
+
+// The parser state at the time of a parse error.  Only captured while
+// `Error::render_diagnostic` parses the input again, so failed parses
+// do not pay for it.
+#[derive(Debug)]
+struct Diagnostic {
+    productions: Vec<String>,
+    names: Vec<String>,
+    types: Vec<String>,
 }
 
+// Backreference table entries are cut off after this many bytes in
+// diagnostics.
+const MAX_DIAGNOSTIC_ENTRY_LEN: usize = 80;
+
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&self.repr, f)
@@ -56,27 +108,138 @@ pub enum ErrorRepr {
     FromUtf8(FromUtf8Error),
     Utf8(Utf8Error),
     Io(io::Error),
-    ParseError(Cow<'static, str>, String, usize),
+    ParseError(ErrorKind, String, usize),
+    SerializeError(ErrorKind),
     Other(String),
 }
 
+/// The reason a mangled name could not be parsed or printed.
+///
+/// More kinds may be added in minor releases.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The input ended in the middle of a mangled name.
+    UnexpectedEnd,
+    /// The input does not start like an MSVC mangled name.
+    NotMangled,
+    /// A complete mangled name was followed by more data (strict mode only).
+    TrailingData,
+    /// The byte does not encode a known calling convention.
+    UnknownCallingConvention(u8),
+    /// The byte does not encode a known function class.
+    UnknownFunctionClass(u8),
+    /// The byte does not encode a known storage class.
+    UnknownStorageClass(u8),
+    /// A type code that is not a known primitive or compound type.
+    UnknownPrimitiveType,
+    /// An unknown operator or special name.
+    UnknownOperator,
+    /// A backreference to a name or type that was never memorized.
+    InvalidBackreference { index: usize, available: usize },
+    /// A malformed encoded number.
+    BadNumber,
+    /// An array with a dimension that is not positive.
+    InvalidArrayDimension(i32),
+    /// A malformed string literal.
+    InvalidStringLiteral,
+    /// A required delimiter or marker is missing.
+    Expected(&'static str),
+    /// The input is structurally invalid in some other way.
+    Malformed(&'static str),
+    /// The input nests deeper than `ParseOptions::max_depth`.
+    DepthLimitExceeded(usize),
+    /// The AST would grow beyond `ParseOptions::max_ast_nodes`.
+    AstNodeLimitExceeded(usize),
+    /// The output would grow beyond `ParseOptions::max_output_len`.  This
+    /// comes from the serializer, so it has no offset.
+    OutputLimitExceeded(usize),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ErrorKind::NotMangled => write!(f, "does not start with b'?'"),
+            ErrorKind::TrailingData => write!(f, "trailing data"),
+            ErrorKind::UnknownCallingConvention(c) => {
+                write!(f, "unknown calling conv {:?}", char::from(c))
+            }
+            ErrorKind::UnknownFunctionClass(c) => {
+                write!(f, "unknown func class {:?}", char::from(c))
+            }
+            ErrorKind::UnknownStorageClass(c) => {
+                write!(f, "unknown storage class {:?}", char::from(c))
+            }
+            ErrorKind::UnknownPrimitiveType => write!(f, "unknown primitive type"),
+            ErrorKind::UnknownOperator => write!(f, "unknown operator name"),
+            ErrorKind::InvalidBackreference { index, available } => write!(
+                f,
+                "invalid backreference: {} ({} available)",
+                index, available
+            ),
+            ErrorKind::BadNumber => write!(f, "bad number"),
+            ErrorKind::InvalidArrayDimension(n) => write!(f, "invalid array dimension: {}", n),
+            ErrorKind::InvalidStringLiteral => write!(f, "invalid string literal"),
+            ErrorKind::Expected(s) => write!(f, "{} expected", s),
+            ErrorKind::Malformed(msg) => write!(f, "{}", msg),
+            ErrorKind::DepthLimitExceeded(max) => {
+                write!(f, "nesting depth exceeds the limit of {}", max)
+            }
+            ErrorKind::AstNodeLimitExceeded(max) => {
+                write!(f, "AST exceeds the limit of {} nodes", max)
+            }
+            ErrorKind::OutputLimitExceeded(max) => {
+                write!(f, "output exceeds the limit of {} bytes", max)
+            }
+        }
+    }
+}
+
 impl Error {
     /// Creates a simple error message.
     pub fn new<S: Into<String>>(s: S) -> Error {
         Error {
             repr: ErrorRepr::Other(s.into()),
+            origin: None,
+            diagnostic: None,
         }
     }
 
-    fn new_parse_error(s: Cow<'static, str>, input: &str, offset: usize) -> Error {
-        let context = Cow::Borrowed(input.as_bytes().get(offset..).unwrap_or(&[]));
+    fn new_parse_error(kind: ErrorKind, input: &[u8], offset: usize) -> Error {
+        let context = Cow::Borrowed(input.get(offset..).unwrap_or(&[]));
         let context = if context.len() > 20 {
             Cow::Owned(format!("{}...", String::from_utf8_lossy(&context[..20])))
         } else {
             String::from_utf8_lossy(&context)
         };
         Error {
-            repr: ErrorRepr::ParseError(s, context.to_string(), offset),
+            repr: ErrorRepr::ParseError(kind, context.to_string(), offset),
+            origin: Some(Box::new(ParseOrigin {
+                input: input.to_vec(),
+                options: None,
+                type_only: false,
+            })),
+            diagnostic: None,
+        }
+    }
+
+    // Creates an error for output that the serializer refused to write.
+    fn new_serialize_error(kind: ErrorKind) -> Error {
+        Error {
+            repr: ErrorRepr::SerializeError(kind),
+            origin: None,
+            diagnostic: None,
+        }
+    }
+
+    /// Returns the kind of parse or serializer error, if this is one.
+    pub fn kind(&self) -> Option<&ErrorKind> {
+        match self.repr {
+            ErrorRepr::ParseError(ref kind, _, _) | ErrorRepr::SerializeError(ref kind) => {
+                Some(kind)
+            }
+            _ => None,
         }
     }
 
@@ -87,12 +250,110 @@ impl Error {
             _ => None,
         }
     }
+
+    /// Renders a parse error the way rustc renders diagnostics.
+    ///
+    /// The output shows the input that failed with a caret under the
+    /// failing byte, followed by the grammar productions that were being
+    /// parsed and the backreference tables at that point.  Errors do not
+    /// keep the parser state, so this parses the input again to find it.
+    /// Errors that did not come from a parser render like their `Display`
+    /// output.  The alternate form of `Display` (`{:#}`) renders the same.
+    ///
+    /// ```
+    /// let err = msvc_demangler::parse("?f@@YAXV?$vector@H!@std@@@Z").unwrap_err();
+    /// let rendered = err.render_diagnostic();
+    /// assert!(rendered.contains("template arguments of `vector`"));
+    /// assert_eq!(format!("{:#}", err), rendered);
+    /// ```
+    pub fn render_diagnostic(&self) -> String {
+        let (kind, offset, origin) = match (self.kind(), self.offset(), self.origin.as_ref()) {
+            (Some(kind), Some(offset), Some(origin)) => (kind, offset, origin),
+            _ => return self.to_string(),
+        };
+        let input = &origin.input[..];
+        let column = String::from_utf8_lossy(input.get(..offset).unwrap_or(input))
+            .chars()
+            .count();
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                let items: Vec<_> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, item)| format!("{} = {}", idx, item))
+                    .collect();
+                items.join(", ")
+            }
+        };
+
+        let mut rv = format!("error: {}\n", kind);
+        rv.push_str(&format!(" --> offset {}\n", offset));
+        rv.push_str("  |\n");
+        rv.push_str(&format!("  | {}\n", String::from_utf8_lossy(input)));
+        rv.push_str(&format!("  | {}^", " ".repeat(column)));
+        let diagnostic = match self.reproduce(origin) {
+            Some(diagnostic) => diagnostic,
+            None => return rv,
+        };
+        if !diagnostic.productions.is_empty() {
+            rv.push_str(&format!(
+                "\n  = while reading {}",
+                diagnostic.productions.join(" \u{2192} ")
+            ));
+        }
+        rv.push_str(&format!(
+            "\n  = name backreferences: {}",
+            list(&diagnostic.names)
+        ));
+        rv.push_str(&format!(
+            "\n  = type backreferences: {}",
+            list(&diagnostic.types)
+        ));
+        rv
+    }
+
+    // Parses the input again the way it was parsed when this error
+    // happened and returns the parser state at the error.  Returns `None`
+    // if the error did not come from the mangled name parser or the parse
+    // does not fail in the same way.
+    fn reproduce(&self, origin: &ParseOrigin) -> Option<Box<Diagnostic>> {
+        let options = origin.options?;
+        let mut state = ParserState::new(&origin.input, options, false);
+        state.diagnose = true;
+        let rv = if origin.type_only {
+            state.parse_type().map(|_| ())
+        } else {
+            state.parse().map(|_| ())
+        };
+        let err = match rv {
+            Err(err) => err,
+            Ok(()) if !state.remaining.is_empty() => state.fail(ErrorKind::TrailingData),
+            Ok(()) => return None,
+        };
+        if err.kind() != self.kind() || err.offset() != self.offset() {
+            return None;
+        }
+        err.diagnostic
+    }
+
+    /// Returns the input that was left at the point of the error, cut
+    /// off after 20 bytes.
+    pub fn context(&self) -> Option<&str> {
+        match self.repr {
+            ErrorRepr::ParseError(_, ref context, _) => Some(context),
+            _ => None,
+        }
+    }
 }
 
 impl From<Utf8Error> for Error {
     fn from(err: Utf8Error) -> Error {
         Error {
             repr: ErrorRepr::Utf8(err),
+            origin: None,
+            diagnostic: None,
         }
     }
 }
@@ -101,6 +362,8 @@ impl From<FromUtf8Error> for Error {
     fn from(err: FromUtf8Error) -> Error {
         Error {
             repr: ErrorRepr::FromUtf8(err),
+            origin: None,
+            diagnostic: None,
         }
     }
 }
@@ -109,6 +372,8 @@ impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Error {
         Error {
             repr: ErrorRepr::Io(err),
+            origin: None,
+            diagnostic: None,
         }
     }
 }
@@ -116,10 +381,11 @@ impl From<std::io::Error> for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self.repr {
-            ErrorRepr::FromUtf8(ref e) => Some(&*e),
-            ErrorRepr::Utf8(ref e) => Some(&*e),
-            ErrorRepr::Io(ref e) => Some(&*e),
+            ErrorRepr::FromUtf8(ref e) => Some(e),
+            ErrorRepr::Utf8(ref e) => Some(e),
+            ErrorRepr::Io(ref e) => Some(e),
             ErrorRepr::ParseError(..) => None,
+            ErrorRepr::SerializeError(_) => None,
             ErrorRepr::Other(_) => None,
         }
     }
@@ -131,9 +397,13 @@ impl fmt::Display for Error {
             ErrorRepr::FromUtf8(ref e) => fmt::Display::fmt(e, f),
             ErrorRepr::Utf8(ref e) => fmt::Display::fmt(e, f),
             ErrorRepr::Io(ref e) => fmt::Display::fmt(e, f),
-            ErrorRepr::ParseError(ref msg, ref context, offset) => {
-                write!(f, "{} (offset: {}, remaining: {:?})", msg, offset, context)
+            ErrorRepr::ParseError(..) if f.alternate() => {
+                write!(f, "{}", self.render_diagnostic())
             }
+            ErrorRepr::ParseError(ref kind, ref context, offset) => {
+                write!(f, "{} (offset: {}, remaining: {:?})", kind, offset, context)
+            }
+            ErrorRepr::SerializeError(ref kind) => write!(f, "{}", kind),
             ErrorRepr::Other(ref msg) => write!(f, "{}", msg),
         }
     }
@@ -218,6 +488,94 @@ impl DemangleFlags {
     }
 }
 
+/// Options that control how the parser treats malformed input.
+///
+/// More options may be added in minor releases, so fill in the rest from
+/// `ParseOptions::default()`, either in a struct literal or with the
+/// `with_*` methods:
+///
+/// ```
+/// use msvc_demangler::ParseOptions;
+///
+/// let options = ParseOptions {
+///     strict: true,
+///     ..ParseOptions::default()
+/// };
+/// assert_eq!(options, ParseOptions::default().with_strict(true));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseOptions {
+    /// Reject everything that is not a complete, well-formed mangled name.
+    ///
+    /// By default the parser is lenient: it ignores trailing data, allows
+    /// names without a type encoding and accepts a few other malformed
+    /// constructs that show up in the wild.  In strict mode a successful
+    /// parse means the whole input was understood.
+    pub strict: bool,
+    /// How deeply types, templates and nested symbols may nest.
+    ///
+    /// The parser is recursive, so without a limit deeply nested input can
+    /// overflow the stack.  Defaults to `DEFAULT_MAX_DEPTH`.
+    pub max_depth: Option<usize>,
+    /// The maximum number of nodes in the parsed AST.
+    ///
+    /// Backreferences copy the referenced type or name, and every level
+    /// of nested templates can double the size again, so a short input can
+    /// expand into a huge AST.  Defaults to `DEFAULT_MAX_AST_NODES`.
+    pub max_ast_nodes: Option<usize>,
+    /// The maximum length of the demangled output, in bytes.  Only the
+    /// `demangle` functions look at this.  Defaults to
+    /// `DEFAULT_MAX_OUTPUT_LEN`.
+    pub max_output_len: Option<usize>,
+}
+
+/// The nesting depth `ParseOptions` allows by default.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// The number of AST nodes `ParseOptions` allows by default.  Real names
+/// stay far below this.
+pub const DEFAULT_MAX_AST_NODES: usize = 100_000;
+
+/// The length of demangled output `ParseOptions` allows by default.
+pub const DEFAULT_MAX_OUTPUT_LEN: usize = 1 << 20;
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            strict: false,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_ast_nodes: Some(DEFAULT_MAX_AST_NODES),
+            max_output_len: Some(DEFAULT_MAX_OUTPUT_LEN),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Sets `strict`.
+    pub fn with_strict(mut self, strict: bool) -> ParseOptions {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets `max_depth`.
+    pub fn with_max_depth(mut self, max_depth: Option<usize>) -> ParseOptions {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets `max_ast_nodes`.
+    pub fn with_max_ast_nodes(mut self, max_ast_nodes: Option<usize>) -> ParseOptions {
+        self.max_ast_nodes = max_ast_nodes;
+        self
+    }
+
+    /// Sets `max_output_len`.
+    pub fn with_max_output_len(mut self, max_output_len: Option<usize>) -> ParseOptions {
+        self.max_output_len = max_output_len;
+        self
+    }
+}
+
 // Calling conventions
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CallingConv {
@@ -226,6 +584,7 @@ pub enum CallingConv {
     Thiscall,
     Stdcall,
     Fastcall,
+    Vectorcall,
     _Regcall,
 }
 
@@ -252,22 +611,77 @@ pub enum VarStorageKind {
     FunctionLocalStatic,
 }
 
+/// What kind of entity a symbol names.  See `ParseResult::kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// A free function, or a function in a namespace.
+    Function,
+    /// A non-static, non-virtual member function.
+    Method,
+    /// A static member function.
+    StaticMethod,
+    /// A virtual member function.
+    VirtualMethod,
+    /// A global, static member or function-local static variable.
+    Variable,
+    /// A virtual function table.
+    VTable,
+    /// A virtual base table.
+    VBTable,
+    /// RTTI data such as a type or class hierarchy descriptor.
+    RTTI,
+    /// An adjustor or vcall thunk.
+    Thunk,
+    /// A string literal.
+    StringLiteral,
+    /// The guard variable of a function-local static.
+    Guard,
+    /// Anything else, such as `extern "C"` names that carry no type.
+    Other,
+}
+
+/// The access specifier of a class member.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    Private,
+    Protected,
+    Public,
+}
+
+// Marks where template parameter packs appear in a template argument list.
+// Non-empty packs are expanded in place, so these markers are the only
+// record of pack boundaries in the mangled name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackMarker {
+    /// `$$V`: an empty type or template pack (MSVC 2015 and later).
+    EmptyTypePack,
+    /// `$$$V`: an empty type or template pack (before MSVC 2015).
+    EmptyTypePackLegacy,
+    /// `$S`: an empty non-type pack.
+    EmptyNonTypePack,
+    /// `$$Z`: separates two adjacent pack expansions.
+    Separator,
+}
+
 // Represents an identifier which may be a template.
 #[derive(Clone, PartialEq)]
 pub enum Name<'a> {
     Operator(Operator<'a>),
-    NonTemplate(&'a [u8]),
+    NonTemplate(Cow<'a, [u8]>),
     Template(Box<Name<'a>>, Params<'a>),
     Discriminator(i32),
     ParsedName(Box<ParseResult<'a>>),
     AnonymousNamespace(Option<String>),
+
+    // A name that could not be parsed by `parse_partial`.
+    Unknown,
 }
 
 impl<'a> fmt::Debug for Name<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Name::Operator(ref op) => f.debug_tuple("Operator").field(&op).finish(),
-            Name::NonTemplate(s) => f
+            Name::NonTemplate(ref s) => f
                 .debug_tuple("NonTemplate")
                 .field(&String::from_utf8_lossy(s))
                 .finish(),
@@ -279,6 +693,24 @@ impl<'a> fmt::Debug for Name<'a> {
             Name::AnonymousNamespace(ref name) => {
                 f.debug_tuple("AnonymousNamespace").field(name).finish()
             }
+            Name::Unknown => f.write_str("Unknown"),
+        }
+    }
+}
+
+impl<'a> Name<'a> {
+    /// Copies all borrowed data so the name no longer borrows the input.
+    pub fn into_owned(self) -> Name<'static> {
+        match self {
+            Name::Operator(op) => Name::Operator(op.into_owned()),
+            Name::NonTemplate(s) => Name::NonTemplate(Cow::Owned(s.into_owned())),
+            Name::Template(name, params) => {
+                Name::Template(Box::new(name.into_owned()), params.into_owned())
+            }
+            Name::Discriminator(i) => Name::Discriminator(i),
+            Name::ParsedName(parsed) => Name::ParsedName(Box::new(parsed.into_owned())),
+            Name::AnonymousNamespace(id) => Name::AnonymousNamespace(id),
+            Name::Unknown => Name::Unknown,
         }
     }
 }
@@ -369,57 +801,269 @@ pub enum Operator<'a> {
     LocalStaticThreadGuard(Option<u32>),
 }
 
+impl<'a> Operator<'a> {
+    /// Copies all borrowed data so the operator no longer borrows the input.
+    pub fn into_owned(self) -> Operator<'static> {
+        match self {
+            Operator::Ctor => Operator::Ctor,
+            Operator::Dtor => Operator::Dtor,
+            Operator::New => Operator::New,
+            Operator::Delete => Operator::Delete,
+            Operator::Equal => Operator::Equal,
+            Operator::RShift => Operator::RShift,
+            Operator::LShift => Operator::LShift,
+            Operator::Bang => Operator::Bang,
+            Operator::EqualEqual => Operator::EqualEqual,
+            Operator::BangEqual => Operator::BangEqual,
+            Operator::Subscript => Operator::Subscript,
+            Operator::Conversion => Operator::Conversion,
+            Operator::Arrow => Operator::Arrow,
+            Operator::Star => Operator::Star,
+            Operator::PlusPlus => Operator::PlusPlus,
+            Operator::MinusMinus => Operator::MinusMinus,
+            Operator::Minus => Operator::Minus,
+            Operator::Plus => Operator::Plus,
+            Operator::Amp => Operator::Amp,
+            Operator::ArrowStar => Operator::ArrowStar,
+            Operator::Slash => Operator::Slash,
+            Operator::Percent => Operator::Percent,
+            Operator::Less => Operator::Less,
+            Operator::LessEqual => Operator::LessEqual,
+            Operator::Greater => Operator::Greater,
+            Operator::GreaterEqual => Operator::GreaterEqual,
+            Operator::Comma => Operator::Comma,
+            Operator::Call => Operator::Call,
+            Operator::Tilde => Operator::Tilde,
+            Operator::Caret => Operator::Caret,
+            Operator::Pipe => Operator::Pipe,
+            Operator::AmpAmp => Operator::AmpAmp,
+            Operator::PipePipe => Operator::PipePipe,
+            Operator::StarEqual => Operator::StarEqual,
+            Operator::PlusEqual => Operator::PlusEqual,
+            Operator::MinusEqual => Operator::MinusEqual,
+            Operator::SlashEqual => Operator::SlashEqual,
+            Operator::PercentEqual => Operator::PercentEqual,
+            Operator::GreaterGreaterEqual => Operator::GreaterGreaterEqual,
+            Operator::LessLessEqual => Operator::LessLessEqual,
+            Operator::AmpEqual => Operator::AmpEqual,
+            Operator::PipeEqual => Operator::PipeEqual,
+            Operator::CaretEqual => Operator::CaretEqual,
+            Operator::VFTable => Operator::VFTable,
+            Operator::VBTable => Operator::VBTable,
+            Operator::VCall => Operator::VCall,
+            Operator::Typeof => Operator::Typeof,
+            Operator::LocalStaticGuard(scope) => Operator::LocalStaticGuard(scope),
+            Operator::String => Operator::String,
+            Operator::VBaseDtor => Operator::VBaseDtor,
+            Operator::VectorDeletingDtor => Operator::VectorDeletingDtor,
+            Operator::DefaultCtorClosure => Operator::DefaultCtorClosure,
+            Operator::ScalarDeletingDtor => Operator::ScalarDeletingDtor,
+            Operator::VectorCtorIterator => Operator::VectorCtorIterator,
+            Operator::VectorDtorIterator => Operator::VectorDtorIterator,
+            Operator::VectorVBaseCtorIterator => Operator::VectorVBaseCtorIterator,
+            Operator::VirtualDisplacementMap => Operator::VirtualDisplacementMap,
+            Operator::EHVectorCtorIterator => Operator::EHVectorCtorIterator,
+            Operator::EHVectorDtorIterator => Operator::EHVectorDtorIterator,
+            Operator::EHVectorVBaseCtorIterator => Operator::EHVectorVBaseCtorIterator,
+            Operator::CopyCtorClosure => Operator::CopyCtorClosure,
+            Operator::LocalVFTable => Operator::LocalVFTable,
+            Operator::LocalVFTableCtorClosure => Operator::LocalVFTableCtorClosure,
+            Operator::ArrayNew => Operator::ArrayNew,
+            Operator::ArrayDelete => Operator::ArrayDelete,
+            Operator::PlacementDeleteClosure => Operator::PlacementDeleteClosure,
+            Operator::PlacementArrayDeleteClosure => Operator::PlacementArrayDeleteClosure,
+            Operator::CoroutineAwait => Operator::CoroutineAwait,
+            Operator::LiteralOperatorName => Operator::LiteralOperatorName,
+            Operator::RTTITypeDescriptor(sc, t) => {
+                Operator::RTTITypeDescriptor(sc, Box::new(t.into_owned()))
+            }
+            Operator::RTTIBaseClassDescriptor(nv_offset, vbptr_offset, vbtable_offset, flags) => {
+                Operator::RTTIBaseClassDescriptor(nv_offset, vbptr_offset, vbtable_offset, flags)
+            }
+            Operator::RTTIBaseClassArray => Operator::RTTIBaseClassArray,
+            Operator::RTTIClassHierarchyDescriptor => Operator::RTTIClassHierarchyDescriptor,
+            Operator::RTTIClassCompleteObjectLocator => Operator::RTTIClassCompleteObjectLocator,
+            Operator::DynamicInitializer => Operator::DynamicInitializer,
+            Operator::DynamicAtexitDtor => Operator::DynamicAtexitDtor,
+            Operator::LocalStaticThreadGuard(scope) => Operator::LocalStaticThreadGuard(scope),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct NameSequence<'a> {
     pub names: Vec<Name<'a>>,
 }
 
+impl<'a> NameSequence<'a> {
+    /// Copies all borrowed data so the names no longer borrow the input.
+    pub fn into_owned(self) -> NameSequence<'static> {
+        NameSequence {
+            names: self.names.into_iter().map(Name::into_owned).collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Params<'a> {
     pub types: Vec<Type<'a>>,
 }
 
+impl<'a> Params<'a> {
+    /// Copies all borrowed data so the types no longer borrow the input.
+    pub fn into_owned(self) -> Params<'static> {
+        Params {
+            types: self.types.into_iter().map(Type::into_owned).collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Symbol<'a> {
     pub name: Name<'a>,
     pub scope: NameSequence<'a>,
 }
 
+impl<'a> Symbol<'a> {
+    /// Copies all borrowed data so the symbol no longer borrows the input.
+    pub fn into_owned(self) -> Symbol<'static> {
+        Symbol {
+            name: self.name.into_owned(),
+            scope: self.scope.into_owned(),
+        }
+    }
+}
+
+/// The signature of a function, shared by member functions, non-member
+/// functions and pointers to member functions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionSignature<'a> {
+    pub calling_conv: CallingConv,
+    pub params: Params<'a>,
+    /// The qualifiers of the implicit `this` pointer, such as `const` or
+    /// `&&`.  Empty for non-member and static functions.
+    pub this_quals: StorageClass,
+    /// `Type::None` for constructors and destructors.
+    pub return_type: Box<Type<'a>>,
+    pub noexcept: bool,
+    /// The offset that an adjustor thunk adds to `this`.  Zero for
+    /// functions that are not thunks.
+    pub this_adjustment: i32,
+}
+
+impl<'a> FunctionSignature<'a> {
+    /// Copies all borrowed data so the signature no longer borrows the
+    /// input.
+    pub fn into_owned(self) -> FunctionSignature<'static> {
+        FunctionSignature {
+            calling_conv: self.calling_conv,
+            params: self.params.into_owned(),
+            this_quals: self.this_quals,
+            return_type: Box::new(self.return_type.into_owned()),
+            noexcept: self.noexcept,
+            this_adjustment: self.this_adjustment,
+        }
+    }
+}
+
+/// A string literal (`??_C@`).  Only the start of the string is part of
+/// the mangled name; the length and checksum cover the whole string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StringLiteral {
+    /// The size of a character in bytes: 1 for `char` and 2 for wide
+    /// strings.
+    pub char_bytes: i32,
+    /// The size of the whole string in bytes, including the terminator.
+    pub byte_length: i32,
+    /// The JamCRC checksum of the whole string.
+    pub crc: u32,
+    /// The first 32 characters of the string, as raw bytes.  Wide
+    /// characters are stored high byte first.
+    pub bytes: Vec<u8>,
+}
+
+impl StringLiteral {
+    /// The literal of the `char` string `s`, which does not include the
+    /// terminator.
+    pub fn new(s: &[u8]) -> StringLiteral {
+        let mut data = s.to_vec();
+        data.push(0);
+        StringLiteral::from_bytes(1, &data, &data)
+    }
+
+    /// The literal of the `wchar_t` string `s`, which does not include the
+    /// terminator.
+    pub fn wide(s: &[u16]) -> StringLiteral {
+        // The checksum covers the little-endian bytes, while the name
+        // spells out each character high byte first.
+        let mut little_endian = Vec::with_capacity(s.len() * 2 + 2);
+        let mut big_endian = Vec::with_capacity(s.len() * 2 + 2);
+        for &c in s.iter().chain(&[0]) {
+            little_endian.extend_from_slice(&c.to_le_bytes());
+            big_endian.extend_from_slice(&c.to_be_bytes());
+        }
+        StringLiteral::from_bytes(2, &little_endian, &big_endian)
+    }
+
+    fn from_bytes(char_bytes: i32, checksummed: &[u8], spelled: &[u8]) -> StringLiteral {
+        let len = min(spelled.len(), char_bytes as usize * 32);
+        StringLiteral {
+            char_bytes,
+            byte_length: checksummed.len() as i32,
+            crc: jam_crc(checksummed),
+            bytes: spelled[..len].to_vec(),
+        }
+    }
+
+    /// The mangled name of the literal, such as `??_C@_03FIKCJHKP@abc?$AA@` for `"abc"`.
+    pub fn mangled_name(&self) -> String {
+        mangle::mangle_string_literal(self)
+    }
+}
+
+// The CRC-32 of `data` without the final inversion, which MSVC uses to
+// tell string literals with the same start apart.
+fn jam_crc(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
 // The type class. Mangled symbols are first parsed and converted to
 // this type and then converted to string.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Type<'a> {
     None,
-    MemberFunction(
-        FuncClass,
-        CallingConv,
-        Params<'a>,
-        StorageClass,
-        Box<Type<'a>>,
-    ), // StorageClass is for the 'this' pointer
-    MemberFunctionPointer(
-        Symbol<'a>,
-        FuncClass,
-        CallingConv,
-        Params<'a>,
-        StorageClass,
-        Box<Type<'a>>,
-    ),
-    NonMemberFunction(CallingConv, Params<'a>, StorageClass, Box<Type<'a>>),
+    MemberFunction(FuncClass, FunctionSignature<'a>),
+    MemberFunctionPointer(Symbol<'a>, FuncClass, FunctionSignature<'a>),
+    // A pointer to a data member of a class: the class, the member type
+    // and the qualifiers of the pointer.
+    MemberDataPointer(Symbol<'a>, Box<Type<'a>>, StorageClass),
+    NonMemberFunction(FunctionSignature<'a>),
     CXXVBTable(NameSequence<'a>, StorageClass),
     CXXVFTable(NameSequence<'a>, StorageClass),
     VCallThunk(i32, CallingConv),
     TemplateParameterWithIndex(i32),
     ThreadSafeStaticGuard(i32),
     Constant(i32),
-    ConstantString(Vec<u8>),
+    ConstantString(StringLiteral),
     Ptr(Box<Type<'a>>, StorageClass),
     Ref(Box<Type<'a>>, StorageClass),
     RValueRef(Box<Type<'a>>, StorageClass),
     Array(i32, Box<Type<'a>>, StorageClass),
     Var(Box<Type<'a>>, VarStorageKind, StorageClass),
 
-    Alias(Symbol<'a>, StorageClass),
+    // A template passed as a template template argument (`$$Y`).
+    TemplateTemplate(Symbol<'a>),
     Struct(Symbol<'a>, StorageClass),
     Union(Symbol<'a>, StorageClass),
     Class(Symbol<'a>, StorageClass),
@@ -448,9 +1092,123 @@ pub enum Type<'a> {
     Double(StorageClass),
     Ldouble(StorageClass),
     VarArgs,
-    EmptyParameterPack,
+    ParameterPack(PackMarker),
     Nullptr,
     RTTIType,
+
+    // A type that could not be parsed by `parse_partial`.
+    Unknown,
+}
+
+impl<'a> Type<'a> {
+    /// Copies all borrowed data so the type no longer borrows the input.
+    pub fn into_owned(self) -> Type<'static> {
+        match self {
+            Type::None => Type::None,
+            Type::MemberFunction(func_class, sig) => {
+                Type::MemberFunction(func_class, sig.into_owned())
+            }
+            Type::MemberFunctionPointer(symbol, func_class, sig) => {
+                Type::MemberFunctionPointer(symbol.into_owned(), func_class, sig.into_owned())
+            }
+            Type::MemberDataPointer(symbol, t, sc) => {
+                Type::MemberDataPointer(symbol.into_owned(), Box::new(t.into_owned()), sc)
+            }
+            Type::NonMemberFunction(sig) => Type::NonMemberFunction(sig.into_owned()),
+            Type::CXXVBTable(scope, sc) => Type::CXXVBTable(scope.into_owned(), sc),
+            Type::CXXVFTable(scope, sc) => Type::CXXVFTable(scope.into_owned(), sc),
+            Type::VCallThunk(offset, calling_conv) => Type::VCallThunk(offset, calling_conv),
+            Type::TemplateParameterWithIndex(n) => Type::TemplateParameterWithIndex(n),
+            Type::ThreadSafeStaticGuard(n) => Type::ThreadSafeStaticGuard(n),
+            Type::Constant(n) => Type::Constant(n),
+            Type::ConstantString(s) => Type::ConstantString(s),
+            Type::Ptr(t, sc) => Type::Ptr(Box::new(t.into_owned()), sc),
+            Type::Ref(t, sc) => Type::Ref(Box::new(t.into_owned()), sc),
+            Type::RValueRef(t, sc) => Type::RValueRef(Box::new(t.into_owned()), sc),
+            Type::Array(len, t, sc) => Type::Array(len, Box::new(t.into_owned()), sc),
+            Type::Var(t, kind, sc) => Type::Var(Box::new(t.into_owned()), kind, sc),
+            Type::TemplateTemplate(symbol) => Type::TemplateTemplate(symbol.into_owned()),
+            Type::Struct(symbol, sc) => Type::Struct(symbol.into_owned(), sc),
+            Type::Union(symbol, sc) => Type::Union(symbol.into_owned(), sc),
+            Type::Class(symbol, sc) => Type::Class(symbol.into_owned(), sc),
+            Type::Enum(symbol, sc) => Type::Enum(symbol.into_owned(), sc),
+            Type::Void(sc) => Type::Void(sc),
+            Type::Bool(sc) => Type::Bool(sc),
+            Type::Char(sc) => Type::Char(sc),
+            Type::Schar(sc) => Type::Schar(sc),
+            Type::Uchar(sc) => Type::Uchar(sc),
+            Type::Short(sc) => Type::Short(sc),
+            Type::Ushort(sc) => Type::Ushort(sc),
+            Type::Int(sc) => Type::Int(sc),
+            Type::Uint(sc) => Type::Uint(sc),
+            Type::Long(sc) => Type::Long(sc),
+            Type::Ulong(sc) => Type::Ulong(sc),
+            Type::Int64(sc) => Type::Int64(sc),
+            Type::Uint64(sc) => Type::Uint64(sc),
+            Type::Int128(sc) => Type::Int128(sc),
+            Type::Uint128(sc) => Type::Uint128(sc),
+            Type::Wchar(sc) => Type::Wchar(sc),
+            Type::Char8(sc) => Type::Char8(sc),
+            Type::Char16(sc) => Type::Char16(sc),
+            Type::Char32(sc) => Type::Char32(sc),
+            Type::Float(sc) => Type::Float(sc),
+            Type::Double(sc) => Type::Double(sc),
+            Type::Ldouble(sc) => Type::Ldouble(sc),
+            Type::VarArgs => Type::VarArgs,
+            Type::ParameterPack(marker) => Type::ParameterPack(marker),
+            Type::Nullptr => Type::Nullptr,
+            Type::RTTIType => Type::RTTIType,
+            Type::Unknown => Type::Unknown,
+        }
+    }
+
+    // The qualifiers of the types that have them.
+    fn storage_class_mut(&mut self) -> Option<&mut StorageClass> {
+        match *self {
+            Type::Enum(_, ref mut sc)
+            | Type::Class(_, ref mut sc)
+            | Type::Struct(_, ref mut sc)
+            | Type::Union(_, ref mut sc)
+            | Type::Ptr(_, ref mut sc)
+            | Type::MemberDataPointer(_, _, ref mut sc)
+            | Type::Ref(_, ref mut sc)
+            | Type::RValueRef(_, ref mut sc)
+            | Type::Void(ref mut sc)
+            | Type::Bool(ref mut sc)
+            | Type::Char(ref mut sc)
+            | Type::Schar(ref mut sc)
+            | Type::Uchar(ref mut sc)
+            | Type::Short(ref mut sc)
+            | Type::Ushort(ref mut sc)
+            | Type::Int(ref mut sc)
+            | Type::Uint(ref mut sc)
+            | Type::Long(ref mut sc)
+            | Type::Ulong(ref mut sc)
+            | Type::Int64(ref mut sc)
+            | Type::Uint64(ref mut sc)
+            | Type::Int128(ref mut sc)
+            | Type::Uint128(ref mut sc)
+            | Type::Wchar(ref mut sc)
+            | Type::Char8(ref mut sc)
+            | Type::Char16(ref mut sc)
+            | Type::Char32(ref mut sc)
+            | Type::Float(ref mut sc)
+            | Type::Double(ref mut sc)
+            | Type::Ldouble(ref mut sc) => Some(sc),
+            _ => None,
+        }
+    }
+
+    /// Returns the signature of a member, non-member or pointer to member
+    /// function type.
+    pub fn signature(&self) -> Option<&FunctionSignature<'a>> {
+        match *self {
+            Type::MemberFunction(_, ref sig)
+            | Type::MemberFunctionPointer(_, _, ref sig)
+            | Type::NonMemberFunction(ref sig) => Some(sig),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -459,6 +1217,317 @@ pub struct ParseResult<'a> {
     pub symbol_type: Type<'a>,
 }
 
+impl<'a> ParseResult<'a> {
+    /// Copies all borrowed data so the result can outlive the input.
+    pub fn into_owned(self) -> ParseResult<'static> {
+        ParseResult {
+            symbol: self.symbol.into_owned(),
+            symbol_type: self.symbol_type.into_owned(),
+        }
+    }
+
+    /// Returns what kind of entity the symbol names.
+    pub fn kind(&self) -> SymbolKind {
+        // RTTI data and guards use a variety of encodings, so go by name.
+        match self.symbol.name {
+            Name::Operator(Operator::LocalStaticGuard(_))
+            | Name::Operator(Operator::LocalStaticThreadGuard(_)) => return SymbolKind::Guard,
+            Name::Operator(Operator::RTTITypeDescriptor(..))
+            | Name::Operator(Operator::RTTIBaseClassDescriptor(..))
+            | Name::Operator(Operator::RTTIBaseClassArray)
+            | Name::Operator(Operator::RTTIClassHierarchyDescriptor)
+            | Name::Operator(Operator::RTTIClassCompleteObjectLocator) => return SymbolKind::RTTI,
+            _ => {}
+        }
+        match self.symbol_type {
+            Type::MemberFunction(func_class, ..) => {
+                if func_class.contains(FuncClass::THUNK) {
+                    SymbolKind::Thunk
+                } else if func_class.contains(FuncClass::GLOBAL) {
+                    SymbolKind::Function
+                } else if func_class.contains(FuncClass::STATIC) {
+                    SymbolKind::StaticMethod
+                } else if func_class.contains(FuncClass::VIRTUAL) {
+                    SymbolKind::VirtualMethod
+                } else {
+                    SymbolKind::Method
+                }
+            }
+            Type::NonMemberFunction(..) => SymbolKind::Function,
+            Type::VCallThunk(..) => SymbolKind::Thunk,
+            Type::CXXVFTable(..) => SymbolKind::VTable,
+            Type::CXXVBTable(..) => SymbolKind::VBTable,
+            Type::RTTIType => SymbolKind::RTTI,
+            Type::ConstantString(_) => SymbolKind::StringLiteral,
+            Type::ThreadSafeStaticGuard(_) => SymbolKind::Guard,
+            Type::Var(..) => SymbolKind::Variable,
+            _ => SymbolKind::Other,
+        }
+    }
+
+    /// Returns the signature if the symbol is a function.
+    pub fn signature(&self) -> Option<&FunctionSignature<'a>> {
+        self.symbol_type.signature()
+    }
+
+    /// Returns the name including its scope, such as `ns::Foo::bar`.
+    pub fn qualified_name(&self) -> Result<String> {
+        serialize(self, DemangleFlags::llvm() | DemangleFlags::NAME_ONLY)
+    }
+
+    // The number of scope names that belong to the enclosing class.  The
+    // mangling does not distinguish classes from namespaces, so this is
+    // inferred from the kind of symbol.  The innermost scope of a member is
+    // its class.  Outer scopes are enclosing classes if they are templates
+    // or are used as class types elsewhere in the symbol.
+    fn class_scope_len(&self) -> usize {
+        let is_member = match self.symbol_type {
+            Type::MemberFunction(func_class, ..) => !func_class.contains(FuncClass::GLOBAL),
+            Type::Var(_, kind, _) => match kind {
+                VarStorageKind::PrivateStatic
+                | VarStorageKind::ProtectedStatic
+                | VarStorageKind::PublicStatic => true,
+                VarStorageKind::Global | VarStorageKind::FunctionLocalStatic => false,
+            },
+            Type::VCallThunk(..) | Type::CXXVFTable(..) | Type::CXXVBTable(..) => true,
+            Type::RTTIType => !matches!(
+                self.symbol.name,
+                Name::Operator(Operator::RTTITypeDescriptor(..))
+            ),
+            _ => false,
+        };
+        let names = &self.symbol.scope.names;
+        if !is_member || names.is_empty() {
+            return 0;
+        }
+
+        let mut class_types = ClassTypes(Vec::new());
+        class_types.visit_parse_result(self);
+        let is_class = |i: usize| {
+            matches!(names[i], Name::Template(..))
+                || class_types
+                    .0
+                    .iter()
+                    .any(|class| class.name == names[i] && class.scope.names[..] == names[i + 1..])
+        };
+        1 + (1..names.len()).take_while(|&i| is_class(i)).count()
+    }
+
+    /// Returns the namespace the symbol is declared in, if any.
+    ///
+    /// Scopes that cannot be told apart from namespaces are included, see
+    /// `class_name`.
+    pub fn namespace(&self) -> Result<Option<String>> {
+        let names = &self.symbol.scope.names[self.class_scope_len()..];
+        if names.is_empty() {
+            return Ok(None);
+        }
+        scope_to_string(names).map(Some)
+    }
+
+    /// Returns the name of the class the symbol is a member of, if any,
+    /// such as `Outer::Inner` for a nested class.
+    ///
+    /// The mangling does not say whether a scope is a class or a namespace.
+    /// An outer scope only counts as an enclosing class if it is a
+    /// template, or if the symbol uses it as a class type elsewhere, for
+    /// example in a parameter.
+    pub fn class_name(&self) -> Result<Option<String>> {
+        let len = self.class_scope_len();
+        if len == 0 {
+            return Ok(None);
+        }
+        scope_to_string(&self.symbol.scope.names[..len]).map(Some)
+    }
+
+    /// Returns the parameters of a function.  `(void)` yields no
+    /// parameters.
+    pub fn parameters(&self) -> Option<&[Type<'a>]> {
+        match self.signature()?.params.types[..] {
+            [Type::Void(sc)] if sc.is_empty() => Some(&[]),
+            ref types => Some(types),
+        }
+    }
+
+    /// Returns the return type of a function.  Constructors and
+    /// destructors have none.
+    pub fn return_type(&self) -> Option<&Type<'a>> {
+        match *self.signature()?.return_type {
+            Type::None => None,
+            ref rv => Some(rv),
+        }
+    }
+
+    /// Returns the calling convention of a function or thunk.
+    pub fn calling_convention(&self) -> Option<CallingConv> {
+        match self.symbol_type {
+            Type::VCallThunk(_, calling_conv) => Some(calling_conv),
+            _ => self.signature().map(|sig| sig.calling_conv),
+        }
+    }
+
+    /// Returns the access specifier of a class member.
+    pub fn access(&self) -> Option<Access> {
+        match self.symbol_type {
+            Type::MemberFunction(func_class, ..) => {
+                if func_class.contains(FuncClass::PRIVATE) {
+                    Some(Access::Private)
+                } else if func_class.contains(FuncClass::PROTECTED) {
+                    Some(Access::Protected)
+                } else if func_class.contains(FuncClass::PUBLIC) {
+                    Some(Access::Public)
+                } else {
+                    None
+                }
+            }
+            Type::Var(_, VarStorageKind::PrivateStatic, _) => Some(Access::Private),
+            Type::Var(_, VarStorageKind::ProtectedStatic, _) => Some(Access::Protected),
+            Type::Var(_, VarStorageKind::PublicStatic, _) => Some(Access::Public),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this is a member function with a `const` `this`.
+    pub fn is_const_method(&self) -> bool {
+        match self.symbol_type {
+            Type::MemberFunction(_, ref sig) => sig.this_quals.contains(StorageClass::CONST),
+            _ => false,
+        }
+    }
+
+    /// Returns the template arguments if the symbol is a template
+    /// specialization.
+    pub fn template_args(&self) -> Option<&[Type<'a>]> {
+        match self.symbol.name {
+            Name::Template(_, ref params) => Some(&params.types),
+            _ => None,
+        }
+    }
+}
+
+/// Formats the full declaration using `DemangleFlags::llvm()`.  The
+/// alternate form (`{:#}`) formats only the name.
+impl<'a> fmt::Display for ParseResult<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_demangled(f, self, DemangleFlags::llvm(), "<?>")
+    }
+}
+
+/// A demangled symbol that is formatted on demand.
+///
+/// Returned by `try_demangle`.  Formatting with `{}` gives the full
+/// declaration and `{:#}` only the name.  If the name cannot be written
+/// with the chosen flags, the mangled input is written instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Demangle<'a> {
+    input: &'a str,
+    parsed: ParseResult<'a>,
+    flags: DemangleFlags,
+}
+
+impl<'a> Demangle<'a> {
+    /// Formats with `flags` instead of `DemangleFlags::llvm()`.
+    pub fn with_flags(mut self, flags: DemangleFlags) -> Demangle<'a> {
+        self.flags = flags;
+        self
+    }
+
+    /// Returns the parsed symbol.
+    pub fn parse_result(&self) -> &ParseResult<'a> {
+        &self.parsed
+    }
+}
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_demangled(f, &self.parsed, self.flags, self.input)
+    }
+}
+
+// `fmt::Error` means the formatter failed, so a name that cannot be
+// serialized is replaced by `fallback` rather than reported.  A dry run
+// into `io::sink()` finds such names before anything is written, so the
+// output goes straight to the formatter.
+fn fmt_demangled(
+    f: &mut fmt::Formatter,
+    parsed: &ParseResult,
+    flags: DemangleFlags,
+    fallback: &str,
+) -> fmt::Result {
+    let flags = if f.alternate() {
+        flags | DemangleFlags::NAME_ONLY
+    } else {
+        flags
+    };
+    if write_demangled(io::sink(), parsed, flags).is_err() {
+        return f.write_str(fallback);
+    }
+    Serializer::new(FmtWriter(f), flags, None)
+        .serialize(parsed)
+        .map_err(|_| fmt::Error)
+}
+
+/// The part of the input an AST node was parsed from.
+///
+/// Returned by `parse_with_spans`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub kind: SpanKind,
+    /// Byte offsets into the input.
+    pub range: Range<usize>,
+    /// Set if the node was given as a backreference.
+    pub backreference: Option<Backreference>,
+}
+
+/// The kind of AST node a `Span` belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpanKind {
+    Symbol,
+    Name,
+    Type,
+}
+
+/// A backreference, such as the `0` in `?f@@YAXPAH0@Z`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backreference {
+    /// The index the backreference resolved to.
+    pub index: usize,
+    /// Where the referenced name or type was first spelled out.
+    pub target: Range<usize>,
+}
+
+// A grammar production the parser is in the middle of.  These are only
+// used to explain where a parse error happened.
+#[derive(Clone, Copy, Debug)]
+enum Production<'a> {
+    NestedSymbol,
+    Scope,
+    TemplateArgs(Option<&'a [u8]>),
+    FunctionParams,
+    ReturnType,
+    VariableType,
+    StringLiteral,
+}
+
+impl<'a> fmt::Display for Production<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Production::NestedSymbol => write!(f, "nested symbol"),
+            Production::Scope => write!(f, "scope"),
+            Production::TemplateArgs(Some(name)) => write!(
+                f,
+                "template arguments of `{}`",
+                String::from_utf8_lossy(name)
+            ),
+            Production::TemplateArgs(None) => write!(f, "template arguments"),
+            Production::FunctionParams => write!(f, "function parameters"),
+            Production::ReturnType => write!(f, "return type"),
+            Production::VariableType => write!(f, "variable type"),
+            Production::StringLiteral => write!(f, "string literal"),
+        }
+    }
+}
+
 // Demangler class takes the main role in demangling symbols.
 // It has a set of functions to parse mangled symbols into Type instnaces.
 // It also has a set of functions to cnovert Type instances to strings.
@@ -468,7 +1537,7 @@ struct ParserState<'a> {
     remaining: &'a [u8],
 
     // The original input
-    input: &'a str,
+    input: &'a [u8],
 
     // how many bytes we advanced
     offset: usize,
@@ -478,50 +1547,244 @@ struct ParserState<'a> {
     memorized_names: Vec<Name<'a>>,
 
     memorized_types: Vec<Type<'a>>,
+
+    // Where in the input the memorized names and types came from.
+    memorized_name_ranges: Vec<Range<usize>>,
+    memorized_type_ranges: Vec<Range<usize>>,
+
+    options: ParseOptions,
+
+    // What we are currently parsing, outermost first.
+    productions: Vec<Production<'a>>,
+
+    // Set by `parse_partial`, which replaces unparsable parts with
+    // placeholders and keeps the first error in `recovered`.
+    partial: bool,
+    recovered: Option<Error>,
+
+    // Current nesting depth and AST size, checked against the limits in
+    // `options`.
+    depth: usize,
+    nodes: usize,
+
+    // Set by `parse_with_spans`.  Spans are pushed when a node starts, so
+    // enclosing nodes come before the nodes inside them.
+    spans: Option<Vec<Span>>,
+
+    // Set by `parse_type`, which starts with a type instead of a symbol.
+    type_only: bool,
+
+    // Set while `Error::render_diagnostic` parses the input again.  Errors
+    // then capture the productions and backreference tables.
+    diagnose: bool,
 }
 
 impl<'a> ParserState<'a> {
-    fn fail(&self, s: &'static str) -> Error {
-        Error::new_parse_error(Cow::Borrowed(s), self.input, self.offset)
+    fn new(input: &'a [u8], options: ParseOptions, partial: bool) -> ParserState<'a> {
+        ParserState {
+            remaining: input,
+            input,
+            offset: 0,
+            memorized_names: Vec::with_capacity(10),
+            memorized_types: Vec::with_capacity(10),
+            memorized_name_ranges: Vec::with_capacity(10),
+            memorized_type_ranges: Vec::with_capacity(10),
+            options,
+            productions: Vec::new(),
+            partial,
+            recovered: None,
+            depth: 0,
+            nodes: 0,
+            spans: None,
+            type_only: false,
+            diagnose: false,
+        }
+    }
+
+    fn fail(&self, kind: ErrorKind) -> Error {
+        self.fail_at(self.offset, kind)
+    }
+
+    fn fail_at(&self, offset: usize, kind: ErrorKind) -> Error {
+        let mut err = Error::new_parse_error(kind, self.input, offset);
+        if let Some(ref mut origin) = err.origin {
+            origin.options = Some(self.options);
+            origin.type_only = self.type_only;
+        }
+        if self.diagnose {
+            err.diagnostic = Some(Box::new(Diagnostic {
+                productions: self.productions.iter().map(|p| p.to_string()).collect(),
+                names: self.memorized_names.iter().map(name_to_string).collect(),
+                types: self.memorized_types.iter().map(type_to_string).collect(),
+            }));
+        }
+        err
+    }
+
+    // Runs `f` while recording that we are inside `production`.  Errors
+    // capture the stack when they are created, so it is always unwound.
+    fn in_production<T, F>(&mut self, production: Production<'a>, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.productions.push(production);
+        let rv = f(self);
+        self.productions.pop();
+        rv
+    }
+
+    // Runs `f`, and when parsing partially turns a failure into
+    // `placeholder`.  The first error is kept and the rest of the input
+    // is dropped since we no longer know where we are in it.
+    fn recover<T, F>(&mut self, placeholder: T, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if self.partial && self.recovered.is_some() {
+            return Ok(placeholder);
+        }
+        match f(self) {
+            Err(err) if self.partial => {
+                if self.recovered.is_none() {
+                    self.recovered = Some(err);
+                }
+                self.advance(self.remaining.len());
+                Ok(placeholder)
+            }
+            rv => rv,
+        }
+    }
+
+    // Runs `f` one nesting level deeper.
+    fn descend<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if let Some(max) = self.options.max_depth {
+            if self.depth >= max {
+                return Err(self.fail(ErrorKind::DepthLimitExceeded(max)));
+            }
+        }
+        self.depth += 1;
+        let rv = f(self);
+        self.depth -= 1;
+        rv
+    }
+
+    // Accounts for `count` new AST nodes.
+    fn add_nodes(&mut self, count: usize) -> Result<()> {
+        self.nodes = self.nodes.saturating_add(count);
+        if let Some(max) = self.options.max_ast_nodes {
+            if self.nodes > max {
+                return Err(self.fail(ErrorKind::AstNodeLimitExceeded(max)));
+            }
+        }
+        Ok(())
+    }
+
+    // Runs `f` and, when spans are tracked, records the input from `start`
+    // to where `f` stopped as a node of `kind`.
+    fn spanned_from<T, F>(&mut self, start: usize, kind: SpanKind, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let index = match self.spans {
+            Some(ref mut spans) => {
+                spans.push(Span {
+                    kind,
+                    range: start..start,
+                    backreference: None,
+                });
+                spans.len() - 1
+            }
+            None => return f(self),
+        };
+        let rv = f(self);
+        let end = self.offset;
+        if let Some(ref mut spans) = self.spans {
+            if rv.is_ok() {
+                spans[index].range.end = end;
+            } else {
+                spans.truncate(index);
+            }
+        }
+        rv
+    }
+
+    fn spanned<T, F>(&mut self, kind: SpanKind, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let start = self.offset;
+        self.spanned_from(start, kind, f)
     }
 
-    fn fail_args(&self, args: fmt::Arguments) -> Error {
-        Error::new_parse_error(Cow::Owned(format!("{}", args)), self.input, self.offset)
+    // Marks the innermost span as a backreference.  Must be called before
+    // any nested node starts.
+    fn mark_backreference(&mut self, index: usize, target: Range<usize>) {
+        if let Some(span) = self.spans.as_mut().and_then(|spans| spans.last_mut()) {
+            span.backreference = Some(Backreference { index, target });
+        }
+    }
+
+    // Whether `parse_partial` has given up on the rest of the input.
+    fn abandoned(&self) -> bool {
+        self.recovered.is_some()
+    }
+
+    // Fails with `UnexpectedEnd` if the input ran out, and with `kind`
+    // otherwise.
+    fn fail_or_end(&self, kind: ErrorKind) -> Error {
+        if self.remaining.is_empty() {
+            self.fail(ErrorKind::UnexpectedEnd)
+        } else {
+            self.fail(kind)
+        }
     }
 
     fn parse(&mut self) -> Result<ParseResult<'a>> {
         // MSVC-style mangled symbols must start with b'?'.
         if !self.consume(b"?") {
-            return Err(self.fail("does not start with b'?'"));
+            return Err(self.fail(ErrorKind::NotMangled));
         }
 
         if self.consume(b"$") {
             if self.consume(b"TSS") {
                 let mut guard_num: i32 = i32::from(
                     self.read_digit()
-                        .ok_or_else(|| self.fail("missing digit"))?,
+                        .ok_or_else(|| self.fail_or_end(ErrorKind::BadNumber))?,
                 );
                 while !self.consume(b"@") {
-                    guard_num = guard_num * 10
-                        + i32::from(
-                            self.read_digit()
-                                .ok_or_else(|| self.fail("missing digit"))?,
-                        );
+                    let digit = self
+                        .read_digit()
+                        .ok_or_else(|| self.fail_or_end(ErrorKind::BadNumber))?;
+                    guard_num = guard_num
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(i32::from(digit)))
+                        .ok_or_else(|| self.fail(ErrorKind::BadNumber))?;
                 }
-                let name = self.read_nested_name()?;
-                let scope = self.read_scope()?;
-                self.expect(b"4HA")?;
+                let symbol = self.spanned(SpanKind::Symbol, |this| {
+                    let name = this.read_nested_name()?;
+                    let scope = this.read_scope()?;
+                    Ok(Symbol { name, scope })
+                })?;
+                self.expect("4HA")?;
                 return Ok(ParseResult {
-                    symbol: Symbol { name, scope },
+                    symbol,
                     symbol_type: Type::ThreadSafeStaticGuard(guard_num),
                 });
             }
-            let name = self.read_template_name()?;
-            return Ok(ParseResult {
-                symbol: Symbol {
+            let symbol = self.spanned_from(self.offset - 2, SpanKind::Symbol, |this| {
+                let name = this.spanned_from(this.offset - 2, SpanKind::Name, |this| {
+                    this.read_template_name()
+                })?;
+                Ok(Symbol {
                     name,
                     scope: NameSequence { names: Vec::new() },
-                },
+                })
+            })?;
+            return Ok(ParseResult {
+                symbol,
                 symbol_type: Type::None,
             });
         }
@@ -540,7 +1803,9 @@ impl<'a> ParserState<'a> {
                 } else if self.consume(b"5") {
                     true
                 } else {
-                    return Err(self.fail("unexpected local guard marker"));
+                    return Err(
+                        self.fail_or_end(ErrorKind::Malformed("unexpected local guard marker"))
+                    );
                 };
                 if !self.remaining.is_empty() {
                     *scope_index = Some(self.read_unsigned()?);
@@ -549,95 +1814,37 @@ impl<'a> ParserState<'a> {
             _ => {}
         }
 
-        if let Ok(c) = self.get() {
-            let symbol_type = match c {
-                b'0'...b'4' => {
-                    // Read a variable.
-                    let kind = match c {
-                        b'0' => VarStorageKind::PrivateStatic,
-                        b'1' => VarStorageKind::ProtectedStatic,
-                        b'2' => VarStorageKind::PublicStatic,
-                        b'3' => VarStorageKind::Global,
-                        b'4' => VarStorageKind::FunctionLocalStatic,
-                        _ => unreachable!(),
-                    };
-                    let ty = self.read_var_type(StorageClass::empty())?;
-                    let sc = self.read_storage_class();
-                    Type::Var(Box::new(ty), kind, sc)
-                }
-                b'6' => {
-                    let access_class = self.read_qualifier();
-                    let scope = self.read_scope()?;
-                    Type::CXXVFTable(scope, access_class)
-                }
-                b'7' => {
-                    let access_class = self.read_qualifier();
-                    let scope = self.read_scope()?;
-                    Type::CXXVBTable(scope, access_class)
-                }
-                b'9' => {
-                    // extern "C" names have their class and type omitted.
-                    Type::None
-                }
-                b'Y' => {
-                    // Read a non-member function.
-                    let calling_conv = self.read_calling_conv()?;
-                    let storage_class = self.read_storage_class_for_return()?;
-                    let return_type = self.read_var_type(storage_class)?;
-                    let params = self.read_func_params()?;
-                    Type::NonMemberFunction(
-                        calling_conv,
-                        params,
-                        StorageClass::empty(),
-                        Box::new(return_type),
-                    )
-                }
-                b'_' => {
-                    // Read an encoded string.
-                    let char_bytes = match self.get()? {
-                        b'0' => 1, // char
-                        b'1' => 2, // wchar_t
-                        _ => {
-                            return Err(self.fail("unknown string character type"));
-                        }
-                    };
-                    self.read_encoded_string(char_bytes)?
-                }
-                b'$' => {
-                    self.expect(b"B")?;
-                    let vftable_offset = self.read_number()?;
-                    self.expect(b"A")?;
-                    let calling_conv = self.read_calling_conv()?;
-                    Type::VCallThunk(vftable_offset, calling_conv)
-                }
-                b'8' => Type::RTTIType,
-                c => {
-                    // Read a member function.
-                    let func_class = self.read_func_class(c)?;
-                    let access_class = if func_class.contains(FuncClass::STATIC) {
-                        StorageClass::empty()
-                    } else {
-                        self.read_func_qualifiers()?
-                    };
+        if self.abandoned() {
+            return Ok(ParseResult {
+                symbol,
+                symbol_type: Type::None,
+            });
+        }
 
-                    let calling_conv = self.read_calling_conv()?;
-                    let storage_class_for_return = self.read_storage_class_for_return()?;
-                    let return_type = self.read_func_return_type(storage_class_for_return)?;
-                    let params = self.read_func_params()?;
-                    Type::MemberFunction(
-                        func_class,
-                        calling_conv,
-                        params,
-                        access_class,
-                        Box::new(return_type),
-                    )
+        let start = self.offset;
+        if let Ok(c) = self.get() {
+            let symbol_type = self.spanned_from(start, SpanKind::Type, |this| {
+                this.recover(Type::Unknown, |this| {
+                    this.read_type_encoding(c, &symbol.name)
+                })
+            })?;
+            if self.options.strict && is_init_fini_stub(&symbol.name) {
+                if let Type::Var(..) = symbol_type {
+                    // The stub for a variable is followed by the encoding of
+                    // the stub function itself.
+                    self.expect("@")?;
+                    let c = self.get()?;
+                    self.read_type_encoding(c, &symbol.name)?;
                 }
-            };
+            }
             Ok(ParseResult {
                 symbol,
                 symbol_type,
             })
         } else {
+            if self.options.strict && !is_local_static_guard(&symbol.name) {
+                return Err(self.fail(ErrorKind::Malformed("missing type encoding")));
+            }
             Ok(ParseResult {
                 symbol,
                 symbol_type: Type::None,
@@ -645,6 +1852,138 @@ impl<'a> ParserState<'a> {
         }
     }
 
+    // Reads a type on its own.  RTTI names prefix it with a dot, and
+    // class types with the `?A` qualifier of return types.
+    //
+    // <type-name> ::= [.] [? <storage class>] <type>
+    fn parse_type(&mut self) -> Result<Type<'a>> {
+        self.consume(b".");
+        let sc = if self.consume(b"?") {
+            self.read_storage_class()
+        } else {
+            StorageClass::empty()
+        };
+        self.read_var_type(sc)
+    }
+
+    // Reads the encoding that follows a symbol name, starting with its
+    // first character `c`.
+    fn read_type_encoding(&mut self, c: u8, name: &Name<'a>) -> Result<Type<'a>> {
+        Ok(match c {
+            b'0'..=b'4' => {
+                // Read a variable.
+                let kind = match c {
+                    b'0' => VarStorageKind::PrivateStatic,
+                    b'1' => VarStorageKind::ProtectedStatic,
+                    b'2' => VarStorageKind::PublicStatic,
+                    b'3' => VarStorageKind::Global,
+                    b'4' => VarStorageKind::FunctionLocalStatic,
+                    _ => unreachable!(),
+                };
+                let ty = self.in_production(Production::VariableType, |this| {
+                    this.recover(Type::Unknown, |this| {
+                        this.read_var_type(StorageClass::empty())
+                    })
+                })?;
+                let ptr64 = if self.consume(b"E") {
+                    StorageClass::PTR64
+                } else {
+                    StorageClass::empty()
+                };
+                let member = matches!(self.peek(), Some(b'Q'..=b'T'));
+                let sc = self.read_storage_class() | ptr64;
+                if member {
+                    // Pointers to members repeat their class, which the
+                    // type already records.
+                    self.read_name(false)?;
+                }
+                Type::Var(Box::new(ty), kind, sc)
+            }
+            b'6' => {
+                let access_class = self.read_qualifier();
+                let scope = self.read_table_target()?;
+                Type::CXXVFTable(scope, access_class)
+            }
+            b'7' => {
+                let access_class = self.read_qualifier();
+                let scope = self.read_table_target()?;
+                Type::CXXVBTable(scope, access_class)
+            }
+            b'9' => {
+                // extern "C" names have their class and type omitted.
+                Type::None
+            }
+            b'Y' => {
+                // Read a non-member function.
+                let calling_conv = self.read_calling_conv()?;
+                let storage_class = self.read_storage_class_for_return()?;
+                let return_type = self.in_production(Production::ReturnType, |this| {
+                    this.recover(Type::Unknown, |this| this.read_var_type(storage_class))
+                })?;
+                let (params, noexcept) = self.read_func_params()?;
+                Type::NonMemberFunction(FunctionSignature {
+                    calling_conv,
+                    params,
+                    this_quals: StorageClass::empty(),
+                    return_type: Box::new(return_type),
+                    noexcept,
+                    this_adjustment: 0,
+                })
+            }
+            b'_' => {
+                // Read an encoded string.
+                let char_bytes = match self.get()? {
+                    b'0' => 1, // char
+                    b'1' => 2, // wchar_t
+                    _ => {
+                        return Err(self.fail(ErrorKind::InvalidStringLiteral));
+                    }
+                };
+                self.in_production(Production::StringLiteral, |this| {
+                    this.read_encoded_string(char_bytes)
+                })?
+            }
+            b'$' => {
+                self.expect("B")?;
+                let vftable_offset = self.read_number()?;
+                self.expect("A")?;
+                let calling_conv = self.read_calling_conv()?;
+                Type::VCallThunk(vftable_offset, calling_conv)
+            }
+            b'8' => {
+                if self.options.strict && !is_rtti_name(name) {
+                    return Err(self.fail(ErrorKind::Malformed("RTTI type for non-RTTI name")));
+                }
+                Type::RTTIType
+            }
+            c => {
+                // Read a member function.
+                let (func_class, this_adjustment) = self.read_func_class(c)?;
+                let access_class = if func_class.contains(FuncClass::STATIC) {
+                    StorageClass::empty()
+                } else {
+                    self.read_func_qualifiers()?
+                };
+
+                let calling_conv = self.read_calling_conv()?;
+                let storage_class_for_return = self.read_storage_class_for_return()?;
+                let return_type = self.read_func_return_type(storage_class_for_return)?;
+                let (params, noexcept) = self.read_func_params()?;
+                Type::MemberFunction(
+                    func_class,
+                    FunctionSignature {
+                        calling_conv,
+                        params,
+                        this_quals: access_class,
+                        return_type: Box::new(return_type),
+                        noexcept,
+                        this_adjustment,
+                    },
+                )
+            }
+        })
+    }
+
     fn peek(&self) -> Option<u8> {
         self.remaining.first().cloned()
     }
@@ -655,7 +1994,7 @@ impl<'a> ParserState<'a> {
                 self.advance(1);
                 Ok(first)
             }
-            None => Err(self.fail("unexpected end of input")),
+            None => Err(self.fail(ErrorKind::UnexpectedEnd)),
         }
     }
 
@@ -674,9 +2013,9 @@ impl<'a> ParserState<'a> {
         self.remaining = new_remaining;
     }
 
-    fn expect(&mut self, s: &[u8]) -> Result<()> {
-        if !self.consume(s) {
-            Err(self.fail_args(format_args!("{} expected", str::from_utf8(s)?,)))
+    fn expect(&mut self, s: &'static str) -> Result<()> {
+        if !self.consume(s.as_bytes()) {
+            Err(self.fail_or_end(ErrorKind::Expected(s)))
         } else {
             Ok(())
         }
@@ -685,7 +2024,7 @@ impl<'a> ParserState<'a> {
     fn read_digit(&mut self) -> Option<u8> {
         match self.peek() {
             Some(first) => {
-                if char::from(first).is_digit(10) {
+                if char::from(first).is_ascii_digit() {
                     self.advance(1);
                     Some(first - b'0')
                 } else {
@@ -699,7 +2038,7 @@ impl<'a> ParserState<'a> {
     fn read_hex_digit(&mut self) -> Option<char> {
         match self.peek() {
             Some(first) => {
-                if char::from(first).is_digit(16) {
+                if char::from(first).is_ascii_hexdigit() {
                     self.advance(1);
                     Some(first as char)
                 } else {
@@ -712,49 +2051,63 @@ impl<'a> ParserState<'a> {
 
     fn read_encoded_string(&mut self, char_bytes: i32) -> Result<Type<'a>> {
         let byte_length = self.read_number()?; // including null terminator
-        let _crc = self.read_number()?;
+        if self.options.strict && byte_length < char_bytes {
+            return Err(self.fail(ErrorKind::InvalidStringLiteral));
+        }
+        let crc = self.read_number()? as u32;
         let bytes = min(byte_length, char_bytes * 32);
 
         let mut collected = vec![];
         for _i in 0..bytes {
             let c = self.get()?;
             let byte: u8 = match c {
-                b'0'...b'9' | b'a'...b'z' | b'A'...b'Z' | b'_' | b'$' => c,
+                b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$' => c,
                 b'?' => {
                     let c = self.get()?;
                     match c {
-                        b'A'...b'Z' => c - b'A' + 0xe1,
-                        b'a'...b'z' => c - b'A' + 0xc1,
-                        b'0'...b'9' => {
-                            let v = &[
-                                b',', b'/', b'\\', b':', b'.', b' ', b'\n', b'\t', b'\'', b'-',
-                            ];
+                        b'A'..=b'Z' => c - b'A' + 0xc1,
+                        b'a'..=b'z' => c - b'A' + 0xc1,
+                        b'0'..=b'9' => {
+                            let v = b",/\\:. \n\t'-";
                             v[(c - b'0') as usize]
                         }
                         b'$' => {
-                            let high = self.get()? - b'A';
-                            let low = self.get()? - b'A';
+                            let high = self.read_string_nibble()?;
+                            let low = self.read_string_nibble()?;
                             high << 4 | low
                         }
                         _ => {
-                            return Err(self.fail_args(format_args!(
-                                "unknown escaped encoded string character {}",
-                                char::from(c)
-                            )));
+                            return Err(self.fail(ErrorKind::InvalidStringLiteral));
                         }
                     }
                 }
                 _ => {
-                    return Err(self.fail_args(format_args!(
-                        "unknown escaped encoded string character {}",
-                        char::from(c)
-                    )));
+                    return Err(self.fail(ErrorKind::InvalidStringLiteral));
                 }
             };
             collected.push(byte);
         }
 
-        Ok(Type::ConstantString(collected))
+        if self.options.strict {
+            self.expect("@")?;
+        } else {
+            self.consume(b"@");
+        }
+
+        Ok(Type::ConstantString(StringLiteral {
+            char_bytes,
+            byte_length,
+            crc,
+            bytes: collected,
+        }))
+    }
+
+    // Reads one half of a `?$XX` escaped byte in a string literal.
+    fn read_string_nibble(&mut self) -> Result<u8> {
+        match self.get()? {
+            c @ b'A'..=b'P' => Ok(c - b'A'),
+            _ => Err(self.fail_at(self.offset - 1, ErrorKind::InvalidStringLiteral)),
+        }
     }
 
     // Sometimes numbers are encoded in mangled symbols. For example,
@@ -776,30 +2129,45 @@ impl<'a> ParserState<'a> {
             return Ok(if neg { -i32::from(ret) } else { i32::from(ret) });
         }
 
+        // Hex numbers are read as 32 bits and reinterpreted as signed, so
+        // unsigned values such as `PPPPPPPP@` keep their bit pattern.
         let mut i = 0;
-        let mut ret = 0;
+        let mut ret: u32 = 0;
         for c in self.remaining {
             match *c {
                 b'@' => {
+                    // Negative numbers go down to `i32::MIN`, whose bit
+                    // pattern is its own negation.
+                    let ret = if !neg {
+                        Some(ret as i32)
+                    } else if ret <= 0x8000_0000 {
+                        Some((ret as i32).wrapping_neg())
+                    } else {
+                        None
+                    };
+                    let ret = ret.ok_or_else(|| self.fail(ErrorKind::BadNumber))?;
                     self.advance(i + 1);
-                    return Ok(if neg { -(ret as i32) } else { ret as i32 });
+                    return Ok(ret);
                 }
-                b'A'...b'P' => {
-                    ret = (ret << 4) + i32::from(c - b'A');
+                b'A'..=b'P' => {
+                    ret = ret
+                        .checked_mul(16)
+                        .map(|ret| ret | u32::from(c - b'A'))
+                        .ok_or_else(|| self.fail_at(self.offset + i, ErrorKind::BadNumber))?;
                     i += 1;
                 }
                 _ => {
-                    return Err(self.fail("bad number"));
+                    return Err(self.fail_at(self.offset + i, ErrorKind::BadNumber));
                 }
             }
         }
-        Err(self.fail("bad number"))
+        Err(self.fail(ErrorKind::UnexpectedEnd))
     }
 
     fn read_unsigned(&mut self) -> Result<u32> {
         let num = self.read_number()?;
         if num < 0 {
-            return Err(self.fail("expected unsigned"));
+            return Err(self.fail(ErrorKind::BadNumber));
         }
         Ok(num as u32)
     }
@@ -807,56 +2175,107 @@ impl<'a> ParserState<'a> {
     // Read until the next b'@'.
     fn read_string(&mut self) -> Result<&'a [u8]> {
         if let Some(pos) = self.remaining.iter().position(|&x| x == b'@') {
+            if self.options.strict && pos == 0 {
+                return Err(self.fail(ErrorKind::Malformed("empty identifier")));
+            }
             let ret = &self.remaining[0..pos];
             self.advance(pos + 1);
             Ok(ret)
         } else {
-            Err(self.fail("read_string: missing b'@'"))
+            Err(self.fail(ErrorKind::UnexpectedEnd))
         }
     }
 
     // First 10 strings can be referenced by special names ?0, ?1, ..., ?9.
     // Memorize it.
-    fn memorize_name(&mut self, n: &Name<'a>) {
+    fn memorize_name(&mut self, n: &Name<'a>, start: usize) {
         // TODO: the contains check does an equality check on the Name enum, which
         // might do unexpected things in subtle cases. It's not a pure string equality check.
         if self.memorized_names.len() < 10 && !self.memorized_names.contains(n) {
             self.memorized_names.push(n.clone());
+            self.memorized_name_ranges.push(start..self.offset);
         }
     }
-    fn memorize_type(&mut self, t: &Type<'a>) {
+    fn memorize_type(&mut self, t: &Type<'a>, start: usize) {
         // TODO: the contains check does an equality check on the Type enum, which
         // might do unexpected things in subtle cases. It's not a pure string equality check.
         if self.memorized_types.len() < 10 && !self.memorized_types.contains(t) {
             self.memorized_types.push(t.clone());
+            self.memorized_type_ranges.push(start..self.offset);
         }
     }
 
     fn read_template_name(&mut self) -> Result<Name<'a>> {
-        // Templates have their own context for backreferences.
-        let saved_memorized_names = mem::replace(&mut self.memorized_names, vec![]);
-        let saved_memorized_types = mem::replace(&mut self.memorized_types, vec![]);
-        let name = self.read_unqualified_name(false)?; // how does wine deal with ??$?DM@std@@YA?AV?$complex@M@0@ABMABV10@@Z
-        let template_params = self.read_params()?;
-        let _ = mem::replace(&mut self.memorized_names, saved_memorized_names);
-        let _ = mem::replace(&mut self.memorized_types, saved_memorized_types);
-        Ok(Name::Template(Box::new(name), template_params))
+        self.descend(|this| {
+            // Templates have their own context for backreferences.
+            let saved_memorized_names = mem::take(&mut this.memorized_names);
+            let saved_memorized_types = mem::take(&mut this.memorized_types);
+            let saved_name_ranges = mem::take(&mut this.memorized_name_ranges);
+            let saved_type_ranges = mem::take(&mut this.memorized_type_ranges);
+            let name = this.read_unqualified_name(false)?; // how does wine deal with ??$?DM@std@@YA?AV?$complex@M@0@ABMABV10@@Z
+            let label = match name {
+                Name::NonTemplate(Cow::Borrowed(s)) => Some(s),
+                _ => None,
+            };
+            let template_params =
+                this.in_production(Production::TemplateArgs(label), |this| this.read_params())?;
+            let _ = mem::replace(&mut this.memorized_names, saved_memorized_names);
+            let _ = mem::replace(&mut this.memorized_types, saved_memorized_types);
+            this.memorized_name_ranges = saved_name_ranges;
+            this.memorized_type_ranges = saved_type_ranges;
+            Ok(Name::Template(Box::new(name), template_params))
+        })
+    }
+
+    // Structors and conversion operators only make sense as the final
+    // component of a symbol name, never as a scope or a type name.
+    fn check_nested_template_name(&self, name: &Name<'a>) -> Result<()> {
+        if let Name::Template(ref inner, _) = *name {
+            if let Name::Operator(Operator::Ctor | Operator::Dtor | Operator::Conversion) = **inner
+            {
+                if self.options.strict {
+                    return Err(self.fail(ErrorKind::Malformed(
+                        "structor or conversion template used as a scope",
+                    )));
+                }
+            }
+        }
+        Ok(())
     }
 
     fn read_nested_name(&mut self) -> Result<Name<'a>> {
+        self.spanned(SpanKind::Name, |this| this.read_nested_name_here())
+    }
+
+    fn read_nested_name_here(&mut self) -> Result<Name<'a>> {
+        let start = self.offset;
         let name = if let Some(i) = self.read_digit() {
             let i = i as usize;
             if i >= self.memorized_names.len() {
-                return Err(self.fail("name reference too large"));
+                return Err(self.fail_at(
+                    self.offset - 1,
+                    ErrorKind::InvalidBackreference {
+                        index: i,
+                        available: self.memorized_names.len(),
+                    },
+                ));
             }
-            self.memorized_names[i].clone()
+            self.add_nodes(name_nodes(&self.memorized_names[i]))?;
+            let name = self.memorized_names[i].clone();
+            self.mark_backreference(i, self.memorized_name_ranges[i].clone());
+            name
         } else if self.consume(b"?") {
             match self.peek() {
-                Some(b'?') => Name::ParsedName(Box::new(self.parse()?)),
+                Some(b'?') => Name::ParsedName(Box::new(
+                    self.in_production(Production::NestedSymbol, |this| {
+                        this.descend(|this| this.parse())
+                    })?,
+                )),
                 _ => {
                     if self.consume(b"$") {
                         let name = self.read_template_name()?;
-                        self.memorize_name(&name);
+                        self.check_nested_template_name(&name)?;
+                        self.memorize_name(&name, start);
                         name
                     } else if self.consume(b"A") {
                         let id = if self.consume(b"0x") {
@@ -868,11 +2287,11 @@ impl<'a> ParserState<'a> {
                         } else {
                             None
                         };
-                        self.expect(b"@")?;
+                        self.expect("@")?;
                         let memorize = id.is_some();
                         let name = Name::AnonymousNamespace(id);
                         if memorize {
-                            self.memorize_name(&name);
+                            self.memorize_name(&name, start);
                         }
                         name
                     } else {
@@ -884,24 +2303,41 @@ impl<'a> ParserState<'a> {
         } else {
             // Non-template functions or classes.
             let name = self.read_string()?;
-            let name = Name::NonTemplate(name);
-            self.memorize_name(&name);
+            let name = Name::NonTemplate(Cow::Borrowed(name));
+            self.memorize_name(&name, start);
             name
         };
         Ok(name)
     }
 
     fn read_unqualified_name(&mut self, function: bool) -> Result<Name<'a>> {
+        self.spanned(SpanKind::Name, |this| {
+            this.read_unqualified_name_here(function)
+        })
+    }
+
+    fn read_unqualified_name_here(&mut self, function: bool) -> Result<Name<'a>> {
+        let start = self.offset;
         let name = if let Some(i) = self.read_digit() {
             let i = i as usize;
             if i >= self.memorized_names.len() {
-                return Err(self.fail("name reference too large"));
+                return Err(self.fail_at(
+                    self.offset - 1,
+                    ErrorKind::InvalidBackreference {
+                        index: i,
+                        available: self.memorized_names.len(),
+                    },
+                ));
             }
-            self.memorized_names[i].clone()
+            self.add_nodes(name_nodes(&self.memorized_names[i]))?;
+            let name = self.memorized_names[i].clone();
+            self.mark_backreference(i, self.memorized_name_ranges[i].clone());
+            name
         } else if self.consume(b"?$") {
             let name = self.read_template_name()?;
             if !function {
-                self.memorize_name(&name);
+                self.check_nested_template_name(&name)?;
+                self.memorize_name(&name, start);
             }
             name
         } else if self.consume(b"?") {
@@ -909,28 +2345,46 @@ impl<'a> ParserState<'a> {
         } else {
             // Non-template functions or classes.
             let name = self.read_string()?;
-            let name = Name::NonTemplate(name);
-            self.memorize_name(&name);
+            let name = Name::NonTemplate(Cow::Borrowed(name));
+            self.memorize_name(&name, start);
             name
         };
         Ok(name)
     }
 
     fn read_scope(&mut self) -> Result<NameSequence<'a>> {
-        let mut names = Vec::new();
-        while !self.consume(b"@") {
-            let name = self.read_nested_name()?;
-            names.push(name);
+        self.in_production(Production::Scope, |this| {
+            let mut names = Vec::new();
+            while !this.abandoned() && !this.consume(b"@") {
+                let name = this.recover(Name::Unknown, |this| this.read_nested_name())?;
+                names.push(name);
+            }
+            Ok(NameSequence { names })
+        })
+    }
+
+    // Reads the `{for ...}` part of a vftable or vbtable.  A non-empty
+    // target is a qualified name followed by another b'@'.
+    fn read_table_target(&mut self) -> Result<NameSequence<'a>> {
+        let scope = self.read_scope()?;
+        if !scope.names.is_empty() {
+            if self.options.strict {
+                self.expect("@")?;
+            } else {
+                self.consume(b"@");
+            }
         }
-        Ok(NameSequence { names })
+        Ok(scope)
     }
 
     // Parses a name in the form of A@B@C@@ which represents C::B::A.
     fn read_name(&mut self, function: bool) -> Result<Symbol<'a>> {
-        let name = self.read_unqualified_name(function)?;
-        let scope = self.read_scope()?;
+        self.spanned(SpanKind::Symbol, |this| {
+            let name = this.recover(Name::Unknown, |this| this.read_unqualified_name(function))?;
+            let scope = this.read_scope()?;
 
-        Ok(Symbol { name, scope })
+            Ok(Symbol { name, scope })
+        })
     }
 
     fn read_func_qualifiers(&mut self) -> Result<StorageClass> {
@@ -951,11 +2405,11 @@ impl<'a> ParserState<'a> {
         };
         let ref_qualifiers = match self.peek() {
             Some(b'G') => {
-                self.expect(b"G")?;
+                self.expect("G")?;
                 StorageClass::LVALUE_QUAL
             }
             Some(b'H') => {
-                self.expect(b"H")?;
+                self.expect("H")?;
                 StorageClass::RVALUE_QUAL
             }
             _ => StorageClass::empty(),
@@ -978,14 +2432,18 @@ impl<'a> ParserState<'a> {
         } else {
             StorageClass::empty()
         };
-        let return_type = self.read_var_type(var_sc)?;
-        let params = self.read_func_params()?;
-        Ok(Type::NonMemberFunction(
+        let return_type = self.in_production(Production::ReturnType, |this| {
+            this.recover(Type::Unknown, |this| this.read_var_type(var_sc))
+        })?;
+        let (params, noexcept) = self.read_func_params()?;
+        Ok(Type::NonMemberFunction(FunctionSignature {
             calling_conv,
             params,
-            sc,
-            Box::new(return_type),
-        ))
+            this_quals: sc,
+            return_type: Box::new(return_type),
+            noexcept,
+            this_adjustment: 0,
+        }))
     }
 
     fn read_special_name(&mut self) -> Result<Name<'a>> {
@@ -1056,7 +2514,7 @@ impl<'a> ParserState<'a> {
                     let c = self.get()?;
                     match c {
                         b'0' => {
-                            self.expect(b"?")?;
+                            self.expect("?")?;
                             let storage_class = self.read_storage_class();
                             let t = self.read_var_type(storage_class)?;
                             Operator::RTTITypeDescriptor(storage_class, Box::new(t))
@@ -1077,7 +2535,7 @@ impl<'a> ParserState<'a> {
                         b'3' => Operator::RTTIClassHierarchyDescriptor,
                         b'4' => Operator::RTTIClassCompleteObjectLocator,
                         _ => {
-                            return Err(self.fail("unknown RTTI Operator name"));
+                            return Err(self.fail(ErrorKind::UnknownOperator));
                         }
                     }
                 }
@@ -1099,33 +2557,35 @@ impl<'a> ParserState<'a> {
                     } else if self.consume(b"K") {
                         Operator::LiteralOperatorName // TODO: read <source-name>, that's the operator name
                     } else {
-                        return Err(self.fail("unknown operator name"));
+                        return Err(self.fail(ErrorKind::UnknownOperator));
                     }
                 }
                 _ => {
-                    return Err(self.fail("unknown operator name"));
+                    return Err(self.fail(ErrorKind::UnknownOperator));
                 }
             },
             _ => {
-                return Err(self.fail("unknown operator name"));
+                return Err(self.fail(ErrorKind::UnknownOperator));
             }
         }))
     }
 
-    fn read_func_class(&mut self, c: u8) -> Result<FuncClass> {
-        // TODO: need to figure out how to wrap up the adjustment.
+    // Reads a function class and, for adjustor thunks, the `this`
+    // adjustment that follows it.
+    fn read_func_class(&mut self, c: u8) -> Result<(FuncClass, i32)> {
+        let mut adjustment = 0;
         let mut read_thunk = |func_class| -> Result<FuncClass> {
-            let _adjustment = self.read_number()?;
+            adjustment = self.read_number()?;
             Ok(func_class | FuncClass::THUNK)
         };
 
-        Ok(match c {
+        let func_class = match c {
             b'A' => FuncClass::PRIVATE,
             b'B' => FuncClass::PRIVATE | FuncClass::FAR,
             b'C' => FuncClass::PRIVATE | FuncClass::STATIC,
-            b'D' => FuncClass::PRIVATE | FuncClass::STATIC,
+            b'D' => FuncClass::PRIVATE | FuncClass::STATIC | FuncClass::FAR,
             b'E' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
-            b'F' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
+            b'F' => FuncClass::PRIVATE | FuncClass::VIRTUAL | FuncClass::FAR,
             // TODO(mitsuhiko): llvm uses adjustor here instead of virtual
             b'G' => read_thunk(FuncClass::PRIVATE | FuncClass::VIRTUAL)?,
             // TODO(mitsuhiko): llvm uses adjustor here instead of virtual
@@ -1153,9 +2613,10 @@ impl<'a> ParserState<'a> {
             b'Y' => FuncClass::GLOBAL,
             b'Z' => FuncClass::GLOBAL | FuncClass::FAR,
             _ => {
-                return Err(self.fail("unknown func class"));
+                return Err(self.fail_at(self.offset - 1, ErrorKind::UnknownFunctionClass(c)));
             }
-        })
+        };
+        Ok((func_class, adjustment))
     }
 
     fn read_qualifier(&mut self) -> StorageClass {
@@ -1182,8 +2643,9 @@ impl<'a> ParserState<'a> {
             b'E' => CallingConv::Thiscall,
             b'G' => CallingConv::Stdcall,
             b'I' => CallingConv::Fastcall,
-            _ => {
-                return Err(self.fail("unknown calling conv"));
+            b'Q' => CallingConv::Vectorcall,
+            c => {
+                return Err(self.fail_at(self.offset - 1, ErrorKind::UnknownCallingConvention(c)));
             }
         })
     }
@@ -1194,7 +2656,9 @@ impl<'a> ParserState<'a> {
         if self.consume(b"@") {
             Ok(Type::None)
         } else {
-            self.read_var_type(storage_class)
+            self.in_production(Production::ReturnType, |this| {
+                this.recover(Type::Unknown, |this| this.read_var_type(storage_class))
+            })
         }
     }
 
@@ -1228,8 +2692,8 @@ impl<'a> ParserState<'a> {
             b'B' => StorageClass::CONST,
             b'C' => StorageClass::VOLATILE,
             b'D' => StorageClass::CONST | StorageClass::VOLATILE,
-            _ => {
-                return Err(self.fail("unknown storage class"));
+            c => {
+                return Err(self.fail_at(self.offset - 1, ErrorKind::UnknownStorageClass(c)));
             }
         })
     }
@@ -1241,8 +2705,8 @@ impl<'a> ParserState<'a> {
         } else {
             StorageClass::empty()
         };
-        let (access_class, func_class) = if read_qualifiers {
-            (self.read_qualifier() | ptr64, FuncClass::empty())
+        let (access_class, (func_class, this_adjustment)) = if read_qualifiers {
+            (self.read_qualifier() | ptr64, (FuncClass::empty(), 0))
         } else {
             let c = self.get()?;
             (ptr64, self.read_func_class(c)?)
@@ -1250,19 +2714,31 @@ impl<'a> ParserState<'a> {
         let calling_conv = self.read_calling_conv()?;
         let storage_class_for_return = self.read_storage_class_for_return()?;
         let return_type = self.read_func_return_type(storage_class_for_return)?;
-        let params = self.read_func_params()?;
+        let (params, noexcept) = self.read_func_params()?;
         Ok(Type::MemberFunctionPointer(
             symbol,
             func_class,
-            calling_conv,
-            params,
-            access_class,
-            Box::new(return_type),
+            FunctionSignature {
+                calling_conv,
+                params,
+                this_quals: access_class,
+                return_type: Box::new(return_type),
+                noexcept,
+                this_adjustment,
+            },
         ))
     }
 
     // Reads a variable type.
-    fn read_var_type(&mut self, mut sc: StorageClass) -> Result<Type<'a>> {
+    fn read_var_type(&mut self, sc: StorageClass) -> Result<Type<'a>> {
+        self.descend(|this| {
+            this.add_nodes(1)?;
+            this.spanned(SpanKind::Type, |this| this.read_var_type_here(sc))
+        })
+    }
+
+    // Reads a type at the current nesting level; see `read_var_type`.
+    fn read_var_type_here(&mut self, mut sc: StorageClass) -> Result<Type<'a>> {
         if self.consume(b"W4") {
             let name = self.read_name(false)?;
             return Ok(Type::Enum(name, sc));
@@ -1292,17 +2768,22 @@ impl<'a> ParserState<'a> {
                 return Ok(Type::TemplateParameterWithIndex(n));
             }
             if self.consume(b"$BY") {
-                return Ok(self.read_array()?);
+                return self.read_array(sc & StorageClass::PTR64);
             }
             if self.consume(b"$Q") {
                 return Ok(Type::RValueRef(Box::new(self.read_pointee()?), sc));
             }
-            if self.consume(b"S")
-                || self.consume(b"$V")
-                || self.consume(b"$Z")
-                || self.consume(b"$$V")
-            {
-                return Ok(Type::EmptyParameterPack);
+            if self.consume(b"S") {
+                return Ok(Type::ParameterPack(PackMarker::EmptyNonTypePack));
+            }
+            if self.consume(b"$V") {
+                return Ok(Type::ParameterPack(PackMarker::EmptyTypePack));
+            }
+            if self.consume(b"$$V") {
+                return Ok(Type::ParameterPack(PackMarker::EmptyTypePackLegacy));
+            }
+            if self.consume(b"$Z") {
+                return Ok(Type::ParameterPack(PackMarker::Separator));
             }
             if self.consume(b"$T") {
                 return Ok(Type::Nullptr);
@@ -1314,52 +2795,57 @@ impl<'a> ParserState<'a> {
                 return self.read_func_type(true);
             }
             if self.consume(b"$Y") {
-                let name = self.read_name(true)?;
-                return Ok(Type::Alias(name, sc));
+                let name = self.read_name(false)?;
+                return Ok(Type::TemplateTemplate(name));
             }
             // These next cases can fallthrough, so be careful adding new ones!
             if self.consume(b"$C") {
                 sc = self.read_qualifier();
-            } else if let Some(x) = self.peek() {
-                match x {
-                    // Inheritance specifiers, which we don't need to remember.
-                    b'1' | b'H' | b'I' | b'J' => {
-                        self.advance(1);
-                        self.expect(b"?")?;
-                        return self.read_member_function_pointer(false);
-                    }
-                    _ => {}
-                };
+            } else if let Some(b'1' | b'H' | b'I' | b'J') = self.peek() {
+                // Inheritance specifiers, which we don't need to remember.
+                self.advance(1);
+                self.expect("?")?;
+                return self.read_member_function_pointer(false);
             }
         }
 
         if self.consume(b"?") {
             let n = self.read_number()?;
-            return Ok(Type::TemplateParameterWithIndex(-n));
+            let n = n
+                .checked_neg()
+                .ok_or_else(|| self.fail(ErrorKind::BadNumber))?;
+            return Ok(Type::TemplateParameterWithIndex(n));
         }
 
         if let Some(n) = self.read_digit() {
             if n as usize >= self.memorized_types.len() {
-                return Err(self.fail_args(format_args!("invalid backreference: {}", n)));
+                return Err(self.fail_at(
+                    self.offset - 1,
+                    ErrorKind::InvalidBackreference {
+                        index: n as usize,
+                        available: self.memorized_types.len(),
+                    },
+                ));
             }
 
-            return Ok(self.memorized_types[n as usize].clone());
+            self.add_nodes(type_nodes(&self.memorized_types[n as usize]))?;
+            let t = self.memorized_types[n as usize].clone();
+            self.mark_backreference(n as usize, self.memorized_type_ranges[n as usize].clone());
+            return Ok(t);
         }
 
+        let start = self.offset;
         Ok(match self.get()? {
             b'T' => Type::Union(self.read_name(false)?, sc),
             b'U' => Type::Struct(self.read_name(false)?, sc),
             b'V' => Type::Class(self.read_name(false)?, sc),
             b'A' => Type::Ref(Box::new(self.read_pointee()?), sc),
             b'B' => Type::Ref(Box::new(self.read_pointee()?), StorageClass::VOLATILE),
-            b'P' => Type::Ptr(Box::new(self.read_pointee()?), sc),
-            b'Q' => Type::Ptr(Box::new(self.read_pointee()?), StorageClass::CONST),
-            b'R' => Type::Ptr(Box::new(self.read_pointee()?), StorageClass::VOLATILE),
-            b'S' => Type::Ptr(
-                Box::new(self.read_pointee()?),
-                StorageClass::CONST | StorageClass::VOLATILE,
-            ),
-            b'Y' => self.read_array()?,
+            b'P' => self.read_pointer(sc)?,
+            b'Q' => self.read_pointer(StorageClass::CONST)?,
+            b'R' => self.read_pointer(StorageClass::VOLATILE)?,
+            b'S' => self.read_pointer(StorageClass::CONST | StorageClass::VOLATILE)?,
+            b'Y' => self.read_array(sc & StorageClass::PTR64)?,
             b'X' => Type::Void(sc),
             b'D' => Type::Char(sc),
             b'C' => Type::Schar(sc),
@@ -1384,142 +2870,663 @@ impl<'a> ParserState<'a> {
                 b'S' => Type::Char16(sc),
                 b'U' => Type::Char32(sc),
                 _ => {
-                    return Err(self.fail("unknown primitive type"));
+                    return Err(self.fail_at(start, ErrorKind::UnknownPrimitiveType));
+                }
+            },
+            _c => {
+                return Err(self.fail_at(start, ErrorKind::UnknownPrimitiveType));
+            }
+        })
+    }
+
+    // Reads a pointer with the qualifiers `sc`.  Pointee qualifiers from
+    // `Q` to `T` make it a pointer to a data member of the class that
+    // follows them.
+    //
+    // <pointer> ::= <pointee>
+    //           ::= [E] <member storage class> <class name> <type>
+    fn read_pointer(&mut self, sc: StorageClass) -> Result<Type<'a>> {
+        let qualifiers = self.remaining.strip_prefix(b"E").unwrap_or(self.remaining);
+        if !matches!(qualifiers.first(), Some(b'Q'..=b'T')) {
+            return Ok(Type::Ptr(Box::new(self.read_pointee()?), sc));
+        }
+        let ptr64 = if self.consume(b"E") {
+            StorageClass::PTR64
+        } else {
+            StorageClass::empty()
+        };
+        let storage_class = self.read_storage_class();
+        let class = self.read_name(false)?;
+        let pointee = self.read_var_type(storage_class | ptr64)?;
+        Ok(Type::MemberDataPointer(class, Box::new(pointee), sc))
+    }
+
+    fn read_pointee(&mut self) -> Result<Type<'a>> {
+        let ptr64 = if self.consume(b"E") {
+            StorageClass::PTR64
+        } else {
+            StorageClass::empty()
+        };
+        if self.options.strict && !matches!(self.peek(), Some(b'A'..=b'H' | b'Q'..=b'T')) {
+            return Err(self.fail_or_end(ErrorKind::Malformed("missing pointee qualifiers")));
+        }
+        let storage_class = self.read_storage_class();
+        self.read_var_type(storage_class | ptr64)
+    }
+
+    // Reads an array.  `ptr64` is kept in the storage class of every
+    // dimension when the array is the pointee of a 64-bit pointer.
+    fn read_array(&mut self, ptr64: StorageClass) -> Result<Type<'a>> {
+        let dimension = self.read_number()?;
+        if dimension <= 0 {
+            return Err(self.fail(ErrorKind::InvalidArrayDimension(dimension)));
+        }
+        let (array, _) = self.read_nested_array(dimension, ptr64)?;
+        Ok(array)
+    }
+
+    fn read_nested_array(
+        &mut self,
+        dimension: i32,
+        ptr64: StorageClass,
+    ) -> Result<(Type<'a>, StorageClass)> {
+        if dimension > 0 {
+            let len = self.read_number()?;
+            let (inner_array, storage_class) =
+                self.descend(|this| this.read_nested_array(dimension - 1, ptr64))?;
+            Ok((
+                Type::Array(len, Box::new(inner_array), storage_class),
+                storage_class,
+            ))
+        } else {
+            let storage_class = if self.consume(b"$$C") {
+                if self.consume(b"B") {
+                    StorageClass::CONST
+                } else if self.consume(b"C") {
+                    StorageClass::VOLATILE
+                } else if self.consume(b"D") {
+                    StorageClass::CONST | StorageClass::VOLATILE
+                } else if !self.consume(b"A") {
+                    return Err(match self.peek() {
+                        Some(c) => self.fail(ErrorKind::UnknownStorageClass(c)),
+                        None => self.fail(ErrorKind::UnexpectedEnd),
+                    });
+                } else {
+                    StorageClass::empty()
+                }
+            } else {
+                StorageClass::empty()
+            };
+
+            Ok((
+                self.read_var_type(StorageClass::empty())?,
+                storage_class | ptr64,
+            ))
+        }
+    }
+
+    // Reads a function or a template parameters.
+    fn read_params(&mut self) -> Result<Params<'a>> {
+        // println!("read_params on {}", str::from_utf8(self.input)?);
+        // Within the same parameter list, you can backreference the first 10 types.
+        // let mut backref: Vec<Type<'a>> = Vec::with_capacity(10);
+
+        let mut params: Vec<Type<'a>> = Vec::new();
+
+        while !self.remaining.starts_with(b"@")
+            && !self.remaining.starts_with(b"Z")
+            && !self.remaining.is_empty()
+        {
+            let param_type = self.recover(Type::Unknown, |this| this.read_param())?;
+            params.push(param_type);
+        }
+
+        if self.consume(b"Z") {
+            params.push(Type::VarArgs);
+        } else if self.remaining.is_empty() && !self.options.strict {
+            // this is needed to handle the weird standalone template manglings
+        } else {
+            self.expect("@")?;
+        }
+        Ok(Params { types: params })
+    }
+
+    // Reads a single entry of a parameter list.
+    fn read_param(&mut self) -> Result<Type<'a>> {
+        if let Some(b'0'..=b'9') = self.peek() {
+            return self.spanned(SpanKind::Type, |this| this.read_param_backreference());
+        }
+
+        let start = self.offset;
+
+        let param_type = self.read_var_type(StorageClass::empty())?;
+
+        // Single-letter types are ignored for backreferences because
+        // memorizing them doesn't save anything.
+        if self.offset - start > 1 {
+            self.memorize_type(&param_type, start);
+        }
+        Ok(param_type)
+    }
+
+    fn read_param_backreference(&mut self) -> Result<Type<'a>> {
+        let n = self.read_digit().map_or(0, usize::from);
+        if n >= self.memorized_types.len() {
+            return Err(self.fail_at(
+                self.offset - 1,
+                ErrorKind::InvalidBackreference {
+                    index: n,
+                    available: self.memorized_types.len(),
+                },
+            ));
+        }
+        // println!("reading a type from memorized_types[{}]. full list: {:#?}", n, self.memorized_types);
+        self.add_nodes(type_nodes(&self.memorized_types[n]))?;
+        let param_type = self.memorized_types[n].clone();
+        self.mark_backreference(n, self.memorized_type_ranges[n].clone());
+        Ok(param_type)
+    }
+
+    // Reads a parameter list and the throw specification that follows it.
+    // Returns whether the function is `noexcept`.
+    fn read_func_params(&mut self) -> Result<(Params<'a>, bool)> {
+        if self.abandoned() {
+            return Ok((
+                Params {
+                    types: vec![Type::Unknown],
+                },
+                false,
+            ));
+        }
+        self.in_production(Production::FunctionParams, |this| {
+            let params = if this.consume(b"X") {
+                Params {
+                    types: vec![Type::Void(StorageClass::empty())],
                 }
+            } else {
+                this.read_params()?
+            };
+
+            let noexcept = this.consume(b"_E");
+            if !noexcept {
+                this.recover((), |this| this.expect("Z"))?;
+            }
+
+            Ok((params, noexcept))
+        })
+    }
+}
+
+pub fn demangle(input: &str, flags: DemangleFlags) -> Result<String> {
+    demangle_with_options(input, flags, ParseOptions::default())
+}
+
+/// Parses a mangled name for formatting later.
+///
+/// Names that parse but cannot be serialized are rejected here, so that
+/// formatting the result with the default flags shows the demangled name.
+///
+/// ```
+/// let demangled = msvc_demangler::try_demangle("?foo@Bar@@QAEHH@Z").unwrap();
+/// assert_eq!(demangled.to_string(), "public: int __thiscall Bar::foo(int)");
+/// assert_eq!(format!("{:#}", demangled), "Bar::foo");
+/// ```
+pub fn try_demangle(input: &str) -> Result<Demangle<'_>> {
+    let parsed = parse(input)?;
+    write_demangled(io::sink(), &parsed, DemangleFlags::llvm())?;
+    Ok(Demangle {
+        input,
+        parsed,
+        flags: DemangleFlags::llvm(),
+    })
+}
+
+fn is_rtti_name(name: &Name) -> bool {
+    matches!(
+        *name,
+        Name::Operator(
+            Operator::RTTITypeDescriptor(..)
+                | Operator::RTTIBaseClassDescriptor(..)
+                | Operator::RTTIBaseClassArray
+                | Operator::RTTIClassHierarchyDescriptor
+        )
+    )
+}
+
+fn is_init_fini_stub(name: &Name) -> bool {
+    matches!(
+        *name,
+        Name::Operator(Operator::DynamicInitializer | Operator::DynamicAtexitDtor)
+    )
+}
+
+fn is_local_static_guard(name: &Name) -> bool {
+    matches!(
+        *name,
+        Name::Operator(Operator::LocalStaticGuard(_) | Operator::LocalStaticThreadGuard(_))
+    )
+}
+
+// Counts AST nodes, for `ParseOptions::max_ast_nodes`.
+struct NodeCounter(usize);
+
+impl<'ast> Visit<'ast> for NodeCounter {
+    fn visit_name(&mut self, name: &'ast Name<'ast>) {
+        self.0 += 1;
+        visit::walk_name(self, name);
+    }
+
+    fn visit_type(&mut self, t: &'ast Type<'ast>) {
+        self.0 += 1;
+        visit::walk_type(self, t);
+    }
+}
+
+fn name_nodes(name: &Name) -> usize {
+    let mut counter = NodeCounter(0);
+    counter.visit_name(name);
+    counter.0
+}
+
+fn type_nodes(t: &Type) -> usize {
+    let mut counter = NodeCounter(0);
+    counter.visit_type(t);
+    counter.0
+}
+
+/// Parses a mangled name.
+///
+/// This never panics; malformed input results in an `Error`.
+pub fn parse(input: &str) -> Result<ParseResult<'_>> {
+    parse_with_options(input, ParseOptions::default())
+}
+
+/// Parses a mangled name with the given options.
+pub fn parse_with_options(input: &str, options: ParseOptions) -> Result<ParseResult<'_>> {
+    parse_bytes_with_options(input.as_bytes(), options)
+}
+
+/// Parses a mangled name that is not necessarily valid UTF-8.
+///
+/// Symbol tables can contain identifiers in legacy encodings such as
+/// Latin-1.  Names keep their original bytes and error offsets refer to
+/// `input`.
+pub fn parse_bytes(input: &[u8]) -> Result<ParseResult<'_>> {
+    parse_bytes_with_options(input, ParseOptions::default())
+}
+
+/// Parses a mangled name given as bytes with the given options.
+pub fn parse_bytes_with_options(input: &[u8], options: ParseOptions) -> Result<ParseResult<'_>> {
+    let mut state = ParserState::new(input, options, false);
+    let result = state.parse()?;
+    if options.strict && !state.remaining.is_empty() {
+        return Err(state.fail(ErrorKind::TrailingData));
+    }
+    Ok(result)
+}
+
+/// Parses a mangled name and records where each `Symbol`, `Name` and `Type`
+/// came from in the input.
+///
+/// Spans are ordered by where they start, and a span comes before the
+/// spans nested inside it.  Parts of the AST that have no spelling of
+/// their own, such as the `void` of an empty parameter list, have no span.
+///
+/// The spans are kept in a list next to the AST rather than on its nodes,
+/// so the AST types keep their shape and names parsed from different
+/// places still compare equal.  The list is a snapshot of the input: it
+/// only matches the tree it was returned with, and editing that tree, for
+/// example with `VisitMut`, does not update it.  To get spans for an
+/// edited tree, mangle it and parse the result again.
+///
+/// ```
+/// use msvc_demangler::{parse_with_spans, ParseOptions, SpanKind};
+///
+/// let input = "?f@@YAXPAUFoo@@0@Z";
+/// let (_, spans) = parse_with_spans(input, ParseOptions::default()).unwrap();
+/// let backref = spans.iter().find(|span| span.backreference.is_some()).unwrap();
+/// assert_eq!(backref.kind, SpanKind::Type);
+/// assert_eq!(&input[backref.range.clone()], "0");
+/// let target = backref.backreference.clone().unwrap().target;
+/// assert_eq!(&input[target], "PAUFoo@@");
+/// ```
+pub fn parse_with_spans(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(ParseResult<'_>, Vec<Span>)> {
+    let mut state = ParserState::new(input.as_bytes(), options, false);
+    state.spans = Some(Vec::new());
+    let result = state.parse()?;
+    if options.strict && !state.remaining.is_empty() {
+        return Err(state.fail(ErrorKind::TrailingData));
+    }
+    Ok((result, state.spans.unwrap_or_default()))
+}
+
+/// Parses as much of a mangled name as possible.
+///
+/// Unlike `parse` this does not give up on the first error.  Parts of the
+/// symbol that could not be parsed are replaced by `Name::Unknown` and
+/// `Type::Unknown` placeholders, which render as `<?>`, and the first error
+/// is returned alongside.  If the error is `None` the result is the same
+/// as what `parse` returns.
+pub fn parse_partial(input: &str) -> (ParseResult<'_>, Option<Error>) {
+    let mut state = ParserState::new(input.as_bytes(), ParseOptions::default(), true);
+    match state.parse() {
+        Ok(result) => (result, state.recovered),
+        Err(err) => (
+            ParseResult {
+                symbol: Symbol {
+                    name: Name::Unknown,
+                    scope: NameSequence { names: Vec::new() },
+                },
+                symbol_type: Type::None,
             },
-            _c => {
-                return Err(self.fail("unknown primitive type"));
-            }
-        })
+            Some(state.recovered.unwrap_or(err)),
+        ),
     }
+}
 
-    fn read_pointee(&mut self) -> Result<Type<'a>> {
-        let ptr64 = if self.consume(b"E") {
-            StorageClass::PTR64
-        } else {
-            StorageClass::empty()
-        };
-        let storage_class = self.read_storage_class();
-        self.read_var_type(storage_class | ptr64)
+/// Demangles as much of a mangled name as possible.
+///
+/// See `parse_partial` for how unparsable parts are handled.
+pub fn demangle_lossy(input: &str, flags: DemangleFlags) -> (String, Option<Error>) {
+    let (parsed, err) = parse_partial(input);
+    match serialize(&parsed, flags) {
+        Ok(s) => (s, err),
+        Err(serialize_err) => (input.to_string(), Some(err.unwrap_or(serialize_err))),
     }
+}
 
-    fn read_array(&mut self) -> Result<Type<'a>> {
-        let dimension = self.read_number()?;
-        if dimension <= 0 {
-            return Err(self.fail_args(format_args!("invalid array dimension: {}", dimension)));
-        }
-        let (array, _) = self.read_nested_array(dimension)?;
-        Ok(array)
+/// Parses a mangled type, such as the `.?AVFoo@bar@@` that
+/// `type_info::raw_name()` returns.
+///
+/// The leading dot and the `?A` that class types start with are optional,
+/// so bare type encodings like `PEAVBar@@` are accepted too.
+///
+/// ```
+/// use msvc_demangler::{demangle_type, parse_type, serialize_type, DemangleFlags};
+///
+/// let t = parse_type(".?AVFoo@bar@@").unwrap();
+/// assert_eq!(
+///     serialize_type(&t, DemangleFlags::llvm()).unwrap(),
+///     "class bar::Foo"
+/// );
+/// assert_eq!(
+///     demangle_type(".PEAVBar@@", DemangleFlags::llvm()).unwrap(),
+///     "class Bar *"
+/// );
+/// ```
+pub fn parse_type(input: &str) -> Result<Type<'_>> {
+    parse_type_with_options(input, ParseOptions::default())
+}
+
+fn parse_type_with_options(input: &str, options: ParseOptions) -> Result<Type<'_>> {
+    let mut state = ParserState::new(input.as_bytes(), options, false);
+    state.type_only = true;
+    let t = state.parse_type()?;
+    if !state.remaining.is_empty() {
+        return Err(state.fail(ErrorKind::TrailingData));
     }
+    Ok(t)
+}
 
-    fn read_nested_array(&mut self, dimension: i32) -> Result<(Type<'a>, StorageClass)> {
-        if dimension > 0 {
-            let len = self.read_number()?;
-            let (inner_array, storage_class) = self.read_nested_array(dimension - 1)?;
-            Ok((
-                Type::Array(len, Box::new(inner_array), storage_class),
-                storage_class,
-            ))
-        } else {
-            let storage_class = if self.consume(b"$$C") {
-                if self.consume(b"B") {
-                    StorageClass::CONST
-                } else if self.consume(b"C") || self.consume(b"D") {
-                    StorageClass::CONST | StorageClass::VOLATILE
-                } else if !self.consume(b"A") {
-                    return Err(self.fail("unknown storage class"));
-                } else {
-                    StorageClass::empty()
-                }
-            } else {
-                StorageClass::empty()
-            };
+/// Demangles a mangled type.  See `parse_type`.
+pub fn demangle_type(input: &str, flags: DemangleFlags) -> Result<String> {
+    demangle_type_with_options(input, flags, ParseOptions::default())
+}
 
-            Ok((self.read_var_type(StorageClass::empty())?, storage_class))
-        }
+// Demangles a type, enforcing all the limits in `options`.
+fn demangle_type_with_options(
+    input: &str,
+    flags: DemangleFlags,
+    options: ParseOptions,
+) -> Result<String> {
+    let t = parse_type_with_options(input, options)?;
+    serialize_type_with_limit(&t, flags, options.max_output_len)
+}
+
+/// Converts a type to a string, like `serialize` does for whole symbols.
+pub fn serialize_type(t: &Type, flags: DemangleFlags) -> Result<String> {
+    serialize_type_with_limit(t, flags, None)
+}
+
+fn serialize_type_with_limit(
+    t: &Type,
+    flags: DemangleFlags,
+    max_len: Option<usize>,
+) -> Result<String> {
+    let mut s = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut s, flags, max_len);
+        serializer.write_pre(t)?;
+        serializer.write_post(t)?;
     }
+    Ok(String::from_utf8(s)?.trim().to_string())
+}
 
-    // Reads a function or a template parameters.
-    fn read_params(&mut self) -> Result<Params<'a>> {
-        // println!("read_params on {}", str::from_utf8(self.input)?);
-        // Within the same parameter list, you can backreference the first 10 types.
-        // let mut backref: Vec<Type<'a>> = Vec::with_capacity(10);
+pub fn serialize(input: &ParseResult, flags: DemangleFlags) -> Result<String> {
+    Ok(String::from_utf8(serialize_bytes(input, flags)?)?)
+}
 
-        let mut params: Vec<Type<'a>> = Vec::new();
+/// Like `serialize` but keeps non-UTF-8 names as they are.
+///
+/// Use `String::from_utf8_lossy` on the result to get a printable string.
+pub fn serialize_bytes(input: &ParseResult, flags: DemangleFlags) -> Result<Vec<u8>> {
+    serialize_with_limit(input, flags, None)
+}
 
-        while !self.remaining.starts_with(b"@")
-            && !self.remaining.starts_with(b"Z")
-            && !self.remaining.is_empty()
-        {
-            if let Some(n) = self.read_digit() {
-                if n as usize >= self.memorized_types.len() {
-                    return Err(self.fail_args(format_args!("invalid backreference: {}", n)));
-                }
-                // println!("reading a type from memorized_types[{}]. full list: {:#?}", n, self.memorized_types);
-                params.push(self.memorized_types[n as usize].clone());
-                continue;
-            }
+/// Writes the demangled form of a parsed name to a `fmt::Write`.
+///
+/// This does not allocate, so it suits writing into a reused buffer or
+/// straight into a `fmt::Formatter`.  Names that are not valid UTF-8 are
+/// converted lossily.
+pub fn serialize_into<W: fmt::Write>(
+    w: W,
+    input: &ParseResult,
+    flags: DemangleFlags,
+) -> Result<()> {
+    Serializer::new(FmtWriter(w), flags, None).serialize(input)
+}
 
-            let len = self.remaining.len();
+/// Writes the demangled form of a parsed name to an `io::Write`.
+///
+/// Like `serialize_bytes`, names are written as they are.
+pub fn write_demangled<W: io::Write>(
+    w: W,
+    input: &ParseResult,
+    flags: DemangleFlags,
+) -> Result<()> {
+    Serializer::new(w, flags, None).serialize(input)
+}
 
-            let param_type = self.read_var_type(StorageClass::empty())?;
+/// Demangles a name that is not necessarily valid UTF-8.
+///
+/// See `parse_bytes` and `serialize_bytes`.
+pub fn demangle_bytes(input: &[u8], flags: DemangleFlags) -> Result<Vec<u8>> {
+    serialize_with_limit(&parse_bytes(input)?, flags, Some(DEFAULT_MAX_OUTPUT_LEN))
+}
 
-            // Single-letter types are ignored for backreferences because
-            // memorizing them doesn't save anything.
-            if len - self.remaining.len() > 1 {
-                self.memorize_type(&param_type);
-            }
-            params.push(param_type);
-        }
+/// Demangles a name that is not necessarily valid UTF-8 into a `String`.
+///
+/// Bytes of the name that are not valid UTF-8 are replaced with U+FFFD,
+/// as `String::from_utf8_lossy` does.  Use `demangle_bytes` to keep them.
+pub fn demangle_bytes_to_string(input: &[u8], flags: DemangleFlags) -> Result<String> {
+    let demangled = demangle_bytes(input, flags)?;
+    Ok(String::from_utf8_lossy(&demangled).into_owned())
+}
 
-        if self.consume(b"Z") {
-            params.push(Type::VarArgs);
-        } else if self.remaining.is_empty() {
-            // this is needed to handle the weird standalone template manglings
-        } else {
-            self.expect(b"@")?;
+/// Demangles a name, enforcing all the limits in `options`.
+pub fn demangle_with_options(
+    input: &str,
+    flags: DemangleFlags,
+    options: ParseOptions,
+) -> Result<String> {
+    let parsed = parse_with_options(input, options)?;
+    let demangled = serialize_with_limit(&parsed, flags, options.max_output_len)?;
+    Ok(String::from_utf8(demangled)?)
+}
+
+/// Serializes a parsed name and records which part of the output shows
+/// what.
+///
+/// Spans are ordered by where they start, and a span comes before the
+/// spans nested inside it.  Names and scopes appear wherever a qualified
+/// name is written; those of the symbol itself are the ones not inside
+/// another span.
+///
+/// ```
+/// use msvc_demangler::{parse, serialize_with_spans, DemangleFlags, OutputSpanKind};
+///
+/// let parsed = parse("?foo@Bar@@QBEHH@Z").unwrap();
+/// let (s, spans) = serialize_with_spans(&parsed, DemangleFlags::llvm()).unwrap();
+/// assert_eq!(s, "public: int __thiscall Bar::foo(int) const");
+/// let params = spans
+///     .iter()
+///     .find(|span| span.kind == OutputSpanKind::Parameters)
+///     .unwrap();
+/// assert_eq!(&s[params.range.clone()], "(int)");
+/// ```
+pub fn serialize_with_spans(
+    input: &ParseResult,
+    flags: DemangleFlags,
+) -> Result<(String, Vec<OutputSpan>)> {
+    let mut s = Vec::new();
+    let mut spans = {
+        let mut serializer = Serializer::new(&mut s, flags, None);
+        serializer.spans = Some(Vec::new());
+        serializer.serialize(input)?;
+        serializer.spans.unwrap_or_default()
+    };
+    // Spaces between the parts are written by whichever part comes later,
+    // so trim them off.
+    for span in &mut spans {
+        while span.range.start < span.range.end && s[span.range.start] == b' ' {
+            span.range.start += 1;
+        }
+        while span.range.start < span.range.end && s[span.range.end - 1] == b' ' {
+            span.range.end -= 1;
         }
-        Ok(Params { types: params })
     }
+    spans.retain(|span| !span.range.is_empty());
+    Ok((String::from_utf8(s)?, spans))
+}
 
-    // Reads a function parameters.
-    fn read_func_params(&mut self) -> Result<Params<'a>> {
-        let params = if self.consume(b"X") {
-            Params {
-                types: vec![Type::Void(StorageClass::empty())],
-            }
-        } else {
-            self.read_params()?
-        };
+/// A part of the output of `serialize_with_spans`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSpan {
+    pub kind: OutputSpanKind,
+    /// Byte offsets into the serialized string.
+    pub range: Range<usize>,
+}
 
-        self.expect(b"Z")?;
+/// What an `OutputSpan` shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputSpanKind {
+    /// Access specifiers and member function kinds, such as `public:
+    /// virtual`.
+    Specifiers,
+    /// A return type.  Function pointers in return position are split
+    /// around the rest of the declaration, so there may be two.
+    ReturnType,
+    CallingConv,
+    /// The namespaces and classes a name is qualified with, such as
+    /// `ns::Foo` in `ns::Foo::bar`.
+    Scope,
+    /// An unqualified name, including its template arguments.
+    Name,
+    /// A function parameter list, including the parentheses.
+    Parameters,
+    Parameter,
+    /// A template argument list, including the angle brackets.
+    TemplateArguments,
+    TemplateArgument,
+    /// The `const`, `volatile` and reference qualifiers of a type or of
+    /// `this`.
+    Qualifiers,
+}
 
-        Ok(params)
+fn serialize_with_limit(
+    input: &ParseResult,
+    flags: DemangleFlags,
+    max_len: Option<usize>,
+) -> Result<Vec<u8>> {
+    let mut s = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut s, flags, max_len);
+        serializer.serialize(input)?;
+        serializer.check_len()?;
     }
+    Ok(s)
 }
 
-pub fn demangle(input: &str, flags: DemangleFlags) -> Result<String> {
-    serialize(&parse(input)?, flags)
+// Renders a memorized name for diagnostics.
+fn name_to_string(name: &Name) -> String {
+    diagnostic_entry(|serializer| serializer.write_one_name(name))
 }
 
-pub fn parse(input: &str) -> Result<ParseResult> {
-    let mut state = ParserState {
-        remaining: input.as_bytes(),
-        input,
-        offset: 0,
-        memorized_names: Vec::with_capacity(10),
-        memorized_types: Vec::with_capacity(10),
-    };
-    state.parse()
+// Writes scope names, innermost first, as `Outer::Inner`.
+fn scope_to_string(names: &[Name]) -> Result<String> {
+    let mut s = Vec::new();
+    {
+        let mut serializer = Serializer::new(&mut s, DemangleFlags::llvm(), None);
+        for (i, name) in names.iter().rev().enumerate() {
+            if i > 0 {
+                write!(serializer.w, "::")?;
+            }
+            serializer.write_one_name(name)?;
+        }
+    }
+    Ok(String::from_utf8(s)?.trim().to_string())
 }
 
-pub fn serialize(input: &ParseResult, flags: DemangleFlags) -> Result<String> {
+// Collects the names of the class types used in a symbol.
+struct ClassTypes<'ast>(Vec<&'ast Symbol<'ast>>);
+
+impl<'ast> Visit<'ast> for ClassTypes<'ast> {
+    fn visit_type(&mut self, t: &'ast Type<'ast>) {
+        match *t {
+            Type::Class(ref symbol, _)
+            | Type::Struct(ref symbol, _)
+            | Type::Union(ref symbol, _)
+            | Type::MemberFunctionPointer(ref symbol, ..)
+            | Type::MemberDataPointer(ref symbol, ..) => self.0.push(symbol),
+            _ => {}
+        }
+        visit::walk_type(self, t);
+    }
+}
+
+// Renders a memorized type for diagnostics.
+fn type_to_string(t: &Type) -> String {
+    diagnostic_entry(|serializer| {
+        serializer.write_pre(t)?;
+        serializer.write_post(t)
+    })
+}
+
+// Runs `f` on a serializer that stops shortly after
+// `MAX_DIAGNOSTIC_ENTRY_LEN` bytes, and cuts off the output there.
+fn diagnostic_entry<F>(f: F) -> String
+where
+    F: FnOnce(&mut Serializer<&mut Vec<u8>>) -> Result<()>,
+{
     let mut s = Vec::new();
-    {
-        let mut serializer = Serializer { flags, w: &mut s };
-        serializer.serialize(&input)?;
+    let rv = f(&mut Serializer::new(
+        &mut s,
+        DemangleFlags::llvm(),
+        Some(MAX_DIAGNOSTIC_ENTRY_LEN),
+    ));
+    let truncated = s.len() > MAX_DIAGNOSTIC_ENTRY_LEN;
+    if rv.is_err() && !truncated {
+        return "<?>".to_string();
+    }
+    let s = String::from_utf8_lossy(&s[..s.len().min(MAX_DIAGNOSTIC_ENTRY_LEN)]);
+    let mut s = s.trim().to_string();
+    if truncated {
+        s.push_str("...");
     }
-    Ok(String::from_utf8(s)?)
+    s
 }
 
 // Converts an AST to a string.
@@ -1539,12 +3546,116 @@ pub fn serialize(input: &ParseResult, flags: DemangleFlags) -> Result<String> {
 // the "first half" of type declaration, and write_post() writes the
 // "second half". For example, write_pre() writes a return type for a
 // function and write_post() writes an parameter list.
-struct Serializer<'a> {
+struct Serializer<W> {
     flags: DemangleFlags,
-    w: &'a mut Vec<u8>,
+    w: Output<W>,
+    max_len: Option<usize>,
+    // Set by `serialize_with_spans`.
+    spans: Option<Vec<OutputSpan>>,
+}
+
+// The sink the serializer writes to.  Where spaces go depends on what was
+// written last, so remember that.
+struct Output<W> {
+    inner: W,
+    len: usize,
+    last: Option<u8>,
+}
+
+impl<W> Output<W> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn last(&self) -> Option<&u8> {
+        self.last.as_ref()
+    }
+}
+
+impl<W: io::Write> io::Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.len += n;
+            self.last = Some(buf[n - 1]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Adapts a `fmt::Write` for the serializer.  Names are always written in
+// one piece, so converting each write on its own is fine.
+struct FmtWriter<W>(W);
+
+impl<W: fmt::Write> io::Write for FmtWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_str(&String::from_utf8_lossy(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "formatter error"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-impl<'a> Serializer<'a> {
+impl<W: io::Write> Serializer<W> {
+    fn new(w: W, flags: DemangleFlags, max_len: Option<usize>) -> Serializer<W> {
+        Serializer {
+            flags,
+            w: Output {
+                inner: w,
+                len: 0,
+                last: None,
+            },
+            max_len,
+            spans: None,
+        }
+    }
+
+    // Runs `f` and, when spans are recorded, records what it wrote as a
+    // span of `kind`.
+    fn span<F>(&mut self, kind: OutputSpanKind, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        if self.spans.is_none() {
+            return f(self);
+        }
+        let index = self.spans.as_ref().map_or(0, Vec::len);
+        let start = self.w.len();
+        f(self)?;
+        let end = self.w.len();
+        if let Some(ref mut spans) = self.spans {
+            if end > start {
+                spans.insert(
+                    index,
+                    OutputSpan {
+                        kind,
+                        range: start..end,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Fails once the output has grown beyond `max_len`.  This is checked
+    // on every name and type, so we stop shortly after the limit.
+    fn check_len(&self) -> Result<()> {
+        match self.max_len {
+            Some(max) if self.w.len() > max => Err(Error::new_serialize_error(
+                ErrorKind::OutputLimitExceeded(max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
     fn serialize(&mut self, parse_result: &ParseResult) -> Result<()> {
         if !self
             .flags
@@ -1559,86 +3670,110 @@ impl<'a> Serializer<'a> {
         Ok(())
     }
 
+    // Writes a symbol nested in a name.  It is spaced as if it was written
+    // on its own.
+    fn write_nested(&mut self, parse_result: &ParseResult) -> Result<()> {
+        let last = self.w.last.take();
+        let len = self.w.len();
+        self.serialize(parse_result)?;
+        if self.w.len() == len {
+            self.w.last = last;
+        }
+        Ok(())
+    }
+
     fn write_calling_conv(&mut self, calling_conv: CallingConv) -> Result<()> {
         match self.w.last() {
             Some(b' ') | Some(b'(') => {}
             _ => write!(self.w, " ")?,
         }
-        if !self.flags.contains(DemangleFlags::NO_MS_KEYWORDS) {
+        if self.flags.contains(DemangleFlags::NO_MS_KEYWORDS) {
+            return Ok(());
+        }
+        self.span(OutputSpanKind::CallingConv, |this| {
             match calling_conv {
                 CallingConv::Cdecl => {
-                    write!(self.w, "__cdecl ")?;
+                    write!(this.w, "__cdecl ")?;
                 }
                 CallingConv::Pascal => {
-                    write!(self.w, "__pascal ")?;
+                    write!(this.w, "__pascal ")?;
                 }
                 CallingConv::Thiscall => {
-                    write!(self.w, "__thiscall ")?;
+                    write!(this.w, "__thiscall ")?;
                 }
                 CallingConv::Stdcall => {
-                    write!(self.w, "__stdcall ")?;
+                    write!(this.w, "__stdcall ")?;
                 }
                 CallingConv::Fastcall => {
-                    write!(self.w, "__fastcall ")?;
+                    write!(this.w, "__fastcall ")?;
+                }
+                CallingConv::Vectorcall => {
+                    write!(this.w, "__vectorcall ")?;
                 }
                 CallingConv::_Regcall => {
-                    write!(self.w, "__regcall ")?;
+                    write!(this.w, "__regcall ")?;
                 }
             };
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 
     // Write the "first half" of a given type.
     fn write_pre(&mut self, t: &Type) -> Result<()> {
+        self.check_len()?;
         let storage_class = match *t {
             Type::None => return Ok(()),
-            Type::MemberFunction(func_class, calling_conv, _, _, ref inner) => {
-                if func_class.contains(FuncClass::THUNK) {
-                    write!(self.w, "[thunk]: ")?
-                }
-                if !self.flags.contains(DemangleFlags::NO_ACCESS_SPECIFIERS) {
-                    if func_class.contains(FuncClass::PRIVATE) {
-                        write!(self.w, "private: ")?
-                    }
-                    if func_class.contains(FuncClass::PROTECTED) {
-                        write!(self.w, "protected: ")?
+            Type::MemberFunction(func_class, ref sig) => {
+                self.span(OutputSpanKind::Specifiers, |this| {
+                    if func_class.contains(FuncClass::THUNK) {
+                        write!(this.w, "[thunk]: ")?
                     }
-                    if func_class.contains(FuncClass::PUBLIC) {
-                        write!(self.w, "public: ")?
-                    }
-                }
-                if !self.flags.contains(DemangleFlags::NO_MEMBER_TYPE) {
-                    if func_class.contains(FuncClass::STATIC) {
-                        write!(self.w, "static ")?
+                    if !this.flags.contains(DemangleFlags::NO_ACCESS_SPECIFIERS) {
+                        if func_class.contains(FuncClass::PRIVATE) {
+                            write!(this.w, "private: ")?
+                        }
+                        if func_class.contains(FuncClass::PROTECTED) {
+                            write!(this.w, "protected: ")?
+                        }
+                        if func_class.contains(FuncClass::PUBLIC) {
+                            write!(this.w, "public: ")?
+                        }
                     }
-                    if func_class.contains(FuncClass::VIRTUAL) {
-                        write!(self.w, "virtual ")?;
+                    if !this.flags.contains(DemangleFlags::NO_MEMBER_TYPE) {
+                        if func_class.contains(FuncClass::STATIC) {
+                            write!(this.w, "static ")?
+                        }
+                        if func_class.contains(FuncClass::VIRTUAL) {
+                            write!(this.w, "virtual ")?;
+                        }
                     }
-                }
-                self.write_pre(inner)?;
-                self.write_calling_conv(calling_conv)?;
+                    Ok(())
+                })?;
+                self.write_return_type_pre(&sig.return_type)?;
+                self.write_calling_conv(sig.calling_conv)?;
                 return Ok(());
             }
-            Type::MemberFunctionPointer(ref symbol, _, calling_conv, _, _, ref inner) => {
-                self.write_pre(inner)?;
+            Type::MemberFunctionPointer(ref symbol, _, ref sig) => {
+                self.write_return_type_pre(&sig.return_type)?;
                 self.write_space()?;
                 write!(self.w, "(")?;
-                self.write_calling_conv(calling_conv)?;
+                self.write_calling_conv(sig.calling_conv)?;
                 self.write_space()?;
                 self.write_space()?;
                 self.write_name(symbol, None)?;
                 write!(self.w, "::*")?;
                 return Ok(());
             }
-            Type::NonMemberFunction(calling_conv, _, _, ref inner) => {
-                self.write_pre(inner)?;
-                self.write_calling_conv(calling_conv)?;
+            Type::NonMemberFunction(ref sig) => {
+                self.write_return_type_pre(&sig.return_type)?;
+                self.write_calling_conv(sig.calling_conv)?;
                 return Ok(());
             }
             Type::VCallThunk(_, calling_conv) => {
-                write!(self.w, "[thunk]: ")?;
+                self.span(OutputSpanKind::Specifiers, |this| {
+                    write!(this.w, "[thunk]: ")?;
+                    Ok(())
+                })?;
                 self.write_calling_conv(calling_conv)?;
                 return Ok(());
             }
@@ -1677,12 +3812,11 @@ impl<'a> Serializer<'a> {
                 // parentheses to supercede the default precedence. (e.g. we want to
                 // emit something like "int (*x)(int)".)
                 match *inner.as_ref() {
-                    Type::MemberFunction(_, calling_conv, _, _, ref inner)
-                    | Type::NonMemberFunction(calling_conv, _, _, ref inner) => {
-                        self.write_pre(inner)?;
+                    Type::MemberFunction(_, ref sig) | Type::NonMemberFunction(ref sig) => {
+                        self.write_return_type_pre(&sig.return_type)?;
                         self.write_space()?;
                         write!(self.w, "(")?;
-                        self.write_calling_conv(calling_conv)?;
+                        self.write_calling_conv(sig.calling_conv)?;
                     }
                     Type::Array(_, _, _) => {
                         self.write_pre(inner)?;
@@ -1724,23 +3858,44 @@ impl<'a> Serializer<'a> {
 
                 storage_class
             }
+            Type::MemberDataPointer(ref symbol, ref inner, storage_class) => {
+                self.write_pre(inner)?;
+                self.write_space()?;
+                if let Type::Array(..) = **inner {
+                    write!(self.w, "(")?;
+                }
+                self.write_name(symbol, None)?;
+                write!(self.w, "::*")?;
+                storage_class
+            }
             Type::Array(_len, ref inner, storage_class) => {
                 self.write_pre(inner)?;
                 storage_class
             }
             Type::Var(ref inner, kind, sc) => {
-                match kind {
-                    VarStorageKind::PrivateStatic => write!(self.w, "private: static ")?,
-                    VarStorageKind::ProtectedStatic => write!(self.w, "protected: static ")?,
-                    VarStorageKind::PublicStatic => write!(self.w, "public: static ")?,
-                    VarStorageKind::Global | VarStorageKind::FunctionLocalStatic => {}
-                }
+                self.span(OutputSpanKind::Specifiers, |this| {
+                    match kind {
+                        VarStorageKind::PrivateStatic => write!(this.w, "private: static ")?,
+                        VarStorageKind::ProtectedStatic => write!(this.w, "protected: static ")?,
+                        VarStorageKind::PublicStatic => write!(this.w, "public: static ")?,
+                        VarStorageKind::Global | VarStorageKind::FunctionLocalStatic => {}
+                    }
+                    Ok(())
+                })?;
                 self.write_pre(inner)?;
-                sc
+                match **inner {
+                    // For pointers and references the trailing qualifiers
+                    // repeat those of the pointee.
+                    Type::Ptr(..)
+                    | Type::MemberDataPointer(..)
+                    | Type::Ref(..)
+                    | Type::RValueRef(..) => StorageClass::empty(),
+                    _ => sc,
+                }
             }
-            Type::Alias(ref names, sc) => {
+            Type::TemplateTemplate(ref names) => {
                 self.write_name(names, None)?;
-                sc
+                return Ok(());
             }
             Type::Struct(ref names, sc) => {
                 self.write_class(names, "struct")?;
@@ -1866,28 +4021,50 @@ impl<'a> Serializer<'a> {
                 write!(self.w, "std::nullptr_t")?;
                 return Ok(());
             }
-            Type::EmptyParameterPack => return Ok(()),
+            Type::ParameterPack(_) => return Ok(()),
             Type::RTTIType => return Ok(()),
+            Type::Unknown => {
+                write!(self.w, "<?>")?;
+                return Ok(());
+            }
         };
 
-        if storage_class.contains(StorageClass::CONST) {
-            if self.flags.contains(DemangleFlags::SPACE_BEFORE_POINTER) {
-                self.write_space_ptr()?;
-            } else {
-                self.write_space()?;
+        self.span(OutputSpanKind::Qualifiers, |this| {
+            if storage_class.contains(StorageClass::CONST) {
+                if this.flags.contains(DemangleFlags::SPACE_BEFORE_POINTER) {
+                    this.write_space_ptr()?;
+                } else {
+                    this.write_space()?;
+                }
+                write!(this.w, "const")?;
             }
-            write!(self.w, "const")?;
-        }
-        if storage_class.contains(StorageClass::VOLATILE) {
-            if self.flags.contains(DemangleFlags::SPACE_BEFORE_POINTER) {
-                self.write_space_ptr()?;
-            } else {
-                self.write_space()?;
+            if storage_class.contains(StorageClass::VOLATILE) {
+                if this.flags.contains(DemangleFlags::SPACE_BEFORE_POINTER) {
+                    this.write_space_ptr()?;
+                } else {
+                    this.write_space()?;
+                }
+                write!(this.w, "volatile")?;
             }
-            write!(self.w, "volatile")?;
-        }
+            Ok(())
+        })
+    }
 
-        Ok(())
+    fn write_return_type_pre(&mut self, t: &Type) -> Result<()> {
+        self.span(OutputSpanKind::ReturnType, |this| this.write_pre(t))
+    }
+
+    fn write_return_type_post(&mut self, t: &Type) -> Result<()> {
+        self.span(OutputSpanKind::ReturnType, |this| this.write_post(t))
+    }
+
+    fn write_params(&mut self, params: &Params) -> Result<()> {
+        self.span(OutputSpanKind::Parameters, |this| {
+            write!(this.w, "(")?;
+            this.write_types(&params.types, OutputSpanKind::Parameter)?;
+            write!(this.w, ")")?;
+            Ok(())
+        })
     }
 
     fn write_memfn_qualifiers(&mut self, sc: StorageClass) -> Result<()> {
@@ -1897,51 +4074,58 @@ impl<'a> Serializer<'a> {
             // separately but I don't know what exactly those affect.
             return Ok(());
         }
-        let mut write_one_qual = |flag, s| -> Result<()> {
-            if sc.contains(flag) {
-                self.write_space()?;
-                self.w.write_all(s)?;
-            }
+        self.span(OutputSpanKind::Qualifiers, |this| {
+            let mut write_one_qual = |flag, s| -> Result<()> {
+                if sc.contains(flag) {
+                    this.write_space()?;
+                    this.w.write_all(s)?;
+                }
 
-            Ok(())
-        };
+                Ok(())
+            };
 
-        write_one_qual(StorageClass::CONST, b"const")?;
-        write_one_qual(StorageClass::VOLATILE, b"volatile")?;
-        if with_ptr64 {
-            write_one_qual(StorageClass::PTR64, b"__ptr64")?;
-        }
-        // __restrict is different than `restrict`, keep the underscores!
-        write_one_qual(StorageClass::RESTRICT, b"__restrict")?;
-        // TODO: undname prints ref-qualifiers tightly to previous qualifiers.
-        write_one_qual(StorageClass::LVALUE_QUAL, b"&")?;
-        write_one_qual(StorageClass::RVALUE_QUAL, b"&&")?;
+            write_one_qual(StorageClass::CONST, b"const")?;
+            write_one_qual(StorageClass::VOLATILE, b"volatile")?;
+            if with_ptr64 {
+                write_one_qual(StorageClass::PTR64, b"__ptr64")?;
+            }
+            // __restrict is different than `restrict`, keep the underscores!
+            write_one_qual(StorageClass::RESTRICT, b"__restrict")?;
+            // TODO: undname prints ref-qualifiers tightly to previous qualifiers.
+            write_one_qual(StorageClass::LVALUE_QUAL, b"&")?;
+            write_one_qual(StorageClass::RVALUE_QUAL, b"&&")?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     // Write the "second half" of a given type.
     fn write_post(&mut self, t: &Type) -> Result<()> {
         match *t {
-            Type::MemberFunction(_, _, ref params, sc, ref return_type)
-            | Type::NonMemberFunction(_, ref params, sc, ref return_type) => {
-                write!(self.w, "(")?;
-                self.write_types(&params.types)?;
-                write!(self.w, ")")?;
+            Type::MemberFunction(_, ref sig) | Type::NonMemberFunction(ref sig) => {
+                self.write_params(&sig.params)?;
 
-                self.write_memfn_qualifiers(sc)?;
-                self.write_post(return_type)?;
+                self.write_memfn_qualifiers(sig.this_quals)?;
+                if sig.noexcept {
+                    write!(self.w, " noexcept")?;
+                }
+                self.write_return_type_post(&sig.return_type)?;
             }
-            Type::MemberFunctionPointer(_, _, _, ref params, sc, ref return_type) => {
-                write!(self.w, ")(")?;
-                self.write_types(&params.types)?;
+            Type::MemberFunctionPointer(_, _, ref sig) => {
                 write!(self.w, ")")?;
+                self.write_params(&sig.params)?;
 
-                self.write_post(return_type)?;
+                self.write_return_type_post(&sig.return_type)?;
 
-                if sc.contains(StorageClass::CONST) {
-                    self.write_space()?;
-                    write!(self.w, "const")?;
+                if sig.this_quals.contains(StorageClass::CONST) {
+                    self.span(OutputSpanKind::Qualifiers, |this| {
+                        this.write_space()?;
+                        write!(this.w, "const")?;
+                        Ok(())
+                    })?;
+                }
+                if sig.noexcept {
+                    write!(self.w, " noexcept")?;
                 }
             }
             Type::CXXVBTable(ref names, _sc) => {
@@ -1950,15 +4134,19 @@ impl<'a> Serializer<'a> {
             }
             Type::Ptr(ref inner, _sc) | Type::Ref(ref inner, _sc) => {
                 match *inner.as_ref() {
-                    Type::MemberFunction(_, _, _, _, _)
-                    | Type::NonMemberFunction(_, _, _, _)
-                    | Type::Array(_, _, _) => {
+                    Type::MemberFunction(..) | Type::NonMemberFunction(..) | Type::Array(..) => {
                         write!(self.w, ")")?;
                     }
                     _ => {}
                 }
                 self.write_post(inner)?;
             }
+            Type::MemberDataPointer(_, ref inner, _sc) => {
+                if let Type::Array(..) = **inner {
+                    write!(self.w, ")")?;
+                }
+                self.write_post(inner)?;
+            }
             Type::Array(len, ref inner, _sc) => {
                 write!(self.w, "[{}]", len)?;
                 self.write_post(inner)?;
@@ -1966,12 +4154,10 @@ impl<'a> Serializer<'a> {
             Type::Var(ref inner, _kind, _sc) => {
                 self.write_post(inner)?;
             }
-            Type::CXXVFTable(ref names, _) => {
-                if !names.names.is_empty() {
-                    write!(self.w, "{{for `")?;
-                    self.write_scope(names)?;
-                    self.w.write_all(b"'}")?;
-                }
+            Type::CXXVFTable(ref names, _) if !names.names.is_empty() => {
+                write!(self.w, "{{for `")?;
+                self.write_scope(names)?;
+                self.w.write_all(b"'}")?;
             }
             Type::VCallThunk(offset, _) => {
                 write!(self.w, "{{{},", offset)?;
@@ -1985,11 +4171,12 @@ impl<'a> Serializer<'a> {
         Ok(())
     }
 
-    // Write a function or template parameter list.
-    fn write_types(&mut self, types: &[Type]) -> Result<()> {
+    // Write a function or template parameter list.  Each entry is a span
+    // of `kind`.
+    fn write_types(&mut self, types: &[Type], kind: OutputSpanKind) -> Result<()> {
         for (idx, param) in types
             .iter()
-            .filter(|x| **x != Type::EmptyParameterPack)
+            .filter(|x| !matches!(**x, Type::ParameterPack(_)))
             .enumerate()
         {
             if idx > 0 {
@@ -1998,8 +4185,10 @@ impl<'a> Serializer<'a> {
                     write!(self.w, " ")?;
                 }
             }
-            self.write_pre(param)?;
-            self.write_post(param)?;
+            self.span(kind, |this| {
+                this.write_pre(param)?;
+                this.write_post(param)
+            })?;
         }
         Ok(())
     }
@@ -2168,6 +4357,7 @@ impl<'a> Serializer<'a> {
     }
 
     fn write_one_name(&mut self, name: &Name) -> Result<()> {
+        self.check_len()?;
         match *name {
             Name::Operator(ref op) => {
                 self.write_space()?;
@@ -2178,17 +4368,22 @@ impl<'a> Serializer<'a> {
             }
             Name::Template(ref name, ref params) => {
                 self.write_one_name(name)?;
-                self.write_tmpl_params(&params)?;
+                self.write_tmpl_params(params)?;
             }
             Name::Discriminator(ref val) => {
                 write!(self.w, "`{}'", val)?;
             }
             Name::ParsedName(ref val) => {
-                write!(self.w, "`{}'", serialize(val, self.flags)?)?;
+                write!(self.w, "`")?;
+                self.write_nested(val)?;
+                write!(self.w, "'")?;
             }
             Name::AnonymousNamespace(_) => {
                 write!(self.w, "`anonymous namespace'")?;
             }
+            Name::Unknown => {
+                write!(self.w, "<?>")?;
+            }
         }
         Ok(())
     }
@@ -2197,17 +4392,18 @@ impl<'a> Serializer<'a> {
         // Print out namespaces or outer class names.
         let mut i = names.names.iter().rev();
         if let Some(name) = i.next() {
-            self.write_one_name(&name)?;
+            self.write_one_name(name)?;
         }
         for name in i {
             write!(self.w, "::")?;
-            self.write_one_name(&name)?;
+            self.write_one_name(name)?;
         }
         Ok(())
     }
 
     // Write a name read by read_name().
     fn write_name(&mut self, names: &Symbol, ty: Option<&Type<'_>>) -> Result<()> {
+        self.check_len()?;
         if !self.flags.contains(DemangleFlags::SPACE_BEFORE_POINTER) {
             self.write_space_pre()?;
         } else {
@@ -2221,17 +4417,24 @@ impl<'a> Serializer<'a> {
             was_literal_op = true;
         }
 
-        self.write_scope(&names.scope)?;
+        self.span(OutputSpanKind::Scope, |this| this.write_scope(&names.scope))?;
 
         if !names.scope.names.is_empty() && !was_literal_op {
             write!(self.w, "::")?;
         }
 
+        self.span(OutputSpanKind::Name, |this| {
+            this.write_unqualified_name(names, ty)
+        })
+    }
+
+    // Write the last component of a name read by read_name().
+    fn write_unqualified_name(&mut self, names: &Symbol, ty: Option<&Type<'_>>) -> Result<()> {
         match names.name {
             Name::Operator(ref op) => {
                 match *op {
                     Operator::Ctor => {
-                        let prev = names.scope.names.iter().next().ok_or_else(|| {
+                        let prev = names.scope.names.first().ok_or_else(|| {
                             Error::new(
                                 "If there's a ctor, there should be another name in this sequence",
                             )
@@ -2239,7 +4442,7 @@ impl<'a> Serializer<'a> {
                         self.write_one_name(prev)?;
                     }
                     Operator::Dtor => {
-                        let prev = names.scope.names.iter().next().ok_or_else(|| {
+                        let prev = names.scope.names.first().ok_or_else(|| {
                             Error::new(
                                 "If there's a dtor, there should be another name in this sequence",
                             )
@@ -2253,10 +4456,10 @@ impl<'a> Serializer<'a> {
                         // symbol type.
                     }
                     Operator::Conversion => {
-                        if let Some(Type::MemberFunction(_, _, _, _, ref rv)) = ty {
+                        if let Some(Type::MemberFunction(_, ref sig)) = ty {
                             write!(self.w, "operator ")?;
-                            self.write_pre(rv)?;
-                            self.write_post(rv)?;
+                            self.write_pre(&sig.return_type)?;
+                            self.write_post(&sig.return_type)?;
                         } else {
                             self.write_space()?;
                             self.write_operator_name(op)?;
@@ -2275,32 +4478,38 @@ impl<'a> Serializer<'a> {
             }
             Name::Template(ref name, ref params) => {
                 self.write_one_name(name)?;
-                self.write_tmpl_params(&params)?;
+                self.write_tmpl_params(params)?;
             }
             Name::Discriminator(ref val) => {
                 write!(self.w, "`{}'", val)?;
             }
             Name::ParsedName(ref val) => {
-                write!(self.w, "{}", serialize(val, self.flags)?)?;
+                self.write_nested(val)?;
             }
             Name::AnonymousNamespace(_) => {
-                // this should never happen as they are handled elsewhere
-                debug_assert!(false, "not supposed to be here");
+                // Only reachable through a backreference to an anonymous
+                // namespace.
+                write!(self.w, "`anonymous namespace'")?;
+            }
+            Name::Unknown => {
+                write!(self.w, "<?>")?;
             }
         }
         Ok(())
     }
 
     fn write_tmpl_params<'b>(&mut self, params: &Params<'b>) -> Result<()> {
-        write!(self.w, "<")?;
-        if !params.types.is_empty() {
-            self.write_types(&params.types)?;
-            if let Some(&b'>') = self.w.last() {
-                write!(self.w, " ")?;
+        self.span(OutputSpanKind::TemplateArguments, |this| {
+            write!(this.w, "<")?;
+            if !params.types.is_empty() {
+                this.write_types(&params.types, OutputSpanKind::TemplateArgument)?;
+                if let Some(&b'>') = this.w.last() {
+                    write!(this.w, " ")?;
+                }
             }
-        }
-        write!(self.w, ">")?;
-        Ok(())
+            write!(this.w, ">")?;
+            Ok(())
+        })
     }
 }
 
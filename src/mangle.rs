@@ -0,0 +1,874 @@
+//! Encoding of the AST back into mangled names.
+//!
+//! The mangler mirrors the parser: it keeps the same tables of memorized
+//! names and types, so it emits a backreference exactly where the parser
+//! would resolve one.
+
+use std::mem;
+
+use super::{
+    is_init_fini_stub, is_local_static_guard, CallingConv, Error, FuncClass, FunctionSignature,
+    Name, NameSequence, Operator, PackMarker, Params, ParseResult, Result, StorageClass,
+    StringLiteral, Symbol, Type, VarStorageKind,
+};
+
+/// Turns a parsed name back into an MSVC-mangled name.
+///
+/// Names and types are backreferenced and numbers are encoded the way
+/// MSVC does it, so names that MSVC emitted round-trip.  Other names that
+/// `parse` accepts, such as a number written in a longer form, come back
+/// in MSVC's form:
+///
+/// ```
+/// use msvc_demangler::{mangle, parse};
+///
+/// let input = "?f@ns@@YAXPAVFoo@@0@Z";
+/// assert_eq!(mangle(&parse(input).unwrap()).unwrap(), input);
+/// ```
+///
+/// Nodes that have no encoding, such as the `Unknown` placeholders of
+/// `parse_partial`, are an error.
+pub fn mangle(input: &ParseResult) -> Result<String> {
+    Ok(String::from_utf8(mangle_bytes(input)?)?)
+}
+
+/// Like `mangle` but keeps non-UTF-8 names as they are.
+pub fn mangle_bytes(input: &ParseResult) -> Result<Vec<u8>> {
+    let mut mangler = Mangler {
+        out: Vec::new(),
+        names: Vec::new(),
+        types: Vec::new(),
+    };
+    mangler.mangle(input)?;
+    Ok(mangler.out)
+}
+
+// The `??_C@` name of a string literal.
+pub fn mangle_string_literal(s: &StringLiteral) -> String {
+    let mut mangler = Mangler {
+        out: Vec::new(),
+        names: Vec::new(),
+        types: Vec::new(),
+    };
+    mangler.write(b"??_C@");
+    mangler.write_string_literal(s);
+    // Everything but identifier characters is escaped.
+    String::from_utf8(mangler.out).expect("string literal names are ASCII")
+}
+
+struct Mangler<'b, 'a: 'b> {
+    out: Vec<u8>,
+    names: Vec<&'b Name<'a>>,
+    types: Vec<&'b Type<'a>>,
+}
+
+impl<'b, 'a: 'b> Mangler<'b, 'a> {
+    fn write(&mut self, s: &[u8]) {
+        self.out.extend_from_slice(s);
+    }
+
+    fn mangle(&mut self, parse_result: &'b ParseResult<'a>) -> Result<()> {
+        let symbol = &parse_result.symbol;
+        self.write(b"?");
+        match parse_result.symbol_type {
+            Type::ThreadSafeStaticGuard(num) => {
+                self.write(format!("$TSS{}@", num).as_bytes());
+                self.write_nested_name(&symbol.name)?;
+                self.write_scope(&symbol.scope)?;
+                self.write(b"4HA");
+                return Ok(());
+            }
+            Type::None if is_template_only(symbol) => {
+                self.write(b"$");
+                return self.write_template_name(&symbol.name);
+            }
+            _ => {}
+        }
+
+        self.write_symbol(symbol, true)?;
+        match symbol.name {
+            Name::Operator(Operator::LocalStaticGuard(scope_index))
+            | Name::Operator(Operator::LocalStaticThreadGuard(scope_index)) => match scope_index {
+                Some(index) => {
+                    self.write(b"5");
+                    self.write_unsigned(index);
+                }
+                None => self.write(b"4IA"),
+            },
+            _ => {}
+        }
+
+        match parse_result.symbol_type {
+            Type::None if is_local_static_guard(&symbol.name) => {}
+            ref t => self.write_type_encoding(t)?,
+        }
+        if let Type::Var(..) = parse_result.symbol_type {
+            if is_init_fini_stub(&symbol.name) {
+                // The stub of a variable is followed by the encoding of the
+                // stub function, which is always `void __cdecl(void)`.
+                self.write(b"@YAXXZ");
+            }
+        }
+        Ok(())
+    }
+
+    // The counterpart of `ParserState::read_type_encoding`.
+    fn write_type_encoding(&mut self, t: &'b Type<'a>) -> Result<()> {
+        match *t {
+            Type::Var(ref ty, kind, sc) => {
+                self.write(match kind {
+                    VarStorageKind::PrivateStatic => b"0",
+                    VarStorageKind::ProtectedStatic => b"1",
+                    VarStorageKind::PublicStatic => b"2",
+                    VarStorageKind::Global => b"3",
+                    VarStorageKind::FunctionLocalStatic => b"4",
+                });
+                self.write_var_type(ty, StorageClass::empty())?;
+                if sc.contains(StorageClass::PTR64) {
+                    self.write(b"E");
+                }
+                match **ty {
+                    // Pointers to members repeat their class.
+                    Type::MemberFunctionPointer(ref symbol, ..)
+                    | Type::MemberDataPointer(ref symbol, ..) => {
+                        self.write_member_qualifier(sc);
+                        self.write_symbol(symbol, false)?;
+                    }
+                    _ => self.write_storage_class(sc),
+                }
+            }
+            Type::CXXVFTable(ref scope, sc) | Type::CXXVBTable(ref scope, sc) => {
+                self.write(if let Type::CXXVFTable(..) = *t {
+                    b"6"
+                } else {
+                    b"7"
+                });
+                self.write_qualifier(sc);
+                self.write_scope(scope)?;
+                if !scope.names.is_empty() {
+                    self.write(b"@");
+                }
+            }
+            Type::None => self.write(b"9"),
+            Type::NonMemberFunction(ref sig) => {
+                self.write(b"Y");
+                self.write_calling_conv(sig.calling_conv)?;
+                self.write_return_type(&sig.return_type)?;
+                self.write_func_params(sig)?;
+            }
+            Type::ConstantString(ref s) => self.write_string_literal(s),
+            Type::VCallThunk(offset, calling_conv) => {
+                self.write(b"$B");
+                self.write_number(offset);
+                self.write(b"A");
+                self.write_calling_conv(calling_conv)?;
+            }
+            Type::RTTIType => self.write(b"8"),
+            Type::MemberFunction(func_class, ref sig) => {
+                self.write_func_class(func_class, sig.this_adjustment)?;
+                if !func_class.contains(FuncClass::STATIC) {
+                    self.write_func_qualifiers(sig.this_quals);
+                }
+                self.write_calling_conv(sig.calling_conv)?;
+                self.write_return_type(&sig.return_type)?;
+                self.write_func_params(sig)?;
+            }
+            _ => return Err(unmangleable(t)),
+        }
+        Ok(())
+    }
+
+    fn write_string_literal(&mut self, s: &StringLiteral) {
+        self.write(if s.char_bytes == 1 { b"_0" } else { b"_1" });
+        self.write_number(s.byte_length);
+        self.write_unsigned(s.crc);
+        for &byte in &s.bytes {
+            match byte {
+                b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$' => self.out.push(byte),
+                _ if (byte & 0x7f).is_ascii_alphabetic() => {
+                    self.out.push(b'?');
+                    self.out.push(byte & 0x7f);
+                }
+                _ => match b",/\\:. \n\t'-".iter().position(|&c| c == byte) {
+                    Some(i) => {
+                        self.out.push(b'?');
+                        self.out.push(b'0' + i as u8);
+                    }
+                    None => {
+                        self.write(b"?$");
+                        self.out.push(b'A' + (byte >> 4));
+                        self.out.push(b'A' + (byte & 0xf));
+                    }
+                },
+            }
+        }
+        self.write(b"@");
+    }
+
+    // <number> ::= [?] <non-negative integer>, see
+    // `ParserState::read_number`.
+    fn write_number(&mut self, n: i32) {
+        if n < 0 {
+            self.write(b"?");
+        }
+        self.write_unsigned(n.unsigned_abs());
+    }
+
+    fn write_unsigned(&mut self, n: u32) {
+        if (1..=10).contains(&n) {
+            self.out.push(b'0' + (n - 1) as u8);
+            return;
+        }
+        let digits = if n == 0 { 1 } else { 8 - n.leading_zeros() / 4 };
+        for i in (0..digits).rev() {
+            self.out.push(b'A' + ((n >> (i * 4)) & 0xf) as u8);
+        }
+        self.write(b"@");
+    }
+
+    fn memorize_name(&mut self, name: &'b Name<'a>) {
+        if self.names.len() < 10 && !self.names.contains(&name) {
+            self.names.push(name);
+        }
+    }
+
+    fn write_name_backreference(&mut self, name: &Name<'a>) -> bool {
+        match self.names.iter().position(|&n| n == name) {
+            Some(i) => {
+                self.out.push(b'0' + i as u8);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Writes `?$` names without the prefix.  Templates have their own
+    // context for backreferences.
+    fn write_template_name(&mut self, name: &'b Name<'a>) -> Result<()> {
+        let (inner, params) = match *name {
+            Name::Template(ref inner, ref params) => (inner, params),
+            _ => return Err(Error::new("expected a template name")),
+        };
+        let saved_names = mem::take(&mut self.names);
+        let saved_types = mem::take(&mut self.types);
+        self.write_unqualified_name(inner, false)?;
+        self.write_template_args(params)?;
+        self.names = saved_names;
+        self.types = saved_types;
+        Ok(())
+    }
+
+    fn write_nested_name(&mut self, name: &'b Name<'a>) -> Result<()> {
+        if self.write_name_backreference(name) {
+            return Ok(());
+        }
+        match *name {
+            Name::ParsedName(ref parse_result) => {
+                self.write(b"?");
+                self.mangle(parse_result)?;
+            }
+            Name::Template(..) => {
+                self.write(b"?$");
+                self.write_template_name(name)?;
+                self.memorize_name(name);
+            }
+            Name::AnonymousNamespace(ref id) => {
+                self.write(b"?A");
+                if let Some(ref id) = *id {
+                    self.write(id.as_bytes());
+                    self.memorize_name(name);
+                }
+                self.write(b"@");
+            }
+            // `?A@` would be an anonymous namespace.
+            Name::Discriminator(0) => self.write(b"?@"),
+            Name::Discriminator(n) => {
+                self.write(b"?");
+                self.write_number(n);
+            }
+            Name::NonTemplate(ref s) => {
+                self.write_identifier(s)?;
+                self.memorize_name(name);
+            }
+            Name::Operator(_) | Name::Unknown => {
+                return Err(Error::new("name cannot be used as a scope"));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_unqualified_name(&mut self, name: &'b Name<'a>, function: bool) -> Result<()> {
+        if self.write_name_backreference(name) {
+            return Ok(());
+        }
+        match *name {
+            Name::Template(..) => {
+                self.write(b"?$");
+                self.write_template_name(name)?;
+                if !function {
+                    self.memorize_name(name);
+                }
+            }
+            Name::Operator(ref op) => {
+                self.write(b"?");
+                self.write_operator(op)?;
+            }
+            Name::NonTemplate(ref s) => {
+                self.write_identifier(s)?;
+                self.memorize_name(name);
+            }
+            _ => return Err(Error::new("name cannot be used as an unqualified name")),
+        }
+        Ok(())
+    }
+
+    fn write_identifier(&mut self, s: &[u8]) -> Result<()> {
+        if s.is_empty() || s.contains(&b'@') {
+            return Err(Error::new("identifier cannot be mangled"));
+        }
+        self.write(s);
+        self.write(b"@");
+        Ok(())
+    }
+
+    fn write_scope(&mut self, scope: &'b NameSequence<'a>) -> Result<()> {
+        for name in &scope.names {
+            self.write_nested_name(name)?;
+        }
+        self.write(b"@");
+        Ok(())
+    }
+
+    fn write_symbol(&mut self, symbol: &'b Symbol<'a>, function: bool) -> Result<()> {
+        self.write_unqualified_name(&symbol.name, function)?;
+        self.write_scope(&symbol.scope)
+    }
+
+    // The counterpart of `ParserState::read_special_name`.
+    fn write_operator(&mut self, op: &'b Operator<'a>) -> Result<()> {
+        let code: &[u8] = match *op {
+            Operator::Ctor => b"0",
+            Operator::Dtor => b"1",
+            Operator::New => b"2",
+            Operator::Delete => b"3",
+            Operator::Equal => b"4",
+            Operator::RShift => b"5",
+            Operator::LShift => b"6",
+            Operator::Bang => b"7",
+            Operator::EqualEqual => b"8",
+            Operator::BangEqual => b"9",
+            Operator::Subscript => b"A",
+            Operator::Conversion => b"B",
+            Operator::Arrow => b"C",
+            Operator::Star => b"D",
+            Operator::PlusPlus => b"E",
+            Operator::MinusMinus => b"F",
+            Operator::Minus => b"G",
+            Operator::Plus => b"H",
+            Operator::Amp => b"I",
+            Operator::ArrowStar => b"J",
+            Operator::Slash => b"K",
+            Operator::Percent => b"L",
+            Operator::Less => b"M",
+            Operator::LessEqual => b"N",
+            Operator::Greater => b"O",
+            Operator::GreaterEqual => b"P",
+            Operator::Comma => b"Q",
+            Operator::Call => b"R",
+            Operator::Tilde => b"S",
+            Operator::Caret => b"T",
+            Operator::Pipe => b"U",
+            Operator::AmpAmp => b"V",
+            Operator::PipePipe => b"W",
+            Operator::StarEqual => b"X",
+            Operator::PlusEqual => b"Y",
+            Operator::MinusEqual => b"Z",
+            Operator::SlashEqual => b"_0",
+            Operator::PercentEqual => b"_1",
+            Operator::GreaterGreaterEqual => b"_2",
+            Operator::LessLessEqual => b"_3",
+            Operator::AmpEqual => b"_4",
+            Operator::PipeEqual => b"_5",
+            Operator::CaretEqual => b"_6",
+            Operator::VFTable => b"_7",
+            Operator::VBTable => b"_8",
+            Operator::VCall => b"_9",
+            Operator::Typeof => b"_A",
+            Operator::LocalStaticGuard(_) => b"_B",
+            Operator::String => b"_C",
+            Operator::VBaseDtor => b"_D",
+            Operator::VectorDeletingDtor => b"_E",
+            Operator::DefaultCtorClosure => b"_F",
+            Operator::ScalarDeletingDtor => b"_G",
+            Operator::VectorCtorIterator => b"_H",
+            Operator::VectorDtorIterator => b"_I",
+            Operator::VectorVBaseCtorIterator => b"_J",
+            Operator::VirtualDisplacementMap => b"_K",
+            Operator::EHVectorCtorIterator => b"_L",
+            Operator::EHVectorDtorIterator => b"_M",
+            Operator::EHVectorVBaseCtorIterator => b"_N",
+            Operator::CopyCtorClosure => b"_O",
+            Operator::RTTITypeDescriptor(sc, ref t) => {
+                self.write(b"_R0?");
+                self.write_storage_class(sc);
+                return self.write_var_type(t, sc);
+            }
+            Operator::RTTIBaseClassDescriptor(nv_offset, vbptr_offset, vbtable_offset, flags) => {
+                self.write(b"_R1");
+                self.write_number(nv_offset);
+                self.write_number(vbptr_offset);
+                self.write_number(vbtable_offset);
+                self.write_number(flags);
+                return Ok(());
+            }
+            Operator::RTTIBaseClassArray => b"_R2",
+            Operator::RTTIClassHierarchyDescriptor => b"_R3",
+            Operator::RTTIClassCompleteObjectLocator => b"_R4",
+            Operator::LocalVFTable => b"_S",
+            Operator::LocalVFTableCtorClosure => b"_T",
+            Operator::ArrayNew => b"_U",
+            Operator::ArrayDelete => b"_V",
+            Operator::PlacementDeleteClosure => b"_X",
+            Operator::PlacementArrayDeleteClosure => b"_Y",
+            Operator::CoroutineAwait => b"__L",
+            Operator::DynamicInitializer => b"__E",
+            Operator::DynamicAtexitDtor => b"__F",
+            Operator::LocalStaticThreadGuard(_) => b"__J",
+            Operator::LiteralOperatorName => b"__K",
+        };
+        self.write(code);
+        Ok(())
+    }
+
+    fn write_func_class(&mut self, func_class: FuncClass, this_adjustment: i32) -> Result<()> {
+        let base = if func_class.contains(FuncClass::GLOBAL) {
+            b'Y'
+        } else {
+            let access = if func_class.contains(FuncClass::PUBLIC) {
+                b'Q'
+            } else if func_class.contains(FuncClass::PROTECTED) {
+                b'I'
+            } else if func_class.contains(FuncClass::PRIVATE) {
+                b'A'
+            } else {
+                return Err(Error::new("function class without access"));
+            };
+            access
+                + if func_class.contains(FuncClass::THUNK) {
+                    6
+                } else if func_class.contains(FuncClass::VIRTUAL) {
+                    4
+                } else if func_class.contains(FuncClass::STATIC) {
+                    2
+                } else {
+                    0
+                }
+        };
+        let far = if func_class.contains(FuncClass::FAR) {
+            1
+        } else {
+            0
+        };
+        self.out.push(base + far);
+        if func_class.contains(FuncClass::THUNK) {
+            self.write_number(this_adjustment);
+        }
+        Ok(())
+    }
+
+    fn write_func_qualifiers(&mut self, sc: StorageClass) {
+        if sc.contains(StorageClass::PTR64) {
+            self.write(b"E");
+        }
+        if sc.contains(StorageClass::RESTRICT) {
+            self.write(b"I");
+        }
+        if sc.contains(StorageClass::UNALIGNED) {
+            self.write(b"F");
+        }
+        if sc.contains(StorageClass::LVALUE_QUAL) {
+            self.write(b"G");
+        } else if sc.contains(StorageClass::RVALUE_QUAL) {
+            self.write(b"H");
+        }
+        self.write_qualifier(sc);
+    }
+
+    fn write_qualifier(&mut self, sc: StorageClass) {
+        let cv = sc & (StorageClass::CONST | StorageClass::VOLATILE);
+        self.out.push(b'A' + cv.bits() as u8);
+    }
+
+    // Writes the qualifiers of a member of a class, from `Q` to `T`.
+    fn write_member_qualifier(&mut self, sc: StorageClass) {
+        let cv = sc & (StorageClass::CONST | StorageClass::VOLATILE);
+        self.out.push(b'Q' + cv.bits() as u8);
+    }
+
+    fn write_storage_class(&mut self, sc: StorageClass) {
+        let cv = sc & (StorageClass::CONST | StorageClass::VOLATILE | StorageClass::FAR);
+        self.out.push(b'A' + cv.bits() as u8);
+    }
+
+    fn write_calling_conv(&mut self, calling_conv: CallingConv) -> Result<()> {
+        self.write(match calling_conv {
+            CallingConv::Cdecl => b"A",
+            CallingConv::Pascal => b"C",
+            CallingConv::Thiscall => b"E",
+            CallingConv::Stdcall => b"G",
+            CallingConv::Fastcall => b"I",
+            CallingConv::Vectorcall => b"Q",
+            CallingConv::_Regcall => {
+                return Err(Error::new("__regcall has no known encoding"));
+            }
+        });
+        Ok(())
+    }
+
+    // Writes a return type, with a `?` storage class for the types that
+    // MSVC qualifies, or `@` for the missing return type of structors.
+    fn write_return_type(&mut self, t: &'b Type<'a>) -> Result<()> {
+        if let Type::None = *t {
+            self.write(b"@");
+            return Ok(());
+        }
+        let sc = incoming_storage_class(t);
+        if is_record(t) || !(sc & (StorageClass::CONST | StorageClass::VOLATILE)).is_empty() {
+            self.write(b"?");
+            self.write_qualifier(sc);
+            self.write_var_type(t, sc)
+        } else {
+            self.write_var_type(t, StorageClass::empty())
+        }
+    }
+
+    // Writes the parameter list and the throw specification.
+    fn write_func_params(&mut self, sig: &'b FunctionSignature<'a>) -> Result<()> {
+        if let [Type::Void(sc)] = *sig.params.types {
+            if sc.is_empty() {
+                self.write(b"X");
+            } else {
+                self.write_params(&sig.params)?;
+            }
+        } else {
+            self.write_params(&sig.params)?;
+        }
+        self.write(if sig.noexcept { b"_E" } else { b"Z" });
+        Ok(())
+    }
+
+    // The counterpart of `ParserState::read_params`.
+    fn write_params(&mut self, params: &'b Params<'a>) -> Result<()> {
+        for t in &params.types {
+            if let Type::VarArgs = *t {
+                self.write(b"Z");
+                return Ok(());
+            }
+            self.write_param(t)?;
+        }
+        self.write(b"@");
+        Ok(())
+    }
+
+    // MSVC spells out every template argument, even one it could
+    // backreference.  The parser still memorizes them, so they go into the
+    // table for the function types that may follow in the same list.
+    fn write_template_args(&mut self, params: &'b Params<'a>) -> Result<()> {
+        for t in &params.types {
+            if let Type::VarArgs = *t {
+                self.write(b"Z");
+                return Ok(());
+            }
+            self.write_memorized_type(t)?;
+        }
+        self.write(b"@");
+        Ok(())
+    }
+
+    fn write_param(&mut self, t: &'b Type<'a>) -> Result<()> {
+        if let Some(i) = self.types.iter().position(|&m| m == t) {
+            self.out.push(b'0' + i as u8);
+            return Ok(());
+        }
+        self.write_memorized_type(t)
+    }
+
+    fn write_memorized_type(&mut self, t: &'b Type<'a>) -> Result<()> {
+        let start = self.out.len();
+        self.write_var_type(t, StorageClass::empty())?;
+        // Single-letter types are not memorized.
+        if self.out.len() - start > 1 && self.types.len() < 10 && !self.types.contains(&t) {
+            self.types.push(t);
+        }
+        Ok(())
+    }
+
+    fn write_pointee(&mut self, t: &'b Type<'a>) -> Result<()> {
+        let sc = incoming_storage_class(t);
+        if sc.contains(StorageClass::PTR64) {
+            self.write(b"E");
+        }
+        self.write_storage_class(sc);
+        self.write_var_type(t, sc)
+    }
+
+    // Writes a `$$C` qualifier when the storage class of a type differs
+    // from the one the context passes in.
+    fn write_cv_prefix(&mut self, sc: StorageClass, incoming: StorageClass) {
+        if sc != incoming {
+            self.write(b"$$C");
+            self.write_qualifier(sc);
+        }
+    }
+
+    // Writes the calling convention, return type and parameters of a
+    // function type.
+    fn write_func_type(&mut self, sig: &'b FunctionSignature<'a>) -> Result<()> {
+        self.write_calling_conv(sig.calling_conv)?;
+        self.write_return_type(&sig.return_type)?;
+        self.write_func_params(sig)
+    }
+
+    // The counterpart of `ParserState::read_var_type`.  `incoming` is
+    // the storage class that the enclosing context passes to the type.
+    fn write_var_type(&mut self, t: &'b Type<'a>, incoming: StorageClass) -> Result<()> {
+        let (code, sc): (&[u8], StorageClass) = match *t {
+            Type::Enum(ref symbol, sc) => {
+                self.write_cv_prefix(sc, incoming);
+                self.write(b"W4");
+                return self.write_symbol(symbol, false);
+            }
+            Type::Union(ref symbol, sc)
+            | Type::Struct(ref symbol, sc)
+            | Type::Class(ref symbol, sc) => {
+                self.write_cv_prefix(sc, incoming);
+                self.write(match *t {
+                    Type::Union(..) => b"T",
+                    Type::Struct(..) => b"U",
+                    _ => b"V",
+                });
+                return self.write_symbol(symbol, false);
+            }
+            Type::Ref(ref inner, sc) | Type::Ptr(ref inner, sc) => {
+                let is_ref = matches!(*t, Type::Ref(..));
+                if let Type::NonMemberFunction(ref sig) = **inner {
+                    self.write_cv_prefix(sc, incoming);
+                    self.write(if is_ref { b"A6" } else { b"P6" });
+                    return self.write_func_type(sig);
+                }
+                self.write_pointer_code(is_ref, sc, incoming);
+                return self.write_pointee(inner);
+            }
+            Type::MemberDataPointer(ref symbol, ref inner, sc) => {
+                self.write_pointer_code(false, sc, incoming);
+                let inner_sc = incoming_storage_class(inner);
+                if inner_sc.contains(StorageClass::PTR64) {
+                    self.write(b"E");
+                }
+                self.write_member_qualifier(inner_sc);
+                self.write_symbol(symbol, false)?;
+                return self.write_var_type(inner, inner_sc);
+            }
+            Type::RValueRef(ref inner, sc) => {
+                self.write_cv_prefix(sc, incoming);
+                self.write(b"$$Q");
+                return self.write_pointee(inner);
+            }
+            Type::MemberFunctionPointer(ref symbol, func_class, ref sig) => {
+                self.write(if func_class.is_empty() { b"P8" } else { b"$1?" });
+                self.write_symbol(symbol, true)?;
+                if sig.this_quals.contains(StorageClass::PTR64) {
+                    self.write(b"E");
+                }
+                if func_class.is_empty() {
+                    self.write_qualifier(sig.this_quals);
+                } else {
+                    self.write_func_class(func_class, sig.this_adjustment)?;
+                }
+                self.write_calling_conv(sig.calling_conv)?;
+                self.write_return_type(&sig.return_type)?;
+                return self.write_func_params(sig);
+            }
+            Type::NonMemberFunction(ref sig) => {
+                if sig.this_quals.is_empty() {
+                    self.write(b"$$A6");
+                } else {
+                    self.write(b"$$A8@@");
+                    self.write_func_qualifiers(sig.this_quals);
+                }
+                return self.write_func_type(sig);
+            }
+            Type::Array(..) => {
+                self.write(b"Y");
+                return self.write_array(t);
+            }
+            Type::Constant(n) => {
+                self.write(b"$0");
+                self.write_number(n);
+                return Ok(());
+            }
+            Type::TemplateParameterWithIndex(n) => {
+                if n < 0 {
+                    self.write(b"?");
+                    self.write_number(-n);
+                } else {
+                    self.write(b"$D");
+                    self.write_number(n);
+                }
+                return Ok(());
+            }
+            Type::ParameterPack(marker) => {
+                self.write(match marker {
+                    PackMarker::EmptyTypePack => b"$$V" as &[u8],
+                    PackMarker::EmptyTypePackLegacy => b"$$$V",
+                    PackMarker::EmptyNonTypePack => b"$S",
+                    PackMarker::Separator => b"$$Z",
+                });
+                return Ok(());
+            }
+            Type::Nullptr => {
+                self.write(b"$$T");
+                return Ok(());
+            }
+            Type::TemplateTemplate(ref symbol) => {
+                self.write(b"$$Y");
+                return self.write_symbol(symbol, false);
+            }
+            Type::Void(sc) => (b"X", sc),
+            Type::Bool(sc) => (b"_N", sc),
+            Type::Char(sc) => (b"D", sc),
+            Type::Schar(sc) => (b"C", sc),
+            Type::Uchar(sc) => (b"E", sc),
+            Type::Short(sc) => (b"F", sc),
+            Type::Ushort(sc) => (b"G", sc),
+            Type::Int(sc) => (b"H", sc),
+            Type::Uint(sc) => (b"I", sc),
+            Type::Long(sc) => (b"J", sc),
+            Type::Ulong(sc) => (b"K", sc),
+            Type::Int64(sc) => (b"_J", sc),
+            Type::Uint64(sc) => (b"_K", sc),
+            Type::Int128(sc) => (b"_L", sc),
+            Type::Uint128(sc) => (b"_M", sc),
+            Type::Wchar(sc) => (b"_W", sc),
+            Type::Char8(sc) => (b"_Q", sc),
+            Type::Char16(sc) => (b"_S", sc),
+            Type::Char32(sc) => (b"_U", sc),
+            Type::Float(sc) => (b"M", sc),
+            Type::Double(sc) => (b"N", sc),
+            Type::Ldouble(sc) => (b"O", sc),
+            _ => return Err(unmangleable(t)),
+        };
+        self.write_cv_prefix(sc, incoming);
+        self.write(code);
+        Ok(())
+    }
+
+    // Writes the letter of a pointer or reference with the qualifiers `sc`,
+    // and a `$$C` prefix if no letter has them.
+    fn write_pointer_code(&mut self, is_ref: bool, sc: StorageClass, incoming: StorageClass) {
+        let code: &[u8] = if sc == incoming {
+            if is_ref {
+                b"A"
+            } else {
+                b"P"
+            }
+        } else if is_ref && sc == StorageClass::VOLATILE {
+            b"B"
+        } else if !is_ref && sc == StorageClass::CONST {
+            b"Q"
+        } else if !is_ref && sc == StorageClass::VOLATILE {
+            b"R"
+        } else if !is_ref && sc == StorageClass::CONST | StorageClass::VOLATILE {
+            b"S"
+        } else {
+            self.write_cv_prefix(sc, incoming);
+            if is_ref {
+                b"A"
+            } else {
+                b"P"
+            }
+        };
+        self.write(code);
+    }
+
+    // Writes the dimensions and element type of an array.  Nested arrays
+    // with the same qualifiers are one multi-dimensional array.
+    fn write_array(&mut self, t: &'b Type<'a>) -> Result<()> {
+        let mut lens = Vec::new();
+        let mut element = t;
+        let mut array_sc = None;
+        while let Type::Array(len, ref inner, sc) = *element {
+            if array_sc.is_some() && array_sc != Some(sc) {
+                break;
+            }
+            array_sc = Some(sc);
+            lens.push(len);
+            element = inner;
+        }
+        self.write_number(lens.len() as i32);
+        for len in lens {
+            self.write_number(len);
+        }
+        let sc = array_sc.unwrap_or_else(StorageClass::empty) - StorageClass::PTR64;
+        if !sc.is_empty() {
+            self.write(b"$$C");
+            self.write_qualifier(sc);
+        }
+        self.write_var_type(element, StorageClass::empty())
+    }
+}
+
+// Whether a name is the `?$` encoding of a bare template.
+fn is_template_only(symbol: &Symbol) -> bool {
+    symbol.scope.names.is_empty() && matches!(symbol.name, Name::Template(..))
+}
+
+fn is_record(t: &Type) -> bool {
+    matches!(
+        *t,
+        Type::Class(..) | Type::Struct(..) | Type::Union(..) | Type::Enum(..)
+    )
+}
+
+// The storage class that a context has to pass to a type so that the
+// type takes it over, or empty for types that ignore it.
+fn incoming_storage_class(t: &Type) -> StorageClass {
+    match *t {
+        Type::Enum(_, sc)
+        | Type::Class(_, sc)
+        | Type::Struct(_, sc)
+        | Type::Union(_, sc)
+        | Type::Ptr(_, sc)
+        | Type::MemberDataPointer(_, _, sc)
+        | Type::Ref(_, sc)
+        | Type::RValueRef(_, sc)
+        | Type::Void(sc)
+        | Type::Bool(sc)
+        | Type::Char(sc)
+        | Type::Schar(sc)
+        | Type::Uchar(sc)
+        | Type::Short(sc)
+        | Type::Ushort(sc)
+        | Type::Int(sc)
+        | Type::Uint(sc)
+        | Type::Long(sc)
+        | Type::Ulong(sc)
+        | Type::Int64(sc)
+        | Type::Uint64(sc)
+        | Type::Int128(sc)
+        | Type::Uint128(sc)
+        | Type::Wchar(sc)
+        | Type::Char8(sc)
+        | Type::Char16(sc)
+        | Type::Char32(sc)
+        | Type::Float(sc)
+        | Type::Double(sc)
+        | Type::Ldouble(sc) => sc,
+        Type::Array(_, _, sc) => sc & StorageClass::PTR64,
+        _ => StorageClass::empty(),
+    }
+}
+
+fn unmangleable(t: &Type) -> Error {
+    Error::new(format!("type cannot be mangled: {:?}", t))
+}
@@ -0,0 +1,143 @@
+//! MD5 names of over-long manglings.
+//!
+//! MSVC does not emit mangled names of 4096 characters or more.  It
+//! replaces them with `??@` followed by the lowercase hex MD5 digest of
+//! the full mangled name and a closing `@`.  The digest is one-way, so the
+//! original name can only be recovered by hashing candidates:
+//!
+//! ```
+//! use msvc_demangler::{find_md5_source, md5_mangled_name};
+//!
+//! let long = format!("?{}@@3HA", "x".repeat(4096));
+//! let hashed = md5_mangled_name(&long);
+//! assert!(hashed.starts_with("??@") && hashed.len() == 36);
+//! assert_eq!(find_md5_source(&hashed, vec!["?x@@3HA", &long]), Some(&long[..]));
+//! ```
+//!
+//! The complete object locator of a class whose vftable name was hashed is
+//! not hashed on its own: its name is the vftable's MD5 name followed by
+//! `??_R4@`.
+
+/// Names at least this long are replaced by their MD5 name.
+const MAX_MANGLED_NAME_LEN: usize = 4096;
+
+/// The length of `??@<md5>@`.
+const MD5_NAME_LEN: usize = 36;
+
+/// The prefix of complete object locator manglings, and the suffix of
+/// their MD5 names.
+const LOCATOR_PREFIX: &str = "??_R4";
+const LOCATOR_SUFFIX: &str = "??_R4@";
+
+/// Returns the name MSVC emits for the mangled name `mangled`: the name
+/// itself if it is shorter than 4096 characters, `??@<md5>@` otherwise.
+/// An over-long complete object locator `??_R4<class>6B@` becomes the MD5
+/// name of the vftable `??_7<class>6B@` followed by `??_R4@`.
+///
+/// An AST can be hashed by mangling it first.
+pub fn md5_mangled_name(mangled: &str) -> String {
+    if mangled.len() < MAX_MANGLED_NAME_LEN {
+        return mangled.to_string();
+    }
+    if let Some(rest) = mangled.strip_prefix(LOCATOR_PREFIX) {
+        let vftable = format!("??_7{}", rest);
+        let mut name = hash_name(&vftable);
+        name.push_str(LOCATOR_SUFFIX);
+        return name;
+    }
+    hash_name(mangled)
+}
+
+fn hash_name(mangled: &str) -> String {
+    let mut name = String::with_capacity(MD5_NAME_LEN + LOCATOR_SUFFIX.len());
+    name.push_str("??@");
+    for byte in &digest(mangled.as_bytes()) {
+        name.push_str(&format!("{:02x}", byte));
+    }
+    name.push('@');
+    name
+}
+
+/// Returns whether `name` has the form of an MD5 name, `??@` followed by
+/// 32 lowercase hex digits and `@`, optionally followed by `??_R4@` for a
+/// complete object locator.
+pub fn is_md5_name(name: &str) -> bool {
+    let bytes = name.strip_suffix(LOCATOR_SUFFIX).unwrap_or(name).as_bytes();
+    bytes.len() == MD5_NAME_LEN
+        && bytes.starts_with(b"??@")
+        && bytes[35] == b'@'
+        && bytes[3..35]
+            .iter()
+            .all(|&c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c))
+}
+
+/// Finds the candidate full mangling that the MD5 name `hashed` was made
+/// from.  Candidates that are too short to be hashed are skipped.
+pub fn find_md5_source<'a, I>(hashed: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    if !is_md5_name(hashed) {
+        return None;
+    }
+    candidates.into_iter().find(|candidate| {
+        candidate.len() >= MAX_MANGLED_NAME_LEN && md5_mangled_name(candidate) == hashed
+    })
+}
+
+// The per-round shift amounts of RFC 1321.
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+// The MD5 digest of `data`.  Only used for names, so no streaming
+// interface.
+fn digest(data: &[u8]) -> [u8; 16] {
+    let table: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for chunk in message.chunks(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f
+                .wrapping_add(a)
+                .wrapping_add(table[i])
+                .wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut out = [0u8; 16];
+    for (bytes, word) in out.chunks_mut(4).zip(&state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,305 @@
+//! Traversal of the AST.
+//!
+//! `Visit` walks a parsed name by reference and `VisitMut` by mutable
+//! reference.  Every method of the traits defaults to recursing into the
+//! children of the node, so an implementation only overrides the methods
+//! for the nodes it cares about.  To keep recursing from an overridden
+//! method, call the matching `walk_*` function.
+//!
+//! ```
+//! use msvc_demangler::visit::{self, Visit};
+//! use msvc_demangler::{parse, Name};
+//!
+//! // Collects every identifier in a symbol.
+//! struct Identifiers(Vec<String>);
+//!
+//! impl<'ast> Visit<'ast> for Identifiers {
+//!     fn visit_name(&mut self, name: &'ast Name<'ast>) {
+//!         if let Name::NonTemplate(ref s) = *name {
+//!             self.0.push(String::from_utf8_lossy(s).into_owned());
+//!         }
+//!         visit::walk_name(self, name);
+//!     }
+//! }
+//!
+//! let parsed = parse("?f@ns@@YAXPAVFoo@@AAV?$Bar@VBaz@@@@@Z").unwrap();
+//! let mut identifiers = Identifiers(Vec::new());
+//! identifiers.visit_parse_result(&parsed);
+//! assert_eq!(identifiers.0, vec!["f", "ns", "Foo", "Bar", "Baz"]);
+//! ```
+
+use super::{FunctionSignature, Name, NameSequence, Operator, Params, ParseResult, Symbol, Type};
+
+/// Walks the AST by reference.
+pub trait Visit<'ast> {
+    fn visit_parse_result(&mut self, parse_result: &'ast ParseResult<'ast>) {
+        walk_parse_result(self, parse_result);
+    }
+
+    fn visit_symbol(&mut self, symbol: &'ast Symbol<'ast>) {
+        walk_symbol(self, symbol);
+    }
+
+    fn visit_name(&mut self, name: &'ast Name<'ast>) {
+        walk_name(self, name);
+    }
+
+    fn visit_params(&mut self, params: &'ast Params<'ast>) {
+        walk_params(self, params);
+    }
+
+    fn visit_signature(&mut self, sig: &'ast FunctionSignature<'ast>) {
+        walk_signature(self, sig);
+    }
+
+    fn visit_type(&mut self, t: &'ast Type<'ast>) {
+        walk_type(self, t);
+    }
+}
+
+pub fn walk_parse_result<'ast, V: Visit<'ast> + ?Sized>(
+    v: &mut V,
+    parse_result: &'ast ParseResult<'ast>,
+) {
+    v.visit_symbol(&parse_result.symbol);
+    v.visit_type(&parse_result.symbol_type);
+}
+
+pub fn walk_symbol<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, symbol: &'ast Symbol<'ast>) {
+    v.visit_name(&symbol.name);
+    walk_scope(v, &symbol.scope);
+}
+
+fn walk_scope<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, scope: &'ast NameSequence<'ast>) {
+    for name in &scope.names {
+        v.visit_name(name);
+    }
+}
+
+pub fn walk_name<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, name: &'ast Name<'ast>) {
+    match *name {
+        Name::Operator(Operator::RTTITypeDescriptor(_, ref t)) => v.visit_type(t),
+        Name::Template(ref name, ref params) => {
+            v.visit_name(name);
+            v.visit_params(params);
+        }
+        Name::ParsedName(ref parse_result) => v.visit_parse_result(parse_result),
+        Name::Operator(_)
+        | Name::NonTemplate(_)
+        | Name::Discriminator(_)
+        | Name::AnonymousNamespace(_)
+        | Name::Unknown => {}
+    }
+}
+
+pub fn walk_params<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, params: &'ast Params<'ast>) {
+    for t in &params.types {
+        v.visit_type(t);
+    }
+}
+
+pub fn walk_signature<'ast, V: Visit<'ast> + ?Sized>(
+    v: &mut V,
+    sig: &'ast FunctionSignature<'ast>,
+) {
+    v.visit_type(&sig.return_type);
+    v.visit_params(&sig.params);
+}
+
+pub fn walk_type<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, t: &'ast Type<'ast>) {
+    match *t {
+        Type::MemberFunction(_, ref sig) | Type::NonMemberFunction(ref sig) => {
+            v.visit_signature(sig)
+        }
+        Type::MemberFunctionPointer(ref symbol, _, ref sig) => {
+            v.visit_symbol(symbol);
+            v.visit_signature(sig);
+        }
+        Type::MemberDataPointer(ref symbol, ref inner, _) => {
+            v.visit_symbol(symbol);
+            v.visit_type(inner);
+        }
+        Type::CXXVBTable(ref scope, _) | Type::CXXVFTable(ref scope, _) => walk_scope(v, scope),
+        Type::Ptr(ref inner, _)
+        | Type::Ref(ref inner, _)
+        | Type::RValueRef(ref inner, _)
+        | Type::Array(_, ref inner, _)
+        | Type::Var(ref inner, _, _) => v.visit_type(inner),
+        Type::TemplateTemplate(ref symbol)
+        | Type::Struct(ref symbol, _)
+        | Type::Union(ref symbol, _)
+        | Type::Class(ref symbol, _)
+        | Type::Enum(ref symbol, _) => v.visit_symbol(symbol),
+        Type::None
+        | Type::VCallThunk(..)
+        | Type::TemplateParameterWithIndex(_)
+        | Type::ThreadSafeStaticGuard(_)
+        | Type::Constant(_)
+        | Type::ConstantString(_)
+        | Type::Void(_)
+        | Type::Bool(_)
+        | Type::Char(_)
+        | Type::Schar(_)
+        | Type::Uchar(_)
+        | Type::Short(_)
+        | Type::Ushort(_)
+        | Type::Int(_)
+        | Type::Uint(_)
+        | Type::Long(_)
+        | Type::Ulong(_)
+        | Type::Int64(_)
+        | Type::Uint64(_)
+        | Type::Int128(_)
+        | Type::Uint128(_)
+        | Type::Wchar(_)
+        | Type::Char8(_)
+        | Type::Char16(_)
+        | Type::Char32(_)
+        | Type::Float(_)
+        | Type::Double(_)
+        | Type::Ldouble(_)
+        | Type::VarArgs
+        | Type::ParameterPack(_)
+        | Type::Nullptr
+        | Type::RTTIType
+        | Type::Unknown => {}
+    }
+}
+
+/// Walks the AST by mutable reference, for rewriting it in place.
+pub trait VisitMut<'a> {
+    fn visit_parse_result(&mut self, parse_result: &mut ParseResult<'a>) {
+        walk_parse_result_mut(self, parse_result);
+    }
+
+    fn visit_symbol(&mut self, symbol: &mut Symbol<'a>) {
+        walk_symbol_mut(self, symbol);
+    }
+
+    fn visit_name(&mut self, name: &mut Name<'a>) {
+        walk_name_mut(self, name);
+    }
+
+    fn visit_params(&mut self, params: &mut Params<'a>) {
+        walk_params_mut(self, params);
+    }
+
+    fn visit_signature(&mut self, sig: &mut FunctionSignature<'a>) {
+        walk_signature_mut(self, sig);
+    }
+
+    fn visit_type(&mut self, t: &mut Type<'a>) {
+        walk_type_mut(self, t);
+    }
+}
+
+pub fn walk_parse_result_mut<'a, V: VisitMut<'a> + ?Sized>(
+    v: &mut V,
+    parse_result: &mut ParseResult<'a>,
+) {
+    v.visit_symbol(&mut parse_result.symbol);
+    v.visit_type(&mut parse_result.symbol_type);
+}
+
+pub fn walk_symbol_mut<'a, V: VisitMut<'a> + ?Sized>(v: &mut V, symbol: &mut Symbol<'a>) {
+    v.visit_name(&mut symbol.name);
+    walk_scope_mut(v, &mut symbol.scope);
+}
+
+fn walk_scope_mut<'a, V: VisitMut<'a> + ?Sized>(v: &mut V, scope: &mut NameSequence<'a>) {
+    for name in &mut scope.names {
+        v.visit_name(name);
+    }
+}
+
+pub fn walk_name_mut<'a, V: VisitMut<'a> + ?Sized>(v: &mut V, name: &mut Name<'a>) {
+    match *name {
+        Name::Operator(Operator::RTTITypeDescriptor(_, ref mut t)) => v.visit_type(t),
+        Name::Template(ref mut name, ref mut params) => {
+            v.visit_name(name);
+            v.visit_params(params);
+        }
+        Name::ParsedName(ref mut parse_result) => v.visit_parse_result(parse_result),
+        Name::Operator(_)
+        | Name::NonTemplate(_)
+        | Name::Discriminator(_)
+        | Name::AnonymousNamespace(_)
+        | Name::Unknown => {}
+    }
+}
+
+pub fn walk_params_mut<'a, V: VisitMut<'a> + ?Sized>(v: &mut V, params: &mut Params<'a>) {
+    for t in &mut params.types {
+        v.visit_type(t);
+    }
+}
+
+pub fn walk_signature_mut<'a, V: VisitMut<'a> + ?Sized>(
+    v: &mut V,
+    sig: &mut FunctionSignature<'a>,
+) {
+    v.visit_type(&mut sig.return_type);
+    v.visit_params(&mut sig.params);
+}
+
+pub fn walk_type_mut<'a, V: VisitMut<'a> + ?Sized>(v: &mut V, t: &mut Type<'a>) {
+    match *t {
+        Type::MemberFunction(_, ref mut sig) | Type::NonMemberFunction(ref mut sig) => {
+            v.visit_signature(sig)
+        }
+        Type::MemberFunctionPointer(ref mut symbol, _, ref mut sig) => {
+            v.visit_symbol(symbol);
+            v.visit_signature(sig);
+        }
+        Type::MemberDataPointer(ref mut symbol, ref mut inner, _) => {
+            v.visit_symbol(symbol);
+            v.visit_type(inner);
+        }
+        Type::CXXVBTable(ref mut scope, _) | Type::CXXVFTable(ref mut scope, _) => {
+            walk_scope_mut(v, scope)
+        }
+        Type::Ptr(ref mut inner, _)
+        | Type::Ref(ref mut inner, _)
+        | Type::RValueRef(ref mut inner, _)
+        | Type::Array(_, ref mut inner, _)
+        | Type::Var(ref mut inner, _, _) => v.visit_type(inner),
+        Type::TemplateTemplate(ref mut symbol)
+        | Type::Struct(ref mut symbol, _)
+        | Type::Union(ref mut symbol, _)
+        | Type::Class(ref mut symbol, _)
+        | Type::Enum(ref mut symbol, _) => v.visit_symbol(symbol),
+        Type::None
+        | Type::VCallThunk(..)
+        | Type::TemplateParameterWithIndex(_)
+        | Type::ThreadSafeStaticGuard(_)
+        | Type::Constant(_)
+        | Type::ConstantString(_)
+        | Type::Void(_)
+        | Type::Bool(_)
+        | Type::Char(_)
+        | Type::Schar(_)
+        | Type::Uchar(_)
+        | Type::Short(_)
+        | Type::Ushort(_)
+        | Type::Int(_)
+        | Type::Uint(_)
+        | Type::Long(_)
+        | Type::Ulong(_)
+        | Type::Int64(_)
+        | Type::Uint64(_)
+        | Type::Int128(_)
+        | Type::Uint128(_)
+        | Type::Wchar(_)
+        | Type::Char8(_)
+        | Type::Char16(_)
+        | Type::Char32(_)
+        | Type::Float(_)
+        | Type::Double(_)
+        | Type::Ldouble(_)
+        | Type::VarArgs
+        | Type::ParameterPack(_)
+        | Type::Nullptr
+        | Type::RTTIType
+        | Type::Unknown => {}
+    }
+}
@@ -1,8 +1,24 @@
 extern crate msvc_demangler;
 
-use msvc_demangler::{demangle, DemangleFlags};
+use msvc_demangler::{
+    demangle, demangle_bytes, demangle_bytes_to_string, demangle_in_text, demangle_lossy,
+    demangle_type, demangle_with_options, detect, find_md5_source, is_md5_name, is_msvc_mangled,
+    mangle, md5_mangled_name, parse, parse_bytes, parse_declaration, parse_decorated,
+    parse_partial, parse_type, parse_with_options, parse_with_spans, serialize, serialize_bytes,
+    serialize_into, serialize_with_spans, try_demangle, visit, write_demangled, Access,
+    CallingConv, DemangleFlags, Error, ErrorKind, FuncClass, FunctionSignature, MangledKind, Name,
+    NameSequence, OutputSpanKind, PackMarker, ParseOptions, ParseResult, Span, SpanKind,
+    StorageClass, StringLiteral, Symbol, SymbolBuilder, SymbolKind, Type, Visit, VisitMut,
+    DEFAULT_MAX_AST_NODES, DEFAULT_MAX_DEPTH, DEFAULT_MAX_OUTPUT_LEN,
+};
+use std::borrow::Cow;
 
 fn expect_with_flags(input: &str, reference: &str, flags: u32) {
+    let strict = ParseOptions::default().with_strict(true);
+    if let Err(err) = parse_with_options(input, strict) {
+        panic!("{:?} rejected in strict mode: {}", input, err);
+    }
+    assert_canonical_round_trip(input);
     let demangled = demangle(input, ::DemangleFlags::from_bits(flags).unwrap());
     let reference = reference.to_owned();
     if let Ok(demangled) = demangled {
@@ -12,6 +28,18 @@ fn expect_with_flags(input: &str, reference: &str, flags: u32) {
     }
 }
 
+// Mangling a parsed name gives back a name with the same AST, and that
+// name mangles to itself.  Most inputs come back unchanged, but some
+// encode a value differently from MSVC, such as a discriminator written
+// as `?B@` instead of `?0`.
+fn assert_canonical_round_trip(input: &str) {
+    let parsed = parse(input).unwrap();
+    let mangled = mangle(&parsed).unwrap();
+    let reparsed = parse(&mangled).unwrap();
+    assert_eq!(reparsed, parsed, "{:?} mangled as {:?}", input, mangled);
+    assert_eq!(mangle(&reparsed).unwrap(), mangled);
+}
+
 // For cases where undname demangles differently/better than we do.
 fn expect_failure(input: &str, reference: &str) {
     let demangled = demangle(input, ::DemangleFlags::COMPLETE).unwrap();
@@ -30,6 +58,7 @@ fn other_tests() {
 
     expect("?f@@YAHQBH@Z", "int __cdecl f(int const * const)");
     expect("?f@@YA_WQB_W@Z", "wchar_t __cdecl f(wchar_t const * const)");
+    expect("?vector_func@@YQXXZ", "void __vectorcall vector_func(void)");
     expect(
         "?f@@YA_UQB_U@Z",
         "char32_t __cdecl f(char32_t const * const)",
@@ -181,6 +210,53 @@ fn other_tests() {
         "?getFactory@SkImageShader@@UBEP6A?AV?$sk_sp@VSkFlattenable@@@@AAVSkReadBuffer@@@ZXZ",
         "public: virtual class sk_sp<class SkFlattenable> (__cdecl * __thiscall SkImageShader::getFactory(void) const)(class SkReadBuffer &)"
     );
+    expect("?m@@3PRfoo@@DR1@", "char const foo::*m");
+    expect(
+        "?memptr1@@3RESB@@HES1@",
+        "int volatile B::* volatile memptr1",
+    );
+    expect("?f8@@YAPQS@@HXZ", "int S::* __cdecl f8(void)");
+    expect("?f@@YAXPQS@@Y02H@Z", "void __cdecl f(int (S::*)[3])");
+}
+
+#[test]
+fn test_parsed_details() {
+    // `D` and `F` are the far forms of `C` and `E`.
+    for input in &["?f@C@@DAXXZ", "?f@C@@FAEXXZ"] {
+        match parse(input).unwrap().symbol_type {
+            Type::MemberFunction(func_class, _) => {
+                assert!(func_class.contains(msvc_demangler::FuncClass::FAR))
+            }
+            ref t => panic!("unexpected type {:?}", t),
+        }
+    }
+
+    // `?A` is 0xC1 and `?a` is 0xE1.
+    for &(input, byte) in &[
+        ("??_C@_01CNACBAHC@?A?$AA@", 0xc1),
+        ("??_C@_01CNACBAHC@?a?$AA@", 0xe1),
+    ] {
+        match parse(input).unwrap().symbol_type {
+            Type::ConstantString(ref s) => assert_eq!(s.bytes[..], [byte, 0]),
+            ref t => panic!("unexpected type {:?}", t),
+        }
+    }
+
+    // The `__ptr64` of a pointer is kept on its pointee, arrays included.
+    match parse("?x@@3PEAY02HEA").unwrap().symbol_type {
+        Type::Var(ref ptr, _, _) => match **ptr {
+            Type::Ptr(ref array, _) => assert_eq!(
+                **array,
+                Type::Array(
+                    3,
+                    Box::new(Type::Int(StorageClass::empty())),
+                    StorageClass::PTR64
+                )
+            ),
+            ref t => panic!("unexpected type {:?}", t),
+        },
+        ref t => panic!("unexpected type {:?}", t),
+    }
 }
 
 #[test]
@@ -579,6 +655,8 @@ fn upstream_tests() {
     expect("?x@@3PEAY02HEA", "int (*x)[3]");
     expect("?x@@3PEAY124HEA", "int (*x)[3][5]");
     expect("?x@@3PEAY02$$CBHEA", "int const (*x)[3]");
+    expect("?x@@3PEAY02$$CCHEA", "int volatile (*x)[3]");
+    expect("?x@@3PEAY02$$CDHEA", "int const volatile (*x)[3]");
     expect("?x@@3PEAEEA", "unsigned char *x");
     expect("?x@@3PEAY1NKM@5HEA", "int (*x)[3500][6]");
     expect("?x@@YAXMH@Z", "void __cdecl x(float,int)");
@@ -825,6 +903,10 @@ fn upstream_tests() {
         "??$templ_fun_with_ty_pack@$$V@@YAXXZ",
         "void __cdecl templ_fun_with_ty_pack<>(void)",
     );
+    expect(
+        "?f@@YAXU?$X@$$YAlias@ns@@$$Y12@@@@Z",
+        "void __cdecl f(struct X<ns::Alias,ns::Alias>)",
+    );
     expect(
         "??__FFLASH_TEMP_FILENAME@sandboxing@mozilla@@YAXXZ",
         "void __cdecl mozilla::sandboxing::FLASH_TEMP_FILENAME::`dynamic atexit destructor'(void)",
@@ -845,8 +927,1342 @@ fn upstream_tests() {
         "??__J?B@??f@@YAAAUS@@XZ@5BB@",
         "`struct S & __cdecl f(void)'::`1'::`local static thread guard'{17}",
     );
+    // MSVC would encode the discriminator as `?0`.
+    let parsed = parse("??__J?B@??f@@YAAAUS@@XZ@5BB@").unwrap();
+    assert_eq!(mangle(&parsed).unwrap(), "??__J?0??f@@YAAAUS@@XZ@5BB@");
     expect(
         "??__J?@??f@@YAAAUS@@XZ@5BB@",
         "`struct S & __cdecl f(void)'::`0'::`local static thread guard'{17}",
     );
 }
+
+#[test]
+fn test_template_pack_markers() {
+    let markers = |input| {
+        let parsed = parse(input).unwrap();
+        match parsed.symbol.name {
+            Name::Template(_, params) => params
+                .types
+                .into_iter()
+                .filter_map(|ty| match ty {
+                    Type::ParameterPack(marker) => Some(marker),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            _ => panic!("not a template: {}", input),
+        }
+    };
+
+    assert_eq!(markers("??$f@$$V@@YAXXZ"), vec![PackMarker::EmptyTypePack]);
+    assert_eq!(
+        markers("??$f@$$$V@@YAXXZ"),
+        vec![PackMarker::EmptyTypePackLegacy]
+    );
+    assert_eq!(
+        markers("??$f@$S@@YAXXZ"),
+        vec![PackMarker::EmptyNonTypePack]
+    );
+    assert_eq!(
+        markers("??$func@H$$ZH@@YAHAEBU?$Foo@H@@0@Z"),
+        vec![PackMarker::Separator]
+    );
+}
+
+#[test]
+fn test_error_kinds() {
+    let kind = |input| parse(input).unwrap_err().kind().cloned().unwrap();
+
+    assert_eq!(kind("foo"), ErrorKind::NotMangled);
+    assert_eq!(kind("?foo@@YAHH"), ErrorKind::UnexpectedEnd);
+    assert_eq!(kind("?foo"), ErrorKind::UnexpectedEnd);
+    assert_eq!(
+        kind("?foo@@YKXXZ"),
+        ErrorKind::UnknownCallingConvention(b'K')
+    );
+    assert_eq!(kind("?foo@@3_XA"), ErrorKind::UnknownPrimitiveType);
+    assert_eq!(
+        kind("?foo@@YAH0@Z"),
+        ErrorKind::InvalidBackreference {
+            index: 0,
+            available: 0
+        }
+    );
+    assert_eq!(kind("?foo@@3Y~01KA"), ErrorKind::BadNumber);
+    assert_eq!(
+        demangle("??$f@$0?IAAAAAAA@@@YAXXZ", DemangleFlags::llvm()).unwrap(),
+        "void __cdecl f<-2147483648>(void)"
+    );
+    assert_eq!(kind("??$f@$0?IAAAAAAB@@@YAXXZ"), ErrorKind::BadNumber);
+    assert_eq!(kind("??}"), ErrorKind::UnknownOperator);
+    assert_eq!(kind("?foo@@3PA"), ErrorKind::UnexpectedEnd);
+
+    let strict = ParseOptions::default().with_strict(true);
+    let err = parse_with_options("?x@@3HAfoo", strict).unwrap_err();
+    assert_eq!(err.kind(), Some(&ErrorKind::TrailingData));
+    assert_eq!(err.offset(), Some(7));
+    assert_eq!(err.context(), Some("foo"));
+}
+
+#[test]
+fn test_error_diagnostic() {
+    let input = "?f@@YAXV?$vector@H!@std@@@Z";
+    let err = parse(input).unwrap_err();
+    assert_eq!(err.offset(), Some(18));
+    assert_eq!(
+        err.render_diagnostic(),
+        "error: unknown primitive type
+ --> offset 18
+  |
+  | ?f@@YAXV?$vector@H!@std@@@Z
+  |                   ^
+  = while reading function parameters \u{2192} template arguments of `vector`
+  = name backreferences: 0 = vector
+  = type backreferences: none"
+    );
+    assert_eq!(format!("{:#}", err), err.render_diagnostic());
+    assert_eq!(
+        err.to_string(),
+        "unknown primitive type (offset: 18, remaining: \"!@std@@@Z\")"
+    );
+
+    // Other parsers keep the input but have no parser state to show.
+    let err = parse_declaration("void __cdecl f(int").unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        format!(
+            "error: {}\n --> offset 18\n  |\n  | void __cdecl f(int\n  | {}^",
+            err.kind().unwrap(),
+            " ".repeat(18)
+        )
+    );
+
+    // Errors that did not come from a parser render like `Display`.
+    let err = Error::new("custom");
+    assert_eq!(err.render_diagnostic(), "custom");
+    assert_eq!(format!("{:#}", err), "custom");
+
+    let input = "?foo@@YAHPAVBar@@PAX2@Z";
+    let err = parse(input).unwrap_err();
+    let rendered = err.render_diagnostic();
+    assert!(rendered.contains("  = name backreferences: 0 = foo, 1 = Bar"));
+    assert!(rendered.contains("  = type backreferences: 0 = class Bar *, 1 = void *"));
+
+    // Long backreference targets are cut off.
+    let input = format!("?f@@YAXPAV{}@@PAX2@Z", "x".repeat(200));
+    let err = parse(&input).unwrap_err();
+    let rendered = err.render_diagnostic();
+    assert!(rendered.contains(&format!("0 = f, 1 = {}...\n", "x".repeat(80))));
+    assert!(rendered.contains(&format!("0 = class {}..., 1 = void *", "x".repeat(74))));
+
+    let input = ".PAVFoo@@!";
+    let err = parse_type(input).unwrap_err();
+    assert!(err
+        .render_diagnostic()
+        .ends_with("  = type backreferences: none"));
+}
+
+#[test]
+fn test_partial() {
+    let expect_lossy = |input: &str, reference: &str, kind: ErrorKind| {
+        let (demangled, err) = demangle_lossy(input, DemangleFlags::llvm());
+        assert_eq!(demangled, reference);
+        assert_eq!(err.as_ref().and_then(|err| err.kind()), Some(&kind));
+    };
+
+    expect_lossy(
+        "?foo@Bar@@QAEHH!@Z",
+        "public: int __thiscall Bar::foo(int, <?>)",
+        ErrorKind::UnknownPrimitiveType,
+    );
+    expect_lossy(
+        "?foo@Bar@@QAE!HH@Z",
+        "public: <?> __thiscall Bar::foo(<?>)",
+        ErrorKind::UnknownPrimitiveType,
+    );
+    expect_lossy(
+        "?f@ns@@YAXV?$vector@H!@std@@H@Z",
+        "void __cdecl ns::f(class vector<int, <?> >)",
+        ErrorKind::UnknownPrimitiveType,
+    );
+    expect_lossy(
+        "?foo@@YAHHH",
+        "int __cdecl foo(int, int)",
+        ErrorKind::UnexpectedEnd,
+    );
+    expect_lossy(
+        "?foo@Bar@@QKEHH@Z",
+        "<?> Bar::foo",
+        ErrorKind::UnknownCallingConvention(b'K'),
+    );
+    expect_lossy("foo", "<?>", ErrorKind::NotMangled);
+
+    let input = "?foo@Bar@@QAEHH@Z";
+    let (parsed, err) = parse_partial(input);
+    assert!(err.is_none());
+    assert_eq!(parsed, parse(input).unwrap());
+}
+
+#[test]
+fn test_limits() {
+    let kind = |input: &str, options: ParseOptions| {
+        demangle_with_options(input, DemangleFlags::llvm(), options)
+            .unwrap_err()
+            .kind()
+            .cloned()
+    };
+
+    // Deep nesting fails cleanly instead of overflowing the stack.
+    let deep_ptr = format!("?x@@3{}HA", "PA".repeat(100_000));
+    assert_eq!(
+        kind(&deep_ptr, ParseOptions::default()),
+        Some(ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH))
+    );
+    let deep_template = format!("?x@@3{}HA", "V?$A@".repeat(100_000));
+    assert_eq!(
+        kind(&deep_template, ParseOptions::default()),
+        Some(ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH))
+    );
+    let shallow = ParseOptions::default().with_max_depth(Some(2));
+    assert!(parse_with_options("?x@@3PAHA", shallow).is_ok());
+    assert_eq!(
+        kind("?x@@3PAPAHA", shallow),
+        Some(ErrorKind::DepthLimitExceeded(2))
+    );
+
+    // Every backreference copies the referenced type.
+    let doubling = "?f@@YAXP6AXHH@ZP6AX00@ZP6AX11@ZP6AX22@ZP6AX33@Z@Z";
+    assert!(parse(doubling).is_ok());
+    let small_ast = ParseOptions::default().with_max_ast_nodes(Some(100));
+    assert_eq!(
+        kind(doubling, small_ast),
+        Some(ErrorKind::AstNodeLimitExceeded(100))
+    );
+
+    // Nested templates double the size again at every level.  The default
+    // limits stop this before it runs out of memory.
+    let mut nested = String::from("P6AXHH@Z");
+    let doublings: String = (0..9).map(|i| format!("P6AX{}{}@Z", i, i)).collect();
+    nested.push_str(&doublings);
+    for _ in 0..2 {
+        nested = format!("V?$A@{}@@{}", nested, doublings);
+    }
+    let nested = format!("?f@@YAX{}@Z", nested);
+    assert_eq!(nested.len(), 247);
+    assert_eq!(
+        kind(&nested, ParseOptions::default()),
+        Some(ErrorKind::AstNodeLimitExceeded(DEFAULT_MAX_AST_NODES))
+    );
+    assert!(demangle(&nested, DemangleFlags::llvm()).is_err());
+    assert_eq!(
+        ParseOptions::default().max_output_len,
+        Some(DEFAULT_MAX_OUTPUT_LEN)
+    );
+
+    let short_output = ParseOptions::default().with_max_output_len(Some(100));
+    let err = demangle_with_options(doubling, DemangleFlags::llvm(), short_output).unwrap_err();
+    assert_eq!(err.kind(), Some(&ErrorKind::OutputLimitExceeded(100)));
+    assert_eq!(err.offset(), None);
+    assert_eq!(err.to_string(), "output exceeds the limit of 100 bytes");
+    assert_eq!(
+        demangle_with_options("?x@@3HA", DemangleFlags::llvm(), short_output).unwrap(),
+        "int x"
+    );
+}
+
+#[test]
+fn test_no_panics() {
+    let strict = ParseOptions::default().with_strict(true);
+    let check = |input: &str| {
+        if let Ok(parsed) = parse(input) {
+            let _ = serialize(&parsed, DemangleFlags::llvm());
+            let _ = serialize(&parsed, DemangleFlags::COMPLETE);
+            let _ = parsed.to_string();
+        }
+        if let Ok(demangled) = try_demangle(input) {
+            let _ = demangled.to_string();
+            let _ = format!("{:#}", demangled.with_flags(DemangleFlags::COMPLETE));
+        }
+        let _ = parse_with_options(input, strict);
+        let (parsed, _) = parse_partial(input);
+        let _ = serialize(&parsed, DemangleFlags::llvm());
+    };
+
+    for line in include_str!("regressions/panics.txt").lines() {
+        if !line.is_empty() && !line.starts_with(';') {
+            check(line);
+        }
+    }
+
+    // Too long to keep in the corpus: array dimensions used to recurse
+    // without a depth limit.
+    check(&format!("?x@@3Y{}{}HA", "PPPPPPP@", "A@".repeat(100_000)));
+}
+
+#[test]
+fn test_bytes() {
+    // A Latin-1 identifier, as emitted by some old compilers.
+    let input = b"?caf\xe9@@YAXPAVna\xefve@@@Z";
+    let parsed = parse_bytes(input).unwrap();
+    assert_eq!(
+        parsed.symbol.name,
+        Name::NonTemplate(Cow::Borrowed(b"caf\xe9"))
+    );
+    assert_eq!(
+        demangle_bytes(input, DemangleFlags::llvm()).unwrap(),
+        b"void __cdecl caf\xe9(class na\xefve *)".to_vec()
+    );
+    assert_eq!(
+        demangle_bytes_to_string(input, DemangleFlags::llvm()).unwrap(),
+        "void __cdecl caf\u{fffd}(class na\u{fffd}ve *)"
+    );
+    assert!(serialize(&parsed, DemangleFlags::llvm()).is_err());
+    assert_eq!(
+        String::from_utf8_lossy(&serialize_bytes(&parsed, DemangleFlags::llvm()).unwrap()),
+        "void __cdecl caf\u{fffd}(class na\u{fffd}ve *)"
+    );
+
+    let input = b"?caf\xe9@@3!A";
+    let err = parse_bytes(input).unwrap_err();
+    assert_eq!(err.kind(), Some(&ErrorKind::UnknownPrimitiveType));
+    assert_eq!(err.offset(), Some(8));
+    assert!(err
+        .render_diagnostic()
+        .contains("  | ?caf\u{fffd}@@3!A\n  |         ^\n"));
+}
+
+#[test]
+fn test_streaming() {
+    let inputs = [
+        "?x@@3HA",
+        "?cached@?1??GetLong@BinaryPath@mozilla@@SA?AW4nsresult@@QA_W@Z@4_NA",
+        "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z",
+    ];
+    let mut buf = String::new();
+    for input in &inputs {
+        let parsed = parse(input).unwrap();
+        let expected = serialize(&parsed, DemangleFlags::llvm()).unwrap();
+
+        buf.clear();
+        serialize_into(&mut buf, &parsed, DemangleFlags::llvm()).unwrap();
+        assert_eq!(buf, expected);
+
+        let mut bytes = Vec::new();
+        write_demangled(&mut bytes, &parsed, DemangleFlags::llvm()).unwrap();
+        assert_eq!(bytes, expected.as_bytes());
+    }
+
+    buf.clear();
+    let parsed = parse_bytes(b"?caf\xe9@@3HA").unwrap();
+    serialize_into(&mut buf, &parsed, DemangleFlags::llvm()).unwrap();
+    assert_eq!(buf, "int caf\u{fffd}");
+}
+
+#[test]
+fn test_display() {
+    let input = "?foo@Bar@@QAEHPAD@Z";
+    let demangled = try_demangle(input).unwrap();
+    assert_eq!(
+        demangled.to_string(),
+        "public: int __thiscall Bar::foo(char *)"
+    );
+    assert_eq!(format!("{:#}", demangled), "Bar::foo");
+    assert_eq!(
+        demangled
+            .clone()
+            .with_flags(DemangleFlags::COMPLETE)
+            .to_string(),
+        demangle(input, DemangleFlags::COMPLETE).unwrap()
+    );
+    assert_eq!(
+        format!("{:#}", demangled.with_flags(DemangleFlags::COMPLETE)),
+        "Bar::foo"
+    );
+
+    let parsed = parse(input).unwrap();
+    assert_eq!(
+        parsed.to_string(),
+        "public: int __thiscall Bar::foo(char *)"
+    );
+    assert_eq!(format!("{:#}", parsed), "Bar::foo");
+
+    assert!(try_demangle("foo").is_err());
+
+    // Parses, but a constructor needs a class name to be written.
+    assert!(try_demangle("??0@").is_err());
+    assert_eq!(parse("??0@").unwrap().to_string(), "<?>");
+
+    // Errors of the formatter itself are passed on.
+    struct Full;
+    impl std::fmt::Write for Full {
+        fn write_str(&mut self, _: &str) -> std::fmt::Result {
+            Err(std::fmt::Error)
+        }
+    }
+    use std::fmt::Write;
+    let demangled = try_demangle(input).unwrap();
+    assert!(write!(Full, "{}", demangled).is_err());
+}
+
+#[test]
+fn test_into_owned() {
+    fn parse_owned(input: String) -> ParseResult<'static> {
+        parse(&input).unwrap().into_owned()
+    }
+
+    let input = "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z";
+    let owned = parse_owned(input.to_string());
+    assert_eq!(owned, parse(input).unwrap());
+    match owned.symbol.scope.names[0] {
+        Name::NonTemplate(Cow::Owned(ref s)) => assert_eq!(s, b"js"),
+        ref name => panic!("unexpected name {:?}", name),
+    }
+
+    // Owned results can be sent to other threads.
+    let demangled = std::thread::spawn(move || owned.to_string())
+        .join()
+        .unwrap();
+    assert_eq!(demangled, demangle(input, DemangleFlags::llvm()).unwrap());
+}
+
+#[test]
+fn test_visit() {
+    struct Classes<'ast>(Vec<&'ast Symbol<'ast>>);
+
+    impl<'ast> Visit<'ast> for Classes<'ast> {
+        fn visit_type(&mut self, t: &'ast Type<'ast>) {
+            if let Type::Class(ref symbol, _) = *t {
+                self.0.push(symbol);
+            }
+            visit::walk_type(self, t);
+        }
+    }
+
+    let parsed = parse("?f@@YAXPAVFoo@ns@@AAV?$Bar@VBaz@@@@P8Qux@@AEXXZ@Z").unwrap();
+    let mut classes = Classes(Vec::new());
+    classes.visit_parse_result(&parsed);
+    let names: Vec<_> = classes.0.iter().map(|symbol| &symbol.name).collect();
+    assert_eq!(names.len(), 3);
+    assert_eq!(names[0], &Name::NonTemplate(Cow::Borrowed(b"Foo")));
+    assert_eq!(names[2], &Name::NonTemplate(Cow::Borrowed(b"Baz")));
+
+    struct Rename;
+
+    impl<'a> VisitMut<'a> for Rename {
+        fn visit_name(&mut self, name: &mut Name<'a>) {
+            if *name == Name::NonTemplate(Cow::Borrowed(b"ns")) {
+                *name = Name::NonTemplate(Cow::Owned(b"renamed".to_vec()));
+            }
+            visit::walk_name_mut(self, name);
+        }
+    }
+
+    let mut parsed = parse("?f@ns@@YAXPAVFoo@ns@@V?$Bar@VBaz@ns@@@@@Z").unwrap();
+    Rename.visit_parse_result(&mut parsed);
+    assert_eq!(
+        parsed.to_string(),
+        "void __cdecl renamed::f(class renamed::Foo *, class Bar<class renamed::Baz>)"
+    );
+}
+
+#[test]
+fn test_accessors() {
+    let method = parse("?foo@Bar@ns@@QBEHPAD@Z").unwrap();
+    assert_eq!(method.kind(), SymbolKind::Method);
+    assert_eq!(method.qualified_name().unwrap(), "ns::Bar::foo");
+    assert_eq!(method.namespace().unwrap().as_deref(), Some("ns"));
+    assert_eq!(method.class_name().unwrap().as_deref(), Some("Bar"));
+    assert_eq!(
+        method.parameters(),
+        Some(
+            &[Type::Ptr(
+                Box::new(Type::Char(StorageClass::empty())),
+                StorageClass::empty()
+            )][..]
+        )
+    );
+    assert_eq!(
+        method.return_type(),
+        Some(&Type::Int(StorageClass::empty()))
+    );
+    assert_eq!(method.calling_convention(), Some(CallingConv::Thiscall));
+    assert_eq!(method.access(), Some(Access::Public));
+    assert!(method.is_const_method());
+    assert_eq!(method.template_args(), None);
+
+    let function = parse("??$f@H@ns@@YAXXZ").unwrap();
+    assert_eq!(function.kind(), SymbolKind::Function);
+    assert_eq!(function.namespace().unwrap().as_deref(), Some("ns"));
+    assert_eq!(function.class_name().unwrap(), None);
+    assert_eq!(function.parameters(), Some(&[][..]));
+    assert_eq!(
+        function.return_type(),
+        Some(&Type::Void(StorageClass::empty()))
+    );
+    assert_eq!(function.access(), None);
+    assert!(!function.is_const_method());
+    assert_eq!(
+        function.template_args(),
+        Some(&[Type::Int(StorageClass::empty())][..])
+    );
+
+    let ctor = parse("??0Foo@@QAE@XZ").unwrap();
+    assert_eq!(ctor.kind(), SymbolKind::Method);
+    assert_eq!(ctor.return_type(), None);
+    assert_eq!(ctor.class_name().unwrap().as_deref(), Some("Foo"));
+    assert_eq!(ctor.namespace().unwrap(), None);
+
+    let kind = |input: &str| parse(input).unwrap().kind();
+    assert_eq!(kind("?f@Foo@@SAXXZ"), SymbolKind::StaticMethod);
+    assert_eq!(kind("?f@Foo@@UAEXXZ"), SymbolKind::VirtualMethod);
+    assert_eq!(kind("?f@Foo@@W7AEXXZ"), SymbolKind::Thunk);
+    assert_eq!(kind("??_9Foo@@$BA@AE"), SymbolKind::Thunk);
+    assert_eq!(kind("?x@@3HA"), SymbolKind::Variable);
+    assert_eq!(kind("??_7Foo@@6B@"), SymbolKind::VTable);
+    assert_eq!(kind("??_8Foo@@7B@"), SymbolKind::VBTable);
+    assert_eq!(kind("??_R0?AVFoo@@@8"), SymbolKind::RTTI);
+    assert_eq!(kind("??_R4Foo@@6B@"), SymbolKind::RTTI);
+    assert_eq!(
+        kind("??_C@_05MFEJDJP@hello?$AA@"),
+        SymbolKind::StringLiteral
+    );
+    assert_eq!(kind("?$TSS0@?1??f@@YAXXZ@4HA"), SymbolKind::Guard);
+    assert_eq!(kind("??_B?1??f@@YAXXZ@51"), SymbolKind::Guard);
+    assert_eq!(kind("??__Ex@@YAXXZ"), SymbolKind::Function);
+    assert_eq!(kind("?x@@9"), SymbolKind::Other);
+
+    let member = parse("?x@Foo@ns@@1HA").unwrap();
+    assert_eq!(member.kind(), SymbolKind::Variable);
+    assert_eq!(member.access(), Some(Access::Protected));
+    assert_eq!(member.class_name().unwrap().as_deref(), Some("Foo"));
+
+    let vtable = parse("??_7?$Foo@H@ns@@6B@").unwrap();
+    assert_eq!(vtable.class_name().unwrap().as_deref(), Some("Foo<int>"));
+    assert_eq!(vtable.namespace().unwrap().as_deref(), Some("ns"));
+
+    // `Outer` is a class because the parameter uses it as one.
+    let nested = parse("?f@Inner@Outer@ns@@QAEXPAVOuter@3@@Z").unwrap();
+    assert_eq!(nested.qualified_name().unwrap(), "ns::Outer::Inner::f");
+    assert_eq!(
+        nested.class_name().unwrap().as_deref(),
+        Some("Outer::Inner")
+    );
+    assert_eq!(nested.namespace().unwrap().as_deref(), Some("ns"));
+
+    // Namespaces cannot be templates.
+    let nested = parse("?f@Inner@?$Outer@H@ns@@QAEXXZ").unwrap();
+    assert_eq!(
+        nested.class_name().unwrap().as_deref(),
+        Some("Outer<int>::Inner")
+    );
+    assert_eq!(nested.namespace().unwrap().as_deref(), Some("ns"));
+
+    // Without either hint `Outer` could be a namespace.
+    let nested = parse("?f@Inner@Outer@ns@@QAEXXZ").unwrap();
+    assert_eq!(nested.class_name().unwrap().as_deref(), Some("Inner"));
+    assert_eq!(nested.namespace().unwrap().as_deref(), Some("ns::Outer"));
+
+    // Scopes are not cut off like diagnostics are.
+    let long = "x".repeat(100);
+    let input = format!("?f@{}@@QAEXXZ", long);
+    assert_eq!(parse(&input).unwrap().class_name().unwrap(), Some(long));
+
+    assert!(parse("??0@").unwrap().qualified_name().is_err());
+}
+
+#[test]
+fn test_signature() {
+    let method = parse("?f@S@@QBEHH@_E").unwrap();
+    let sig = method.signature().unwrap();
+    assert_eq!(sig.calling_conv, CallingConv::Thiscall);
+    assert_eq!(sig.params.types, vec![Type::Int(StorageClass::empty())]);
+    assert_eq!(sig.this_quals, StorageClass::CONST);
+    assert_eq!(*sig.return_type, Type::Int(StorageClass::empty()));
+    assert!(sig.noexcept);
+
+    // Member pointers share the same signature type.
+    let var = parse("?p@@3P8S@@BEHXZQ1@").unwrap();
+    let sig = match var.symbol_type {
+        Type::Var(ref inner, ..) => inner.signature().unwrap(),
+        ref other => panic!("unexpected type {:?}", other),
+    };
+    assert_eq!(sig.this_quals, StorageClass::CONST);
+    assert!(!sig.noexcept);
+
+    assert!(parse("?x@@3HA").unwrap().signature().is_none());
+}
+
+#[test]
+fn test_spans() {
+    let input = "?foo@bar@@QEAAHH@Z";
+    let (parsed, spans) = parse_with_spans(input, ParseOptions::default()).unwrap();
+    assert_eq!(parsed, parse(input).unwrap());
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| (span.kind, &input[span.range.clone()]))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (SpanKind::Symbol, "foo@bar@@"),
+            (SpanKind::Name, "foo@"),
+            (SpanKind::Name, "bar@"),
+            (SpanKind::Type, "QEAAHH@Z"),
+            (SpanKind::Type, "H"),
+            (SpanKind::Type, "H"),
+        ]
+    );
+
+    // Name and type backreferences point at what they resolved to.
+    let input = "??$f@VFoo@ns@@@ns@@YAXPAVFoo@0@0@Z";
+    let (_, spans) = parse_with_spans(input, ParseOptions::default()).unwrap();
+    let backrefs: Vec<_> = spans
+        .iter()
+        .filter_map(|span: &Span| {
+            let backref = span.backreference.as_ref()?;
+            Some((
+                span.kind,
+                &input[span.range.clone()],
+                backref.index,
+                &input[backref.target.clone()],
+            ))
+        })
+        .collect();
+    assert_eq!(
+        backrefs,
+        vec![
+            (SpanKind::Name, "0", 0, "ns@"),
+            (SpanKind::Type, "0", 0, "PAVFoo@0@"),
+        ]
+    );
+
+    // Template arguments have their own backreference context.
+    let input = "?f@@YAXV?$A@PAUB@@0@@PAUB@@@Z";
+    let (_, spans) = parse_with_spans(input, ParseOptions::default()).unwrap();
+    let backref = spans
+        .iter()
+        .find_map(|span| span.backreference.as_ref())
+        .unwrap();
+    assert_eq!(&input[backref.target.clone()], "PAUB@@");
+    assert_eq!(backref.target.start, 12);
+
+    assert!(parse_with_spans("?f@@YAX0@Z", ParseOptions::default()).is_err());
+}
+
+#[test]
+fn test_output_spans() {
+    let parsed =
+        parse("?foo@Bar@ns@@UEBAPEAV?$vector@HV?$alloc@H@std@@@std@@AEBUX@@P6AHH@Z@Z").unwrap();
+    let (s, spans) = serialize_with_spans(&parsed, DemangleFlags::llvm()).unwrap();
+    assert_eq!(s, serialize(&parsed, DemangleFlags::llvm()).unwrap());
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| (span.kind, &s[span.range.clone()]))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (OutputSpanKind::Specifiers, "public: virtual"),
+            (
+                OutputSpanKind::ReturnType,
+                "class std::vector<int, class std::alloc<int> > *"
+            ),
+            (OutputSpanKind::Scope, "std"),
+            (OutputSpanKind::Name, "vector<int, class std::alloc<int> >"),
+            (
+                OutputSpanKind::TemplateArguments,
+                "<int, class std::alloc<int> >"
+            ),
+            (OutputSpanKind::TemplateArgument, "int"),
+            (OutputSpanKind::TemplateArgument, "class std::alloc<int>"),
+            (OutputSpanKind::Scope, "std"),
+            (OutputSpanKind::Name, "alloc<int>"),
+            (OutputSpanKind::TemplateArguments, "<int>"),
+            (OutputSpanKind::TemplateArgument, "int"),
+            (OutputSpanKind::CallingConv, "__cdecl"),
+            (OutputSpanKind::Scope, "ns::Bar"),
+            (OutputSpanKind::Name, "foo"),
+            (
+                OutputSpanKind::Parameters,
+                "(struct X const &, int (__cdecl *)(int))"
+            ),
+            (OutputSpanKind::Parameter, "struct X const &"),
+            (OutputSpanKind::Name, "X"),
+            (OutputSpanKind::Qualifiers, "const"),
+            (OutputSpanKind::Parameter, "int (__cdecl *)(int)"),
+            (OutputSpanKind::ReturnType, "int"),
+            (OutputSpanKind::CallingConv, "__cdecl"),
+            (OutputSpanKind::Parameters, "(int)"),
+            (OutputSpanKind::Parameter, "int"),
+            (OutputSpanKind::Qualifiers, "const"),
+        ]
+    );
+
+    // Flags that drop parts of the output drop their spans too.
+    let (s, spans) = serialize_with_spans(&parsed, DemangleFlags::NAME_ONLY).unwrap();
+    assert_eq!(s, "ns::Bar::foo");
+    let kinds: Vec<_> = spans.iter().map(|span| span.kind).collect();
+    assert_eq!(kinds, vec![OutputSpanKind::Scope, OutputSpanKind::Name]);
+}
+
+#[test]
+fn test_mangle() {
+    struct Rename;
+
+    impl<'a> VisitMut<'a> for Rename {
+        fn visit_name(&mut self, name: &mut Name<'a>) {
+            if *name == Name::NonTemplate(Cow::Borrowed(b"Bar")) {
+                *name = Name::NonTemplate(Cow::Borrowed(b"Foo"));
+            }
+            visit::walk_name_mut(self, name);
+        }
+    }
+
+    // Rewriting can make types equal, which mangles them as backreferences.
+    let mut parsed = parse("?f@@YAXPAVFoo@@PAVBar@@@Z").unwrap();
+    Rename.visit_parse_result(&mut parsed);
+    assert_eq!(mangle(&parsed).unwrap(), "?f@@YAXPAVFoo@@0@Z");
+
+    // Template arguments are memorized like parameters, so the second
+    // `C *` refers to type 1, but MSVC spells out the arguments themselves.
+    let input = "?f@@YAXV?$A@PAUB@@$$A6AXPAUC@@1@Z@@@Z";
+    assert_eq!(mangle(&parse(input).unwrap()).unwrap(), input);
+    assert_eq!(
+        demangle(input, DemangleFlags::llvm()).unwrap(),
+        "void __cdecl f(class A<struct B *, void __cdecl (struct C *, struct C *)>)"
+    );
+    let input = "?f@@YAXV?$A@PAUB@@0@@PAUB@@@Z";
+    assert_canonical_round_trip(input);
+    assert_eq!(
+        mangle(&parse(input).unwrap()).unwrap(),
+        "?f@@YAXV?$A@PAUB@@PAU1@@@PAUB@@@Z"
+    );
+
+    let parsed = parse("?Release@ContentSignatureVerifier@@WBA@AGKXZ").unwrap();
+    assert_eq!(parsed.signature().unwrap().this_adjustment, 16);
+
+    let parsed = parse("??_C@_13KDLDGPGJ@?$AA?7?$AA?$AA@").unwrap();
+    match parsed.symbol_type {
+        Type::ConstantString(ref s) => {
+            assert_eq!(s.char_bytes, 2);
+            assert_eq!(s.byte_length, 4);
+            assert_eq!(s.bytes, b"\0\t\0\0");
+        }
+        ref t => panic!("unexpected type {:?}", t),
+    }
+
+    let (partial, _) = parse_partial("?f@@YAXPAV");
+    assert!(mangle(&partial).is_err());
+}
+
+#[test]
+fn test_builder() {
+    let method = SymbolBuilder::method("Foo", &["ns"])
+        .public()
+        .virtual_()
+        .cdecl()
+        .returns(Type::int())
+        .param(Type::ptr(Type::class("Bar")))
+        .build();
+    assert_eq!(
+        method.to_string(),
+        "public: virtual int __cdecl ns::Foo(class Bar *)"
+    );
+    assert_eq!(mangle(&method).unwrap(), "?Foo@ns@@UAAHPAVBar@@@Z");
+
+    let method = SymbolBuilder::method("get", &["ns", "Widget"])
+        .const_()
+        .returns(Type::reference(Type::class("ns::Widget").const_()))
+        .build();
+    assert_eq!(
+        method.to_string(),
+        "public: class ns::Widget const & __thiscall ns::Widget::get(void) const"
+    );
+    assert_eq!(mangle(&method).unwrap(), "?get@Widget@ns@@QBEABV12@XZ");
+    assert_eq!(parse("?get@Widget@ns@@QBEABV12@XZ").unwrap(), method);
+
+    let function = SymbolBuilder::function("f", &[])
+        .stdcall()
+        .param(Type::int())
+        .param(Type::ptr(Type::int()))
+        .param(Type::ptr(Type::int()))
+        .build();
+    assert_eq!(mangle(&function).unwrap(), "?f@@YGXHPAH0@Z");
+
+    let variable = SymbolBuilder::variable("count", &["Foo"], Type::int())
+        .private()
+        .build();
+    assert_eq!(variable.to_string(), "private: static int Foo::count");
+    assert_eq!(mangle(&variable).unwrap(), "?count@Foo@@0HA");
+
+    let method = SymbolBuilder::method("bar", &["Foo"])
+        .ptr64()
+        .returns(Type::int())
+        .param(Type::ptr(Type::char().const_()))
+        .param(Type::ptr(Type::ptr(Type::int())))
+        .build();
+    assert_eq!(mangle(&method).unwrap(), "?bar@Foo@@QEAAHPEBDPEAPEAH@Z");
+    assert_eq!(parse("?bar@Foo@@QEAAHPEBDPEAPEAH@Z").unwrap(), method);
+
+    let method = SymbolBuilder::method("get", &["ns", "Widget"])
+        .const_()
+        .ptr64()
+        .returns(Type::reference(Type::class("ns::Widget").const_()))
+        .build();
+    assert_eq!(
+        method.to_string(),
+        "public: class ns::Widget const & __cdecl ns::Widget::get(void) const"
+    );
+    assert_eq!(mangle(&method).unwrap(), "?get@Widget@ns@@QEBAAEBV12@XZ");
+
+    let function = SymbolBuilder::function("f", &[])
+        .param(Type::ptr64(Type::int()))
+        .build();
+    assert_eq!(mangle(&function).unwrap(), "?f@@YAXPEAH@Z");
+    assert_eq!(
+        SymbolBuilder::function("f", &[])
+            .ptr64()
+            .param(Type::ptr(Type::int()))
+            .build(),
+        function
+    );
+
+    let variable = SymbolBuilder::variable("p", &[], Type::ptr(Type::int()))
+        .ptr64()
+        .build();
+    assert_eq!(mangle(&variable).unwrap(), "?p@@3PEAHEA");
+    assert_eq!(parse("?p@@3PEAHEA").unwrap(), variable);
+}
+
+#[test]
+fn test_parse_declaration() {
+    let parsed =
+        parse_declaration("public: virtual int __thiscall Foo::bar(char const *)").unwrap();
+    assert_eq!(mangle(&parsed).unwrap(), "?bar@Foo@@UAEHPBD@Z");
+
+    for input in &[
+        "?f@@YAXXZ",
+        "?x@@3HA",
+        "?x@@3HB",
+        "?x@@3PBDA",
+        "?x@@3PAY124HA",
+        "?x@@3P6AHP6AHM@ZN@ZA",
+        "?count@Foo@@0HA",
+        "??0Foo@@QAE@XZ",
+        "??1?$Foo@H@@QAE@XZ",
+        "??BFoo@@QAEHXZ",
+        "??HFoo@@QAEHH@Z",
+        "??$?RA6AXXZ@SkOnce@@QAEXA6AXXZ@Z",
+        "?f@?A@@YAXXZ",
+        "?f@@YAXP8Foo@@AEHH@Z@Z",
+        "?f@@YAX$$QAH@Z",
+        "?f@@YAXPAV?$Foo@V?$Bar@H@@@@@Z",
+        "?f@@YAXV?$Foo@$0A@H@@@Z",
+        "?f@@YAXHZZ",
+        "?get@Widget@ns@@QBEABV12@XZ",
+        "??BFoo@@QAEPBHXZ",
+        "??$?BH@Foo@@QAEHXZ",
+        "??$f@$$V@@YAXXZ",
+        "?m@@3PQfoo@@HQ1@",
+        "?f8@@YAPQS@@HXZ",
+        "?f@@YAXPQS@@Y02H@Z",
+        "??_7Foo@@6B@",
+        "??_8A@@7B@",
+        "??_8B@@7BA@@@",
+        "??_SA@@6B@",
+        "??_R4B@@6BA@@@",
+        "??_R0?BUS@@@8",
+        "??_R0?APAUS@@@8",
+        "??_B?1??f@@YAXXZ@51",
+        "?$TSS0@?1??f@@YAXXZ@4HA",
+    ] {
+        let parsed = parse(input).unwrap();
+        for &flags in &[DemangleFlags::COMPLETE, DemangleFlags::llvm()] {
+            let text = serialize(&parsed, flags).unwrap();
+            assert_eq!(parse_declaration(&text).unwrap(), parsed, "{}", text);
+        }
+    }
+
+    // Conversion operators take their return type from the name.
+    for &(input, mangled) in &[
+        (
+            "public: __thiscall Foo::operator int(void)",
+            "??BFoo@@QAEHXZ",
+        ),
+        (
+            "public: __thiscall Foo::operator int const *(void)",
+            "??BFoo@@QAEPBHXZ",
+        ),
+        (
+            "public: int __thiscall Foo::operatorcast<int>(void)",
+            "??$?BH@Foo@@QAEHXZ",
+        ),
+        ("void __cdecl f<>(void)", "??$f@$$V@@YAXXZ"),
+    ] {
+        let parsed = parse_declaration(input).unwrap();
+        assert_eq!(mangle(&parsed).unwrap(), mangled, "{}", input);
+    }
+    assert!(parse_declaration("public: char __thiscall Foo::operator int(void)").is_err());
+    assert!(parse_declaration("public: int __thiscall Foo::operatorcast(void)").is_err());
+
+    assert!(parse_declaration("const Foo::`vbtable'").is_err());
+    assert!(parse_declaration("[thunk]: __thiscall Foo::`vcall'{8}").is_err());
+    assert!(parse_declaration("void __cdecl `f(void)'::g(void)").is_err());
+    assert!(parse_declaration("void __cdecl f(int").is_err());
+    assert!(parse_declaration("void (__cdecl Foo::*const p)(void)").is_err());
+    assert!(parse_declaration("int x y").is_err());
+
+    // Deep nesting is an error instead of a stack overflow.
+    let deep = [
+        format!(
+            "void __cdecl f(struct {}int{})",
+            "A<".repeat(5000),
+            ">".repeat(5000)
+        ),
+        format!("int {}x", "*".repeat(5000)),
+        format!("int {}x{}", "(*".repeat(5000), ")".repeat(5000)),
+        format!("void __cdecl f(int {})", "&".repeat(5000)),
+        format!("int x{}", "[1]".repeat(5000)),
+        format!("{}Foo::~Foo(void)", "~".repeat(5000)),
+    ];
+    for input in &deep {
+        assert_eq!(
+            parse_declaration(input).unwrap_err().kind(),
+            Some(&ErrorKind::DepthLimitExceeded(DEFAULT_MAX_DEPTH)),
+            "{}",
+            &input[..40]
+        );
+    }
+}
+
+#[test]
+fn test_parse_declaration_corpus() {
+    // Drops what the text of a symbol leaves out: `__ptr64`, adjustments,
+    // pack markers, anonymous namespace ids, the trailing qualifiers of
+    // pointer variables and the class of member function template
+    // arguments.
+    struct Lossy;
+
+    impl<'a> VisitMut<'a> for Lossy {
+        fn visit_name(&mut self, name: &mut Name<'a>) {
+            match *name {
+                Name::Template(_, ref mut params) => {
+                    params
+                        .types
+                        .retain(|t| !matches!(*t, Type::ParameterPack(_)));
+                    if params.types.is_empty() {
+                        params
+                            .types
+                            .push(Type::ParameterPack(PackMarker::EmptyTypePack));
+                    }
+                }
+                Name::AnonymousNamespace(ref mut id) => *id = None,
+                _ => {}
+            }
+            visit::walk_name_mut(self, name);
+        }
+
+        fn visit_signature(&mut self, sig: &mut FunctionSignature<'a>) {
+            sig.this_quals -= StorageClass::PTR64;
+            sig.this_adjustment = 0;
+            visit::walk_signature_mut(self, sig);
+        }
+
+        fn visit_type(&mut self, t: &mut Type<'a>) {
+            match *t {
+                Type::Var(ref inner, _, ref mut sc) => match **inner {
+                    Type::Ptr(..)
+                    | Type::MemberDataPointer(..)
+                    | Type::Ref(..)
+                    | Type::RValueRef(..) => *sc = StorageClass::empty(),
+                    _ => *sc -= StorageClass::PTR64,
+                },
+                Type::MemberFunctionPointer(_, ref mut func_class, _) => {
+                    *func_class = FuncClass::empty()
+                }
+                _ => {}
+            }
+            if let Some(sc) = storage_class_mut(t) {
+                *sc -= StorageClass::PTR64;
+            }
+            visit::walk_type_mut(self, t);
+        }
+    }
+
+    fn storage_class_mut<'a, 'b>(t: &'b mut Type<'a>) -> Option<&'b mut StorageClass> {
+        match *t {
+            Type::Ptr(_, ref mut sc)
+            | Type::MemberDataPointer(_, _, ref mut sc)
+            | Type::Ref(_, ref mut sc)
+            | Type::RValueRef(_, ref mut sc)
+            | Type::Array(_, _, ref mut sc)
+            | Type::Class(_, ref mut sc)
+            | Type::Struct(_, ref mut sc)
+            | Type::Union(_, ref mut sc)
+            | Type::Enum(_, ref mut sc)
+            | Type::Void(ref mut sc)
+            | Type::Bool(ref mut sc)
+            | Type::Char(ref mut sc)
+            | Type::Schar(ref mut sc)
+            | Type::Uchar(ref mut sc)
+            | Type::Short(ref mut sc)
+            | Type::Ushort(ref mut sc)
+            | Type::Int(ref mut sc)
+            | Type::Uint(ref mut sc)
+            | Type::Long(ref mut sc)
+            | Type::Ulong(ref mut sc)
+            | Type::Int64(ref mut sc)
+            | Type::Uint64(ref mut sc)
+            | Type::Int128(ref mut sc)
+            | Type::Uint128(ref mut sc)
+            | Type::Wchar(ref mut sc)
+            | Type::Char8(ref mut sc)
+            | Type::Char16(ref mut sc)
+            | Type::Char32(ref mut sc)
+            | Type::Float(ref mut sc)
+            | Type::Double(ref mut sc)
+            | Type::Ldouble(ref mut sc) => Some(sc),
+            _ => None,
+        }
+    }
+
+    // Every name that the tests in this file demangle, except string
+    // literals, whose text does not have their contents.
+    let source = include_str!("test_basics.rs");
+    let corpus: Vec<&str> = source
+        .split("expect(")
+        .skip(1)
+        .filter_map(|rest| rest.trim_start().strip_prefix('"'))
+        .filter_map(|rest| rest.split('"').next())
+        .filter(|name| name.starts_with('?') && !name.starts_with("??_C@"))
+        .collect();
+    assert!(corpus.len() > 100);
+
+    for input in corpus {
+        let parsed = parse(input).unwrap();
+        let mut expected = parsed.clone();
+        Lossy.visit_parse_result(&mut expected);
+        let expected = mangle(&expected).unwrap();
+        for &flags in &[DemangleFlags::COMPLETE, DemangleFlags::llvm()] {
+            let text = serialize(&parsed, flags).unwrap();
+            let declaration = match parse_declaration(&text) {
+                Ok(declaration) => declaration,
+                Err(err) => panic!("{} ({}): {:#}", input, text, err),
+            };
+            assert_eq!(mangle(&declaration).unwrap(), expected, "{}", text);
+        }
+    }
+}
+
+#[test]
+fn test_md5_mangled_name() {
+    let long = format!("?{}@@3HA", "x".repeat(4096));
+    assert_eq!(
+        md5_mangled_name(&long),
+        "??@553fdaecdab301f2e2251eeccdf79a44@"
+    );
+    assert_eq!(
+        md5_mangled_name(&"a".repeat(4096)),
+        "??@21a199c53f422a380e20b162fb6ebe9c@"
+    );
+    let short = "a".repeat(4095);
+    assert_eq!(md5_mangled_name(&short), short);
+
+    let hashed = md5_mangled_name(&long);
+    assert!(is_md5_name(&hashed));
+    assert!(!is_md5_name("?x@@3HA"));
+    assert_eq!(
+        find_md5_source(&hashed, vec![&short[..], "?x@@3HA", &long[..]]),
+        Some(&long[..])
+    );
+    assert_eq!(find_md5_source(&hashed, vec![&short[..]]), None);
+    assert_eq!(find_md5_source("?x@@3HA", vec!["?x@@3HA"]), None);
+
+    let class = format!("{}@@", "x".repeat(4096));
+    let vftable = format!("??_7{}6B@", class);
+    let locator = format!("??_R4{}6B@", class);
+    let hashed_locator = md5_mangled_name(&locator);
+    assert_eq!(
+        hashed_locator,
+        format!("{}??_R4@", md5_mangled_name(&vftable))
+    );
+    assert!(is_md5_name(&hashed_locator));
+    assert_eq!(
+        find_md5_source(&hashed_locator, vec![&vftable[..], &locator[..]]),
+        Some(&locator[..])
+    );
+
+    let names: Vec<_> = include_str!("llvm-cases/unused/ms-md5.test")
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .collect();
+    assert_eq!(names.len(), 3);
+    assert!(is_md5_name(names[0]));
+    assert!(!is_md5_name(names[1]));
+    assert!(is_md5_name(names[2]));
+    assert!(!is_md5_name("??_R4@"));
+}
+
+#[test]
+fn test_string_literal_name() {
+    assert_eq!(
+        StringLiteral::new(b"abc").mangled_name(),
+        "??_C@_03FIKCJHKP@abc?$AA@"
+    );
+    assert_eq!(
+        StringLiteral::new(b"\xff").mangled_name(),
+        "??_C@_01CNACBAHC@?$PP?$AA@"
+    );
+    assert_eq!(
+        StringLiteral::wide(&[u16::from(b'\t')]).mangled_name(),
+        "??_C@_13KDLDGPGJ@?$AA?7?$AA?$AA@"
+    );
+
+    // Only the first 32 bytes are spelled out, but the length and checksum
+    // cover the whole string.
+    let long = StringLiteral::new(&[b'x'; 40]);
+    assert_eq!(long.byte_length, 41);
+    assert_eq!(long.bytes, vec![b'x'; 32]);
+    let mangled = long.mangled_name();
+    assert_eq!(
+        parse(&mangled).unwrap().symbol_type,
+        Type::ConstantString(long)
+    );
+
+    let wide = StringLiteral::wide(&[u16::from(b'x'); 40]);
+    assert_eq!(wide.byte_length, 82);
+    assert_eq!(wide.bytes.len(), 64);
+    let mangled = wide.mangled_name();
+    assert_eq!(
+        parse(&mangled).unwrap().symbol_type,
+        Type::ConstantString(wide)
+    );
+}
+
+#[test]
+fn test_demangle_type() {
+    let flags = DemangleFlags::llvm();
+    for &(input, expected) in &[
+        (".?AVFoo@bar@@", "class bar::Foo"),
+        (".?AUS@@", "struct S"),
+        (".?BVFoo@@", "class Foo const"),
+        (".PEAVBar@@", "class Bar *"),
+        ("PEAVBar@@", "class Bar *"),
+        ("?AVFoo@@", "class Foo"),
+        (".H", "int"),
+        (
+            ".?AV?$vector@HV?$allocator@H@std@@@std@@",
+            "class std::vector<int, class std::allocator<int> >",
+        ),
+        (".P6AXPAUX@@0@Z", "void (__cdecl *)(struct X *, struct X *)"),
+    ] {
+        assert_eq!(demangle_type(input, flags).unwrap(), expected, "{}", input);
+    }
+
+    assert_eq!(
+        parse_type(".?AVFoo@@").unwrap(),
+        Type::Class(
+            Symbol {
+                name: Name::NonTemplate(b"Foo"[..].into()),
+                scope: NameSequence { names: vec![] },
+            },
+            StorageClass::empty()
+        )
+    );
+    assert_eq!(
+        parse_type(".?AVFoo@@x").unwrap_err().kind(),
+        Some(&ErrorKind::TrailingData)
+    );
+    assert!(parse_type(".?AVFoo").is_err());
+}
+
+#[test]
+fn test_parse_decorated() {
+    for &(input, import, calling_conv, arg_bytes, name) in &[
+        ("_main", false, Some(CallingConv::Cdecl), None, "main"),
+        (
+            "_foo@12",
+            false,
+            Some(CallingConv::Stdcall),
+            Some(12),
+            "foo",
+        ),
+        (
+            "@fastfn@8",
+            false,
+            Some(CallingConv::Fastcall),
+            Some(8),
+            "fastfn",
+        ),
+        (
+            "vecfn@@16",
+            false,
+            Some(CallingConv::Vectorcall),
+            Some(16),
+            "vecfn",
+        ),
+        (
+            "__imp__GetTickCount@0",
+            true,
+            Some(CallingConv::Stdcall),
+            Some(0),
+            "GetTickCount",
+        ),
+        ("__imp_CreateFileW", true, None, None, "CreateFileW"),
+        ("main", false, None, None, "main"),
+    ] {
+        let decorated = parse_decorated(input).unwrap();
+        assert_eq!(decorated.import, import, "{}", input);
+        assert_eq!(decorated.calling_conv, calling_conv, "{}", input);
+        assert_eq!(decorated.arg_bytes, arg_bytes, "{}", input);
+        assert_eq!(decorated.name, name, "{}", input);
+        assert!(decorated.parsed.is_none());
+    }
+
+    let decorated = parse_decorated("__imp_?foo@@YAXXZ").unwrap();
+    assert!(decorated.import);
+    assert_eq!(decorated.calling_conv, None);
+    assert_eq!(decorated.name, "?foo@@YAXXZ");
+    assert_eq!(decorated.parsed, Some(parse("?foo@@YAXXZ").unwrap()));
+
+    let err = parse_decorated("__imp_?foo@@YAX").unwrap_err();
+    assert_eq!(err.offset(), Some(15));
+    assert!(parse_decorated("@fastfn").is_err());
+    assert!(parse_decorated("__imp_").is_err());
+    assert!(parse_decorated("_@4").is_err());
+
+    // A decoration without a usable size is not part of a `__cdecl` name.
+    let err = parse_decorated("_foo@").unwrap_err();
+    assert_eq!(err.kind(), Some(&ErrorKind::Expected("argument size")));
+    assert_eq!(err.offset(), Some(5));
+    let err = parse_decorated("_foo@99999999999").unwrap_err();
+    assert_eq!(err.kind(), Some(&ErrorKind::BadNumber));
+    assert_eq!(err.offset(), Some(5));
+    assert!(parse_decorated("@fastfn@").is_err());
+    assert!(parse_decorated("vecfn@@").is_err());
+    assert!(parse_decorated("vecfn@@4294967296").is_err());
+    assert_eq!(
+        parse_decorated("_foo@4294967295").unwrap().arg_bytes,
+        Some(u32::MAX)
+    );
+}
+
+#[test]
+fn test_detect() {
+    for &(input, kind) in &[
+        ("?foo@@YAXXZ", Some(MangledKind::Mangled)),
+        ("??0Foo@@QAE@XZ", Some(MangledKind::Mangled)),
+        (
+            "??@a6a285da2eea70dba6b578022be61d81@",
+            Some(MangledKind::Md5),
+        ),
+        ("??$f@H@@YAXH@Z", Some(MangledKind::Template)),
+        ("?$TSS0@?1??f@@YAXXZ@4HA", Some(MangledKind::Guard)),
+        (
+            "??_C@_03FIKCJHKP@abc?$AA@",
+            Some(MangledKind::StringLiteral),
+        ),
+        ("??_7Foo@@6B@", Some(MangledKind::VTable)),
+        ("??_R0?AVFoo@@@8", Some(MangledKind::RTTI)),
+        (".?AVFoo@bar@@", Some(MangledKind::TypeName)),
+        (".PEAVBar@@", Some(MangledKind::TypeName)),
+        (".H", Some(MangledKind::TypeName)),
+        (".N", Some(MangledKind::TypeName)),
+        ("._N", Some(MangledKind::TypeName)),
+        (".$$T", Some(MangledKind::TypeName)),
+        (".QAH", Some(MangledKind::TypeName)),
+        (".CRT$XCU", None),
+        (".text", None),
+        ("__imp_?foo@@YAXXZ", Some(MangledKind::Import)),
+        ("__imp__GetTickCount@0", Some(MangledKind::Import)),
+        ("_main", Some(MangledKind::Decorated)),
+        ("_foo@12", Some(MangledKind::Decorated)),
+        ("@fastfn@8", Some(MangledKind::Decorated)),
+        ("vecfn@@16", Some(MangledKind::Decorated)),
+        ("main", None),
+        ("_ZN3foo3barEv", None),
+        ("__ZN3foo3barEv", None),
+        ("_RNvCs1234_7mycrate3foo", None),
+        ("_R", None),
+        ("_R0NvC3foo", None),
+        ("_Z", None),
+        ("_Render@4", Some(MangledKind::Decorated)),
+        ("_Zoom", Some(MangledKind::Decorated)),
+        ("_foo@99999999999", None),
+        ("@fastfn", None),
+        ("", None),
+    ] {
+        assert_eq!(detect(input), kind, "{}", input);
+    }
+
+    assert!(is_msvc_mangled("?foo@@YAXXZ"));
+    assert!(is_msvc_mangled("__imp_?foo@@YAXXZ"));
+    assert!(is_msvc_mangled(".?AVFoo@@"));
+    assert!(!is_msvc_mangled("_foo@12"));
+    assert!(!is_msvc_mangled("__imp__foo@12"));
+    assert!(!is_msvc_mangled("_ZN3foo3barEv"));
+}
+
+#[test]
+fn test_demangle_in_text() {
+    let flags = DemangleFlags::llvm();
+    for &(input, expected) in &[
+        (
+            "error LNK2019: unresolved external symbol \"void __cdecl foo(void)\" \
+             (?foo@@YAXXZ) referenced in function _main",
+            "error LNK2019: unresolved external symbol \"void __cdecl foo(void)\" \
+             (void __cdecl foo(void)) referenced in function _main",
+        ),
+        (
+            "unresolved external symbol __imp_?bar@Foo@@QAEXXZ",
+            "unresolved external symbol \
+             __declspec(dllimport) public: void __thiscall Foo::bar(void)",
+        ),
+        (
+            " 0001:00000010       ?f@@YAHH@Z   00401010 f   main.obj",
+            " 0001:00000010       int __cdecl f(int)   00401010 f   main.obj",
+        ),
+        (
+            "app.exe!?foo@Bar@@QAEHH@Z+0x1a",
+            "app.exe!public: int __thiscall Bar::foo(int)+0x1a",
+        ),
+        ("call '?x@@3HA'", "call 'int x'"),
+        ("typeid .?AVFoo@bar@@", "typeid class bar::Foo"),
+        (
+            "app.exe!??R<lambda_1>@?0??f@@YAXXZ@QBE@XZ+0x4",
+            "app.exe!public: __thiscall `void __cdecl f(void)'::`1'::<lambda_1>\
+             ::operator()(void) const+0x4",
+        ),
+        (
+            "see ?x@<unnamed-tag>@@2HA.",
+            "see public: static int <unnamed-tag>::x.",
+        ),
+        ("<?x@@3HA>", "<int x>"),
+        ("?x@@3HA->y", "int x->y"),
+        // Not names of their own, or not demanglable.
+        ("foo?bar@@YAXXZ", "foo?bar@@YAXXZ"),
+        ("?x@@3HAfoo", "?x@@3HAfoo"),
+        (
+            "??@a6a285da2eea70dba6b578022be61d81@",
+            "??@a6a285da2eea70dba6b578022be61d81@",
+        ),
+        ("what? nothing", "what? nothing"),
+    ] {
+        assert_eq!(demangle_in_text(input, flags), expected);
+    }
+
+    // Names that blow up through nested backreferences stay as they are.
+    let mut nested = String::from("P6AXHH@Z");
+    let doublings: String = (0..9).map(|i| format!("P6AX{}{}@Z", i, i)).collect();
+    nested.push_str(&doublings);
+    for _ in 0..2 {
+        nested = format!("V?$A@{}@@{}", nested, doublings);
+        let text = format!("crash in ?f@@YAX{}@Z+0x10", nested);
+        assert_eq!(demangle_in_text(&text, flags), text);
+    }
+
+    let text = "no symbols here";
+    match demangle_in_text(text, flags) {
+        Cow::Borrowed(s) => assert_eq!(s, text),
+        Cow::Owned(_) => panic!("text without symbols was copied"),
+    }
+}
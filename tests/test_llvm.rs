@@ -1,6 +1,6 @@
 extern crate msvc_demangler;
 
-use msvc_demangler::{parse, serialize, DemangleFlags};
+use msvc_demangler::{mangle, parse, parse_with_options, serialize, DemangleFlags, ParseOptions};
 use std::iter;
 
 #[derive(Debug)]
@@ -29,8 +29,8 @@ fn parse_cases<'a, I: Iterator<Item = &'a str>>(i: I) -> impl Iterator<Item = Te
             Some(LineRule::CheckNotInvalid)
         } else if item.starts_with("; CHECK-NOT: ") {
             panic!("unsupported rule: {}", &item[2..]);
-        } else if item.starts_with("; CHECK: ") {
-            Some(LineRule::Check(&item[9..]))
+        } else if let Some(check) = item.strip_prefix("; CHECK: ") {
+            Some(LineRule::Check(check))
         } else if item.starts_with(';') {
             None
         } else {
@@ -46,7 +46,7 @@ fn parse_cases<'a, I: Iterator<Item = &'a str>>(i: I) -> impl Iterator<Item = Te
                 not_invalid = true;
             }
             Some(LineRule::Input(input)) => {
-                while let Some(next) = rule_iter.next() {
+                if let Some(next) = rule_iter.next() {
                     match next {
                         LineRule::CheckNotInvalid => {
                             panic!("not invalid at unexpected position");
@@ -71,12 +71,36 @@ fn parse_cases<'a, I: Iterator<Item = &'a str>>(i: I) -> impl Iterator<Item = Te
     })
 }
 
+/// Newer llvm drops the space between the calling convention and the
+/// parameters of a function type, as in `e<void __cdecl(void)>`, while
+/// older test files keep it.  The space is dropped on both sides for every
+/// calling convention, not just the `__cdecl` that the current files use.
+fn normalize_calling_convs(s: &str) -> String {
+    const CALLING_CONVS: &[&str] = &[
+        "__cdecl",
+        "__pascal",
+        "__thiscall",
+        "__stdcall",
+        "__fastcall",
+        "__vectorcall",
+        "__regcall",
+    ];
+    let mut s = s.to_string();
+    for cc in CALLING_CONVS {
+        s = s.replace(&format!("{} (", cc), &format!("{}(", cc));
+    }
+    s
+}
+
 macro_rules! llvm_test {
     ($filename:expr) => {{
         let rules = include_str!($filename);
         for case in parse_cases(rules.lines()) {
             if case.not_invalid {
                 let parsed = dbg!(parse(case.mangled).unwrap());
+                let strict = ParseOptions::default().with_strict(true);
+                assert_eq!(parse_with_options(case.mangled, strict).unwrap(), parsed);
+                assert_eq!(mangle(&parsed).unwrap(), case.mangled);
                 let demangled = serialize(&parsed, DemangleFlags::llvm()).unwrap();
                 println!("      mangled: {}", case.mangled);
                 println!("demangled ref: {}", case.demangled_ref);
@@ -89,8 +113,9 @@ macro_rules! llvm_test {
                     .replace("> > > >", ">>>>")
                     .replace("> > >", ">>>")
                     .replace("> >", ">>");
+                let demangled_fuzzy = normalize_calling_convs(&demangled_fuzzy);
                 assert!(
-                    demangled_fuzzy.contains(case.demangled_ref)
+                    demangled_fuzzy.contains(&normalize_calling_convs(case.demangled_ref))
                         || demangled.contains(case.demangled_ref)
                 );
             } else {
@@ -101,6 +126,21 @@ macro_rules! llvm_test {
     }};
 }
 
+macro_rules! llvm_invalid_test {
+    ($filename:expr) => {{
+        let rules = include_str!($filename);
+        let inputs = rules
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with(';'));
+        for input in inputs {
+            println!("mangled: {}", input);
+            let strict = ParseOptions::default().with_strict(true);
+            let err = parse_with_options(input, strict).unwrap_err();
+            println!("  error: {}", err);
+        }
+    }};
+}
+
 #[test]
 fn test_llvm_ms_basic() {
     llvm_test!("llvm-cases/ms-basic.test");
@@ -125,3 +165,13 @@ fn test_llvm_ms_windows() {
 fn test_llvm_cxx11() {
     llvm_test!("llvm-cases/ms-cxx11.test");
 }
+
+#[test]
+fn test_llvm_invalid_manglings() {
+    llvm_invalid_test!("llvm-cases/invalid-manglings.test");
+}
+
+#[test]
+fn test_llvm_cxx17_noexcept() {
+    llvm_test!("llvm-cases/ms-cxx17-noexcept.test");
+}